@@ -0,0 +1,24 @@
+// The simulation without a window, audio or any other SDL subsystem. Builds with
+// --no-default-features for dedicated servers, bots and automated tests. SDL2 itself is
+// still linked for Point and Rect, it just never gets initialised
+use genjin::headless;
+use genjin::library::profiler::CountingAlloc;
+use genjin::options::parse_args;
+
+// Gives the profiler its allocation counts
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+pub fn main() -> Result<(), String> {
+    let options = parse_args()?;
+    if options.netplay.is_some() || options.connect.is_some() {
+        return Err("--netplay and --connect need a window, use the genjin binary".to_string());
+    }
+    if !headless::run(&options)? {
+        return Err(
+            "nothing to run, pass --replay, --diff, --server, --bot, --rollback-test or --net-test"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use sdl2::rect::Point;
+use specs::prelude::*;
+use specs_derive::Component;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Behavior {
+    Idle,
+    Patrol { points: Vec<Point> },
+    Chase { range: i32 },
+    Flee { range: i32 },
+    Shoot { range: i32, cooldown: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+    PlayerWithin(i32),
+    PlayerBeyond(i32),
+    HealthBelow(u32),
+    LineOfSight,
+    NoLineOfSight,
+    After(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub condition: Condition,
+    pub target: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AiState {
+    pub name: String,
+    pub behavior: Behavior,
+    pub transitions: Vec<Transition>,
+}
+
+// Finite state machine driving a non-player entity, first state is the entry state
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct AI {
+    pub states: Vec<AiState>,
+    pub current: usize,
+    pub ticks_in_state: u32,
+    pub patrol_index: usize,
+    pub cooldown: u32,
+    // Waypoints of the route being chased along and whether each is reached by a jump, kept
+    // until the target changes cell or the grid is rebuilt
+    pub path: Vec<(Point, bool)>,
+    pub path_goal: Option<(i32, i32)>,
+    pub path_grid: u64,
+}
+
+impl AI {
+    pub fn new(states: Vec<AiState>) -> AI {
+        AI {
+            states,
+            current: 0,
+            ticks_in_state: 0,
+            patrol_index: 0,
+            cooldown: 0,
+            path: Vec::new(),
+            path_goal: None,
+            path_grid: 0,
+        }
+    }
+
+    // Parses a state machine from its text form:
+    //
+    //   # comment
+    //   state patrol: patrol -150,0 150,0
+    //       player_within 200 -> chase
+    //   state chase: chase 300
+    //       player_beyond 300 -> patrol
+    //
+    // Behaviors: idle | patrol x,y.. | chase range | flee range | shoot range cooldown
+    // Conditions: always | player_within n | player_beyond n | health_below n
+    //             | line_of_sight | no_line_of_sight | after ticks
+    pub fn parse(source: &str) -> Result<AI, String> {
+        let mut states: Vec<AiState> = Vec::new();
+        let mut transitions: Vec<(usize, Condition, String)> = Vec::new();
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line = match line.split('#').next() {
+                Some(line) => line.trim(),
+                None => continue,
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", line_no + 1, msg);
+
+            if let Some(rest) = line.strip_prefix("state ") {
+                let (name, behavior) = rest
+                    .split_once(':')
+                    .ok_or_else(|| err("expected `state <name>: <behavior>`"))?;
+                states.push(AiState {
+                    name: name.trim().to_string(),
+                    behavior: parse_behavior(behavior.trim()).map_err(|e| err(&e))?,
+                    transitions: Vec::new(),
+                });
+            } else {
+                let (condition, target) = line
+                    .split_once("->")
+                    .ok_or_else(|| err("expected `<condition> -> <state>`"))?;
+                if states.is_empty() {
+                    return Err(err("transition before any state"));
+                }
+                transitions.push((
+                    states.len() - 1,
+                    parse_condition(condition.trim()).map_err(|e| err(&e))?,
+                    target.trim().to_string(),
+                ));
+            }
+        }
+
+        if states.is_empty() {
+            return Err("state machine has no states".to_string());
+        }
+
+        for (from, condition, target) in transitions {
+            let target = states
+                .iter()
+                .position(|s| s.name == target)
+                .ok_or_else(|| format!("unknown state `{}`", target))?;
+            states[from]
+                .transitions
+                .push(Transition { condition, target });
+        }
+
+        Ok(AI::new(states))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<&str>) -> Result<T, String> {
+    let word = word.ok_or_else(|| "missing number".to_string())?;
+    word.parse::<T>()
+        .map_err(|_| format!("`{}` is not a valid number", word))
+}
+
+fn parse_behavior(text: &str) -> Result<Behavior, String> {
+    let mut words = text.split_whitespace();
+    match words.next() {
+        Some("idle") => Ok(Behavior::Idle),
+        Some("patrol") => {
+            let mut points = Vec::new();
+            for word in words {
+                let (x, y) = word
+                    .split_once(',')
+                    .ok_or_else(|| format!("`{}` is not an x,y point", word))?;
+                points.push(Point::new(parse_number(Some(x))?, parse_number(Some(y))?));
+            }
+            if points.is_empty() {
+                return Err("patrol needs at least one point".to_string());
+            }
+            Ok(Behavior::Patrol { points })
+        }
+        Some("chase") => Ok(Behavior::Chase {
+            range: parse_number(words.next())?,
+        }),
+        Some("flee") => Ok(Behavior::Flee {
+            range: parse_number(words.next())?,
+        }),
+        Some("shoot") => Ok(Behavior::Shoot {
+            range: parse_number(words.next())?,
+            cooldown: parse_number(words.next())?,
+        }),
+        Some(other) => Err(format!("unknown behavior `{}`", other)),
+        None => Err("missing behavior".to_string()),
+    }
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let mut words = text.split_whitespace();
+    match words.next() {
+        Some("always") => Ok(Condition::Always),
+        Some("player_within") => Ok(Condition::PlayerWithin(parse_number(words.next())?)),
+        Some("player_beyond") => Ok(Condition::PlayerBeyond(parse_number(words.next())?)),
+        Some("health_below") => Ok(Condition::HealthBelow(parse_number(words.next())?)),
+        Some("line_of_sight") => Ok(Condition::LineOfSight),
+        Some("no_line_of_sight") => Ok(Condition::NoLineOfSight),
+        Some("after") => Ok(Condition::After(parse_number(words.next())?)),
+        Some(other) => Err(format!("unknown condition `{}`", other)),
+        None => Err("missing condition".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions_may_point_forward() {
+        let ai = AI::parse(
+            "# comment\n\
+             state patrol: patrol -150,0 150,0\n\
+             \x20   player_within 200 -> chase\n\
+             state chase: chase 300\n\
+             \x20   player_beyond 300 -> patrol\n\
+             \x20   health_below 20 -> hide\n\
+             state hide: flee 400 # far away\n\
+             \x20   after 60 -> patrol\n",
+        )
+        .unwrap();
+        assert_eq!(ai.current, 0);
+        assert_eq!(
+            ai.states,
+            vec![
+                AiState {
+                    name: "patrol".to_string(),
+                    behavior: Behavior::Patrol {
+                        points: vec![Point::new(-150, 0), Point::new(150, 0)],
+                    },
+                    transitions: vec![Transition {
+                        condition: Condition::PlayerWithin(200),
+                        target: 1,
+                    }],
+                },
+                AiState {
+                    name: "chase".to_string(),
+                    behavior: Behavior::Chase { range: 300 },
+                    transitions: vec![
+                        Transition {
+                            condition: Condition::PlayerBeyond(300),
+                            target: 0,
+                        },
+                        Transition {
+                            condition: Condition::HealthBelow(20),
+                            target: 2,
+                        },
+                    ],
+                },
+                AiState {
+                    name: "hide".to_string(),
+                    behavior: Behavior::Flee { range: 400 },
+                    transitions: vec![Transition {
+                        condition: Condition::After(60),
+                        target: 0,
+                    }],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_the_grunt() {
+        let source = std::fs::read_to_string("assets/ai/grunt.fsm").unwrap();
+        let ai = AI::parse(&source).unwrap();
+        let names: Vec<&str> = ai.states.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["patrol", "chase", "shoot", "flee"]);
+        assert_eq!(
+            ai.states[2].behavior,
+            Behavior::Shoot {
+                range: 260,
+                cooldown: 40
+            }
+        );
+        assert_eq!(
+            ai.states[2].transitions[0].condition,
+            Condition::NoLineOfSight
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = |source: &str| AI::parse(source).unwrap_err();
+        assert_eq!(err("# nothing"), "state machine has no states");
+        assert_eq!(err("always -> idle"), "line 1: transition before any state");
+        assert_eq!(
+            err("state idle idle"),
+            "line 1: expected `state <name>: <behavior>`"
+        );
+        assert_eq!(
+            err("state idle: idle\nalways"),
+            "line 2: expected `<condition> -> <state>`"
+        );
+        assert_eq!(err("state a: dance"), "line 1: unknown behavior `dance`");
+        assert_eq!(
+            err("state a: patrol"),
+            "line 1: patrol needs at least one point"
+        );
+        assert_eq!(err("state a: shoot 10"), "line 1: missing number");
+        assert_eq!(
+            err("state a: idle\nsometimes -> a"),
+            "line 2: unknown condition `sometimes`"
+        );
+        assert_eq!(err("state a: idle\nalways -> b"), "unknown state `b`");
+    }
+}
@@ -0,0 +1,213 @@
+use sdl2::rect::Rect;
+use specs::prelude::*;
+use specs_derive::Component;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationFrame {
+    pub region: Rect,
+    pub duration: u32,
+    // None keeps whatever boxes the previous frame set
+    pub hurt_boxes: Option<Vec<Rect>>,
+    pub hit_boxes: Option<Vec<Rect>>,
+}
+
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Animation {
+    pub spritesheet: usize,
+    pub frames: Vec<AnimationFrame>,
+    pub looping: bool,
+    pub current: usize,
+    pub elapsed: u32,
+}
+
+impl Animation {
+    pub fn frame(&self) -> &AnimationFrame {
+        &self.frames[self.current]
+    }
+
+    pub fn finished(&self) -> bool {
+        !self.looping
+            && self.current + 1 == self.frames.len()
+            && self.elapsed >= self.frame().duration
+    }
+
+    pub fn restart(&mut self) {
+        self.current = 0;
+        self.elapsed = 0;
+    }
+
+    // Parses an animation from its text form:
+    //
+    //   # reaper swing
+    //   sheet 0
+    //   once
+    //   frame 0,0,26,36 6
+    //       hurt 0,0,16,36
+    //       hit none
+    //   frame 32,0,26,36 4
+    //       hit 14,-4,12,20
+    //
+    // Boxes are x,y,w,h with x/y the offset of the box center from the entity.
+    // `hit none` or `hurt none` clears the boxes for that frame.
+    pub fn parse(source: &str) -> Result<Animation, String> {
+        let mut spritesheet = 0;
+        let mut looping = true;
+        let mut frames: Vec<AnimationFrame> = Vec::new();
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line = match line.split('#').next() {
+                Some(line) => line.trim(),
+                None => continue,
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", line_no + 1, msg);
+
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("sheet") => {
+                    spritesheet = parse_number(words.next()).map_err(err)?;
+                }
+                Some("loop") => looping = true,
+                Some("once") => looping = false,
+                Some("frame") => {
+                    let region = parse_rect(words.next()).map_err(err)?;
+                    let duration = parse_number(words.next()).map_err(err)?;
+                    frames.push(AnimationFrame {
+                        region,
+                        duration,
+                        hurt_boxes: None,
+                        hit_boxes: None,
+                    });
+                }
+                Some(kind @ ("hurt" | "hit")) => {
+                    let frame = frames
+                        .last_mut()
+                        .ok_or_else(|| err(format!("`{}` before any frame", kind)))?;
+                    let boxes = if kind == "hurt" {
+                        &mut frame.hurt_boxes
+                    } else {
+                        &mut frame.hit_boxes
+                    };
+                    let boxes = boxes.get_or_insert_with(Vec::new);
+                    let word = words.next();
+                    if word != Some("none") {
+                        boxes.push(parse_rect(word).map_err(err)?);
+                    }
+                }
+                Some(other) => return Err(err(format!("unknown keyword `{}`", other))),
+                None => {}
+            }
+        }
+
+        if frames.is_empty() {
+            return Err("animation has no frames".to_string());
+        }
+
+        Ok(Animation {
+            spritesheet,
+            frames,
+            looping,
+            current: 0,
+            elapsed: 0,
+        })
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<&str>) -> Result<T, String> {
+    let word = word.ok_or_else(|| "missing number".to_string())?;
+    word.parse::<T>()
+        .map_err(|_| format!("`{}` is not a valid number", word))
+}
+
+fn parse_rect(word: Option<&str>) -> Result<Rect, String> {
+    let word = word.ok_or_else(|| "missing x,y,w,h box".to_string())?;
+    let parts: Vec<&str> = word.split(',').collect();
+    if parts.len() != 4 {
+        return Err(format!("`{}` is not an x,y,w,h box", word));
+    }
+    Ok(Rect::new(
+        parse_number(Some(parts[0]))?,
+        parse_number(Some(parts[1]))?,
+        parse_number(Some(parts[2]))?,
+        parse_number(Some(parts[3]))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_documented_example() {
+        let anim = Animation::parse(
+            "# reaper swing\n\
+             sheet 3\n\
+             once\n\
+             frame 0,0,26,36 6\n\
+             \x20   hurt 0,0,16,36\n\
+             \x20   hit none\n\
+             frame 32,0,26,36 4\n\
+             \x20   hit 14,-4,12,20\n\
+             \x20   hit 0,0,4,4\n",
+        )
+        .unwrap();
+        assert_eq!(anim.spritesheet, 3);
+        assert!(!anim.looping);
+        assert_eq!(
+            anim.frames,
+            vec![
+                AnimationFrame {
+                    region: Rect::new(0, 0, 26, 36),
+                    duration: 6,
+                    hurt_boxes: Some(vec![Rect::new(0, 0, 16, 36)]),
+                    hit_boxes: Some(vec![]),
+                },
+                AnimationFrame {
+                    region: Rect::new(32, 0, 26, 36),
+                    duration: 4,
+                    hurt_boxes: None,
+                    hit_boxes: Some(vec![Rect::new(14, -4, 12, 20), Rect::new(0, 0, 4, 4)]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn walk_cycle_loops() {
+        let source = std::fs::read_to_string("assets/anim/reaper_walk.anim").unwrap();
+        let mut anim = Animation::parse(&source).unwrap();
+        assert!(anim.looping);
+        assert_eq!(anim.frames.len(), 3);
+        anim.current = 2;
+        anim.elapsed = 8;
+        assert!(!anim.finished());
+
+        anim.looping = false;
+        assert!(anim.finished());
+        anim.restart();
+        assert_eq!((anim.current, anim.elapsed), (0, 0));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = |source: &str| Animation::parse(source).unwrap_err();
+        assert_eq!(err("sheet 0\n# only a comment"), "animation has no frames");
+        assert_eq!(err("hurt 0,0,1,1"), "line 1: `hurt` before any frame");
+        assert_eq!(
+            err("frame 0,0,1,1 4\nspin"),
+            "line 2: unknown keyword `spin`"
+        );
+        assert_eq!(err("frame 0,0,1,1"), "line 1: missing number");
+        assert_eq!(
+            err("frame 0,0,1,1 -2"),
+            "line 1: `-2` is not a valid number"
+        );
+        assert_eq!(
+            err("frame 0,0,1,1 2\nhit 1,2"),
+            "line 2: `1,2` is not an x,y,w,h box"
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use crate::commands::ActionCommand;
+use crate::navigation::JumpProfile;
+use crate::particles::EmitterConfig;
+use crate::PhysicsConfig;
 use crate::ScreenSize;
 use sdl2::rect::{Point, Rect};
 use specs::prelude::*;
@@ -20,12 +24,25 @@ impl Position {
             }
         };
     }
+
+    // Places a box authored relative to the entity, x/y being the offset of its center.
+    // Boxes are authored facing right, mirrored flips the x offset for entities facing left
+    pub fn place_box(&self, local: Rect, mirrored: bool) -> Rect {
+        let x = if mirrored { -local.x() } else { local.x() };
+        Rect::from_center(
+            self.point.offset(x, local.y()),
+            local.width(),
+            local.height(),
+        )
+    }
 }
 
 #[derive(Component, Debug, Clone)]
 #[storage(VecStorage)]
 pub struct Collideable {
     pub col_box: Rect,
+    // Friction for entities standing on it, 1.0 is normal ground, ice is lower and mud higher
+    pub friction: f32,
 }
 
 #[derive(Component, Debug, Clone)]
@@ -34,6 +51,30 @@ pub struct GravityAfflicted {
     pub max_vel: i32,
     pub grounded: bool,
     pub grounded_rect: Option<Rect>,
+    // Friction of the surface stood on, set by CollisionSys on landing
+    pub ground_friction: f32,
+    // Upward speed of a plain jump off the ground, 0 can not jump. A PlatformerController
+    // jumps with its own settings instead
+    pub jump_speed: i8,
+}
+
+impl GravityAfflicted {
+    // How high and far a jump gets, rising speed drops by gravity plus the air drag in
+    // Physics every tick
+    pub fn jump_profile(
+        &self,
+        ctrl: Option<&PlatformerController>,
+        max_speed: i8,
+        config: &PhysicsConfig,
+    ) -> JumpProfile {
+        let v = ctrl.map_or(self.jump_speed, |c| c.jump_speed) as f32;
+        let decel = (config.gravity.1 + config.air_drag).max(0.1);
+        let rise_ticks = (v / decel).ceil();
+        JumpProfile {
+            height: (v * v / (2.0 * decel)) as i32,
+            reach: (2.0 * rise_ticks * max_speed as f32) as i32,
+        }
+    }
 }
 
 #[derive(Component, Debug, Clone)]
@@ -46,14 +87,62 @@ pub struct DecayLife {
 #[storage(VecStorage)]
 pub struct Health {
     pub hp: u32,
-    pub hurt_box: Rect,
+    pub max_hp: u32,
+    pub hurt_boxes: Vec<Rect>,
+    pub last_attacker: Option<Entity>,
 }
 
 #[derive(Component, Debug, Clone)]
 #[storage(VecStorage)]
 pub struct Damage {
     pub dmg: u32,
-    pub dmg_box: Rect,
+    pub dmg_boxes: Vec<Rect>,
+    pub kind: DamageKind,
+    // Targets already hit by the current swing, cleared when the attack starts over
+    pub hits: Vec<Entity>,
+    // Speed the target is pushed away from the source with
+    pub knockback: i8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DamageKind {
+    Physical,
+    Fire,
+    Ice,
+    Explosive,
+}
+
+// Damage multipliers per kind, kinds not listed take full damage
+#[derive(Component, Debug, Clone, Default)]
+#[storage(VecStorage)]
+pub struct Resistances {
+    pub multipliers: Vec<(DamageKind, f32)>,
+}
+
+impl Health {
+    // Returns how much was actually restored
+    pub fn heal(&mut self, amount: u32) -> u32 {
+        let before = self.hp;
+        self.hp = self.hp.saturating_add(amount).min(self.max_hp);
+        self.hp - before
+    }
+}
+
+impl Resistances {
+    pub fn scale(&self, kind: DamageKind, amount: u32) -> u32 {
+        match self.multipliers.iter().find(|(k, _)| *k == kind) {
+            Some((_, m)) => (amount as f32 * m.max(0.0)).round() as u32,
+            None => amount,
+        }
+    }
+}
+
+// Ticks the entity ignores damage for after being hit
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Invulnerability {
+    pub duration: u32,
+    pub remaining: u32,
 }
 
 #[derive(Component, Debug, Clone)]
@@ -63,9 +152,15 @@ pub struct Velocity {
     pub max_speed: i8,
     pub acc: u32,
     pub last_dir: Option<Direction>,
+    // Fractions of speed not yet applied, carried over to the next tick
+    pub carry: (f32, f32),
 }
 
 impl Velocity {
+    pub fn facing_left(&self) -> bool {
+        matches!(self.last_dir, Some(Direction::MoveDelta { x, .. }) if x < 0)
+    }
+
     pub fn encode_speed(&mut self, x_speed: i8, y_speed: i8) {
         let mut x_speed = x_speed;
         if x_speed == 127 {
@@ -91,6 +186,79 @@ impl Velocity {
         let y_speed: i8 = ((vel >> 8) & 0xff) as i8;
         return (x_speed, y_speed);
     }
+
+    // Adds a possibly fractional change to the speed, whole steps are applied and the rest carried
+    pub fn accelerate(&mut self, dx: f32, dy: f32) {
+        let (x_cur, y_cur) = self.unencode_speed();
+        let x = self.carry.0 + dx;
+        let y = self.carry.1 + dy;
+        self.carry = (x.fract(), y.fract());
+        self.encode_speed(
+            (x_cur as i32 + x.trunc() as i32).clamp(-128, 127) as i8,
+            (y_cur as i32 + y.trunc() as i32).clamp(-128, 127) as i8,
+        );
+    }
+
+    // Slows each axis towards zero by the given amounts without reversing it
+    pub fn damp(&mut self, x_loss: f32, y_loss: f32) {
+        let (x_before, y_before) = self.unencode_speed();
+        self.accelerate(
+            -(x_before.signum() as f32) * x_loss,
+            -(y_before.signum() as f32) * y_loss,
+        );
+
+        let (mut x_cur, mut y_cur) = self.unencode_speed();
+        if x_before == 0 || x_cur.signum() != x_before.signum() {
+            x_cur = 0;
+            self.carry.0 = 0.0;
+        }
+        if y_before == 0 || y_cur.signum() != y_before.signum() {
+            y_cur = 0;
+            self.carry.1 = 0.0;
+        }
+        self.encode_speed(x_cur, y_cur);
+    }
+
+    // Accelerates towards the given direction, clamped to max_speed
+    pub fn steer(&mut self, dir: Direction) {
+        match dir {
+            Direction::MoveDelta { x, y } => {
+                let (mut x_cur, mut y_cur) = self.unencode_speed();
+
+                x_cur += x * (self.acc as i8);
+                y_cur += y * (self.acc as i8);
+
+                if x_cur >= self.max_speed {
+                    x_cur = self.max_speed;
+                } else if x_cur <= -self.max_speed {
+                    x_cur = -self.max_speed;
+                }
+
+                if y_cur >= self.max_speed {
+                    y_cur = self.max_speed;
+                } else if y_cur <= -self.max_speed {
+                    y_cur = -self.max_speed;
+                }
+
+                self.encode_speed(x_cur, y_cur);
+
+                if x != 0 {
+                    self.last_dir = Some(dir);
+                }
+            }
+        }
+    }
+
+    // Like steer but sideways only, vertical speed is left to gravity and jumps
+    pub fn steer_x(&mut self, x: i8) {
+        let (x_cur, y_cur) = self.unencode_speed();
+        let x_cur = (x_cur + x * (self.acc as i8)).clamp(-self.max_speed, self.max_speed);
+        self.encode_speed(x_cur, y_cur);
+
+        if x != 0 {
+            self.last_dir = Some(Direction::MoveDelta { x, y: 0 });
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -121,8 +289,148 @@ pub struct SpriteVec {
 #[derive(Component, Debug, Clone)]
 #[storage(VecStorage)]
 pub struct ProjectileProperties {
-    pub owner: i32,
-    pub direction: Direction,
+    pub owner: Option<Entity>,
+    // Team of the owner when fired, still applies after the owner is gone
+    pub team: Option<u32>,
+    // Radians, 0 points right and positive turns clockwise on screen
+    pub heading: f32,
+    pub speed: i8,
+    // Radians per tick the heading may turn towards the nearest target
+    pub homing: f32,
+    // Targets left to pass through before the projectile is spent
+    pub pierce: u32,
+    pub hits: Vec<Entity>,
+    pub launched: bool,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[storage(VecStorage)]
+pub struct Team {
+    pub id: u32,
+}
+
+#[derive(Component, Debug, Clone, Default)]
+#[storage(VecStorage)]
+pub struct Score {
+    pub kills: u32,
+    pub damage_dealt: u32,
+}
+
+// Components that point at other entities implement this so anything recreating
+// entities (save games, rollback) can rewrite the references to the new ids
+pub trait EntityRefs {
+    fn remap_entities<F: Fn(Entity) -> Option<Entity>>(&mut self, map: F);
+}
+
+impl EntityRefs for ProjectileProperties {
+    fn remap_entities<F: Fn(Entity) -> Option<Entity>>(&mut self, map: F) {
+        self.owner = self.owner.and_then(&map);
+        self.hits = self.hits.iter().filter_map(|e| map(*e)).collect();
+    }
+}
+
+impl EntityRefs for Damage {
+    fn remap_entities<F: Fn(Entity) -> Option<Entity>>(&mut self, map: F) {
+        self.hits = self.hits.iter().filter_map(|e| map(*e)).collect();
+    }
+}
+
+impl EntityRefs for Health {
+    fn remap_entities<F: Fn(Entity) -> Option<Entity>>(&mut self, map: F) {
+        self.last_attacker = self.last_attacker.and_then(map);
+    }
+}
+
+// Actions issued by whoever controls the entity, drained by ActionSys every tick
+#[derive(Component, Debug, Clone, Default)]
+#[storage(VecStorage)]
+pub struct ActionQueue {
+    pub actions: Vec<ActionCommand>,
+}
+
+impl ActionQueue {
+    pub fn push(&mut self, action: ActionCommand) {
+        self.actions.push(action);
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Weapon {
+    // Minimum ticks between two shots
+    pub fire_delay: u32,
+    pub cooldown: u32,
+    pub projectile_count: u32,
+    // Degrees between the first and last projectile of a volley
+    pub spread: f32,
+    pub speed: i8,
+    pub damage: u32,
+    pub kind: DamageKind,
+    pub knockback: i8,
+    pub lifetime: u32,
+    // Ammo items taken from the inventory per shot, 0 for unlimited
+    pub ammo_per_shot: u32,
+    pub pierce: u32,
+    // Projectiles fall and arc instead of flying straight
+    pub gravity: bool,
+    // Degrees per tick, 0 disables homing
+    pub homing: f32,
+    pub sprite: Sprite,
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Weapon {
+            fire_delay: 8,
+            cooldown: 0,
+            projectile_count: 1,
+            spread: 0.0,
+            speed: 12,
+            damage: 10,
+            kind: DamageKind::Physical,
+            knockback: 3,
+            lifetime: 40,
+            ammo_per_shot: 0,
+            pierce: 0,
+            gravity: false,
+            homing: 0.0,
+            sprite: Sprite {
+                spritesheet: 1,
+                region: Rect::new(0, 0, 5, 5),
+                rotation: None,
+            },
+        }
+    }
+}
+
+impl Weapon {
+    pub fn projectile(
+        &self,
+        owner: Entity,
+        team: Option<u32>,
+        heading: f32,
+    ) -> ProjectileProperties {
+        ProjectileProperties {
+            owner: Some(owner),
+            team,
+            heading,
+            speed: self.speed,
+            homing: self.homing.to_radians(),
+            pierce: self.pierce,
+            hits: Vec::new(),
+            launched: false,
+        }
+    }
+}
+
+impl Direction {
+    // Angle in radians, None when the delta is zero
+    pub fn heading(&self) -> Option<f32> {
+        match *self {
+            Direction::MoveDelta { x: 0, y: 0 } => None,
+            Direction::MoveDelta { x, y } => Some((y as f32).atan2(x as f32)),
+        }
+    }
 }
 
 #[derive(Component, Debug)]
@@ -132,3 +440,328 @@ pub struct MovementAnimation {
     pub left_frames: Vec<Sprite>,
     pub right_frames: Vec<Sprite>,
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Item {
+    Medkit,
+    Ammo,
+    Coin,
+}
+
+impl Item {
+    pub fn max_stack(&self) -> u32 {
+        match self {
+            Item::Medkit => 5,
+            Item::Ammo => 99,
+            Item::Coin => 999,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ItemStack {
+    pub item: Item,
+    pub count: u32,
+}
+
+// Healing restored by using one medkit
+pub const MEDKIT_HEAL: u32 = 25;
+
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Inventory {
+    pub slots: Vec<ItemStack>,
+    // Maximum number of stacks
+    pub capacity: usize,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Inventory {
+        Inventory {
+            slots: Vec::new(),
+            capacity,
+        }
+    }
+
+    pub fn count(&self, item: Item) -> u32 {
+        self.slots
+            .iter()
+            .filter(|s| s.item == item)
+            .map(|s| s.count)
+            .sum()
+    }
+
+    // Fills existing stacks first, then opens new ones, returns what did not fit
+    pub fn add(&mut self, item: Item, count: u32) -> u32 {
+        let mut left = count;
+        for stack in self.slots.iter_mut().filter(|s| s.item == item) {
+            let moved = left.min(item.max_stack() - stack.count);
+            stack.count += moved;
+            left -= moved;
+        }
+        while left > 0 && self.slots.len() < self.capacity {
+            let moved = left.min(item.max_stack());
+            self.slots.push(ItemStack { item, count: moved });
+            left -= moved;
+        }
+        left
+    }
+
+    // Takes count items or nothing at all
+    pub fn remove(&mut self, item: Item, count: u32) -> bool {
+        if self.count(item) < count {
+            return false;
+        }
+        let mut left = count;
+        for stack in self.slots.iter_mut().rev().filter(|s| s.item == item) {
+            let moved = left.min(stack.count);
+            stack.count -= moved;
+            left -= moved;
+        }
+        self.slots.retain(|s| s.count > 0);
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PickupKind {
+    // Restores health on touch, never enters the inventory
+    Heal(u32),
+    Item(ItemStack),
+    // Replaces the collector's weapon
+    Weapon(Box<Weapon>),
+}
+
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    pub area: Rect,
+}
+
+// Continuous particle source that follows the entity
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct ParticleEmitter {
+    pub config: EmitterConfig,
+    pub offset: Point,
+    pub active: bool,
+    pub accumulator: f32,
+}
+
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct PlatformerController {
+    // Upward speed a jump starts with
+    pub jump_speed: i8,
+    // Upward speed kept when the jump button is let go early
+    pub jump_cut: i8,
+    // Ticks after walking off a ledge that still allow a ground jump
+    pub coyote_ticks: u32,
+    // Ticks a jump pressed too early is remembered for
+    pub buffer_ticks: u32,
+    // Fraction of ground acceleration available in the air
+    pub air_control: f32,
+    // 1 for a double jump
+    pub max_air_jumps: u32,
+    pub wall_jump: bool,
+    // Horizontal speed a wall jump pushes away from the wall with
+    pub wall_jump_push: i8,
+
+    pub jump_held: bool,
+    pub coyote: u32,
+    pub buffer: u32,
+    pub air_jumps: u32,
+    pub jumping: bool,
+    pub air_accumulator: f32,
+}
+
+impl Default for PlatformerController {
+    fn default() -> Self {
+        PlatformerController {
+            jump_speed: 14,
+            jump_cut: 4,
+            coyote_ticks: 6,
+            buffer_ticks: 6,
+            air_control: 0.5,
+            max_air_jumps: 0,
+            wall_jump: false,
+            wall_jump_push: 6,
+            jump_held: false,
+            coyote: 0,
+            buffer: 0,
+            air_jumps: 0,
+            jumping: false,
+            air_accumulator: 0.0,
+        }
+    }
+}
+
+impl PlatformerController {
+    // Horizontal steering, in the air only a fraction of the ticks get to accelerate
+    pub fn steer(&mut self, vel: &mut Velocity, x: i8, grounded: bool) {
+        if grounded {
+            self.air_accumulator = 0.0;
+            vel.steer_x(x);
+            return;
+        }
+
+        self.air_accumulator += self.air_control;
+        if self.air_accumulator >= 1.0 {
+            self.air_accumulator -= 1.0;
+            vel.steer_x(x);
+        } else if x != 0 {
+            vel.last_dir = Some(Direction::MoveDelta { x, y: 0 });
+        }
+    }
+}
+
+// Heavier entities lose less speed to air drag and get knocked back less, 1.0 when missing
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Mass {
+    pub mass: f32,
+}
+
+impl Mass {
+    // Entities without a Mass weigh 1.0, a mass of zero or less cannot be moved
+    pub fn inverse(mass: Option<&Mass>) -> f32 {
+        match mass {
+            Some(m) if m.mass <= 0.0 => 0.0,
+            Some(m) => 1.0 / m.mass,
+            None => 1.0,
+        }
+    }
+}
+
+// Multiplier on the friction of whatever surface the entity stands on
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Friction {
+    pub friction: f32,
+}
+
+// Multiplier on the world gravity, 0.0 floats and negative values fall upwards
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct GravityScale {
+    pub scale: f32,
+}
+
+// Scales gravity for everything whose center is inside the area, e.g. low gravity rooms
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct GravityZone {
+    pub area: Rect,
+    pub scale: f32,
+}
+
+// Dynamic body moved by RigidBodySys and ContactSys instead of Velocity, weighed by Mass
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct RigidBody {
+    pub velocity: (f32, f32),
+    // Forces last a single tick, impulses are applied whole on the next one
+    pub force: (f32, f32),
+    pub impulse: (f32, f32),
+    // 0.0 stops dead on impact, 1.0 bounces back at full speed
+    pub restitution: f32,
+    // Fraction of the velocity lost every tick
+    pub damping: f32,
+    // Part of the position finer than a pixel
+    pub subpixel: (f32, f32),
+}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        RigidBody {
+            velocity: (0.0, 0.0),
+            force: (0.0, 0.0),
+            impulse: (0.0, 0.0),
+            restitution: 0.2,
+            damping: 0.01,
+            subpixel: (0.0, 0.0),
+        }
+    }
+}
+
+impl RigidBody {
+    pub fn apply_force(&mut self, x: f32, y: f32) {
+        self.force.0 += x;
+        self.force.1 += y;
+    }
+
+    pub fn apply_impulse(&mut self, x: f32, y: f32) {
+        self.impulse.0 += x;
+        self.impulse.1 += y;
+    }
+
+    // Exact center of the body, Position only holds whole pixels
+    pub fn center(&self, pos: &Position) -> (f32, f32) {
+        (
+            pos.point.x() as f32 + self.subpixel.0,
+            pos.point.y() as f32 + self.subpixel.1,
+        )
+    }
+
+    // Moves the body, whole pixels go to Position and the rest stays in subpixel
+    pub fn shift(&mut self, pos: &mut Position, dx: f32, dy: f32) {
+        let x = self.subpixel.0 + dx;
+        let y = self.subpixel.1 + dy;
+        let (whole_x, whole_y) = (x.round(), y.round());
+        pos.point = pos.point.offset(whole_x as i32, whole_y as i32);
+        self.subpixel = (x - whole_x, y - whole_y);
+    }
+}
+
+// Player whose input comes from PlayerInputs instead of the local keyboard, in multiplayer
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[storage(VecStorage)]
+pub struct NetPlayer {
+    pub slot: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stacks(inventory: &Inventory) -> Vec<(Item, u32)> {
+        inventory.slots.iter().map(|s| (s.item, s.count)).collect()
+    }
+
+    #[test]
+    fn add_fills_stacks_before_opening_new_ones() {
+        let mut inventory = Inventory::new(3);
+        assert_eq!(inventory.add(Item::Medkit, 3), 0);
+        assert_eq!(inventory.add(Item::Coin, 10), 0);
+        assert_eq!(inventory.add(Item::Medkit, 4), 0);
+        assert_eq!(
+            stacks(&inventory),
+            [(Item::Medkit, 5), (Item::Coin, 10), (Item::Medkit, 2)]
+        );
+        assert_eq!(inventory.count(Item::Medkit), 7);
+
+        // Full stacks and no free slot, the rest is handed back
+        assert_eq!(inventory.add(Item::Medkit, 5), 2);
+        assert_eq!(inventory.add(Item::Ammo, 1), 1);
+        assert_eq!(inventory.count(Item::Medkit), 10);
+        assert_eq!(inventory.count(Item::Ammo), 0);
+    }
+
+    #[test]
+    fn remove_takes_all_or_nothing() {
+        let mut inventory = Inventory::new(4);
+        inventory.add(Item::Medkit, 7);
+        inventory.add(Item::Ammo, 50);
+        assert!(!inventory.remove(Item::Medkit, 8));
+        assert!(!inventory.remove(Item::Coin, 1));
+        assert_eq!(inventory.count(Item::Medkit), 7);
+
+        // From the last stack first, emptied stacks free their slot
+        assert!(inventory.remove(Item::Medkit, 3));
+        assert_eq!(stacks(&inventory), [(Item::Medkit, 4), (Item::Ammo, 50)]);
+        assert!(inventory.remove(Item::Ammo, 50));
+        assert!(inventory.remove(Item::Medkit, 0));
+        assert_eq!(stacks(&inventory), [(Item::Medkit, 4)]);
+    }
+}
@@ -12,3 +12,8 @@ pub struct Player;
 #[derive(Component, Debug, Clone)]
 #[storage(NullStorage)]
 pub struct SingleDamage;
+
+// Ignored by DamageSys, set from the developer console
+#[derive(Component, Debug, Clone)]
+#[storage(NullStorage)]
+pub struct GodMode;
@@ -0,0 +1,124 @@
+use sdl2::rect::Point;
+use specs::prelude::*;
+use specs_derive::Component;
+
+use crate::entity_components::EntityRefs;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    // Maps linear progress through a segment, 0.0..=1.0, to eased progress
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathMode {
+    // Goes from the last waypoint back to the first
+    Loop,
+    // Walks the waypoints backwards after reaching the last one
+    PingPong,
+    // Stops at the last waypoint
+    Once,
+}
+
+// Kinematic body following waypoints, carries whatever stands on it. Moved by PlatformSys
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct PlatformPath {
+    pub waypoints: Vec<Point>,
+    // Average pixels per tick along a segment, the easing speeds up and slows down around it
+    pub speed: f32,
+    pub easing: Easing,
+    pub mode: PathMode,
+    pub from: usize,
+    pub to: usize,
+    // Linear progress through the current segment
+    pub progress: f32,
+    pub forward: bool,
+    // Displacement of the last tick, riders leaving the platform keep it
+    pub velocity: (i32, i32),
+    pub riders: Vec<Entity>,
+}
+
+impl PlatformPath {
+    pub fn new(waypoints: Vec<Point>, speed: f32, easing: Easing, mode: PathMode) -> PlatformPath {
+        PlatformPath {
+            to: if waypoints.len() > 1 { 1 } else { 0 },
+            waypoints,
+            speed,
+            easing,
+            mode,
+            from: 0,
+            progress: 0.0,
+            forward: true,
+            velocity: (0, 0),
+            riders: Vec::new(),
+        }
+    }
+
+    // Moves along the path by one tick and returns where the platform should now be
+    pub fn advance(&mut self) -> Option<Point> {
+        let (a, b) = (
+            *self.waypoints.get(self.from)?,
+            *self.waypoints.get(self.to)?,
+        );
+        if self.from == self.to {
+            return Some(a);
+        }
+
+        let length = (((b.x() - a.x()).pow(2) + (b.y() - a.y()).pow(2)) as f32).sqrt();
+        self.progress += self.speed / length.max(1.0);
+
+        if self.progress >= 1.0 {
+            self.progress = 0.0;
+            self.from = self.to;
+            self.to = self.next_waypoint();
+            return Some(b);
+        }
+
+        let t = self.easing.apply(self.progress);
+        Some(Point::new(
+            a.x() + ((b.x() - a.x()) as f32 * t).round() as i32,
+            a.y() + ((b.y() - a.y()) as f32 * t).round() as i32,
+        ))
+    }
+
+    fn next_waypoint(&mut self) -> usize {
+        let last = self.waypoints.len() - 1;
+        match self.mode {
+            PathMode::Loop => (self.from + 1) % self.waypoints.len(),
+            PathMode::Once => (self.from + 1).min(last),
+            PathMode::PingPong => {
+                if self.forward && self.from == last {
+                    self.forward = false;
+                } else if !self.forward && self.from == 0 {
+                    self.forward = true;
+                }
+                if self.forward {
+                    self.from + 1
+                } else {
+                    self.from - 1
+                }
+            }
+        }
+    }
+}
+
+impl EntityRefs for PlatformPath {
+    fn remap_entities<F: Fn(Entity) -> Option<Entity>>(&mut self, map: F) {
+        self.riders = self.riders.iter().filter_map(|e| map(*e)).collect();
+    }
+}
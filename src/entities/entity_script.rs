@@ -0,0 +1,35 @@
+use rhai::Map;
+use specs::prelude::*;
+use specs_derive::Component;
+
+use crate::entity_components::EntityRefs;
+
+// Rhai script driving an entity, ScriptSys calls the hooks it defines
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct Script {
+    pub path: String,
+    // Whatever the script keeps between calls, `this.state` inside it
+    pub state: Map,
+    // Set once init has run
+    pub started: bool,
+    // Collideables overlapping the entity last tick, on_collision only fires for new ones
+    pub touching: Vec<Entity>,
+}
+
+impl Script {
+    pub fn new(path: &str) -> Script {
+        Script {
+            path: path.to_string(),
+            state: Map::new(),
+            started: false,
+            touching: Vec::new(),
+        }
+    }
+}
+
+impl EntityRefs for Script {
+    fn remap_entities<F: Fn(Entity) -> Option<Entity>>(&mut self, map: F) {
+        self.touching = self.touching.iter().filter_map(|e| map(*e)).collect();
+    }
+}
@@ -1,2 +1,6 @@
+pub mod entity_ai;
+pub mod entity_animation;
 pub mod entity_components;
 pub mod entity_flags;
+pub mod entity_platform;
+pub mod entity_script;
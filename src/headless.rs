@@ -0,0 +1,306 @@
+use std::time::Instant;
+
+use crate::checksum::*;
+use crate::commands::*;
+use crate::entity_components::Direction;
+use crate::events::{ScriptLog, SoundQueue};
+use crate::level::*;
+use crate::netclient::NetClient;
+use crate::netcode::*;
+use crate::netserver::NetServer;
+use crate::options::Options;
+use crate::profiler::Profiler;
+use crate::replay::*;
+use crate::rng::*;
+use crate::rollback::*;
+use crate::simulation::Simulation;
+use crate::{SERVER_PORT, SIM_RATE};
+use specs::prelude::*;
+
+// Receive polls a loopback packet spends in flight during --rollback-test
+const TEST_LATENCY: u32 = 5;
+
+// Runs the mode the options ask for if it needs no window, false if there is none. Nothing
+// here touches SDL, so it works in the headless build and on machines without a display
+pub fn run(options: &Options) -> Result<bool, String> {
+    if let Some((first, second)) = &options.diff {
+        diff(first, second)?;
+    } else if let Some(path) = &options.replay {
+        replay(path, options)?;
+    } else if let Some(ticks) = options.rollback_test {
+        rollback_test(ticks, options)?;
+    } else if let Some(ticks) = options.net_test {
+        net_test(ticks, options)?;
+    } else if options.server {
+        serve(options)?;
+    } else if let Some(server) = &options.bot {
+        bot(server, options)?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Plays a recording or a hand written input script back. Stops at the first tick whose
+// checksum differs from the recorded one, the dump of that tick can then be diffed against a
+// good run. With --record the run is saved again with a checksum for every tick
+fn replay(path: &str, options: &Options) -> Result<(), String> {
+    let recording = InputRecording::load(path)?;
+    let level_path = recording
+        .level
+        .as_ref()
+        .map_or(DEFAULT_LEVEL, |level| level.path.as_str());
+    let level = Level::load(level_path)?;
+    let played = LevelId::new(level_path, &level);
+    if let Some(recorded) = &recording.level {
+        if recorded.checksum != played.checksum {
+            return Err(format!(
+                "{} was recorded on {}, the level is now {}",
+                path, recorded, played
+            ));
+        }
+    }
+    let mut sim = Simulation::from_level(recording.seed, true, level, Profiler::default())?;
+    if options.trace.is_some() {
+        sim.world.read_resource::<Profiler>().start_trace();
+    }
+    let limit = options.ticks.unwrap_or(u64::MAX);
+    let mut rerecording = match &options.record {
+        Some(path) => Some(RecordingWriter::create(path, recording.seed, &played)?),
+        None => None,
+    };
+
+    for recorded in recording.ticks.iter() {
+        if sim.tick >= limit {
+            break;
+        }
+        sim.step(&recorded.input);
+        // Nothing plays the sounds, keep the queue from growing
+        *sim.world.write_resource::<SoundQueue>() = SoundQueue::default();
+        for line in sim.world.write_resource::<ScriptLog>().take() {
+            println!("{}", line);
+        }
+
+        if let Some(rerecording) = &mut rerecording {
+            rerecording.record(&recorded.input, Some(sim.checksum()))?;
+        }
+        if let Some(expected) = recorded.checksum {
+            let actual = sim.checksum();
+            if actual != expected {
+                println!(
+                    "desync at tick {}: recorded {:016x}, replayed {:016x}",
+                    sim.tick, expected, actual
+                );
+                break;
+            }
+        }
+    }
+
+    println!(
+        "replayed {} ticks with seed {}, checksum {:016x}",
+        sim.tick,
+        recording.seed,
+        sim.checksum()
+    );
+    if let Some(trace) = &options.trace {
+        sim.world.read_resource::<Profiler>().write_trace(trace)?;
+    }
+    if let Some(dump) = &options.dump {
+        StateDump::capture(&sim.world, sim.tick).save(dump)?;
+    }
+    Ok(())
+}
+
+fn diff(first: &str, second: &str) -> Result<(), String> {
+    let differences = StateDump::load(first)?.diff(&StateDump::load(second)?);
+    if differences.is_empty() {
+        println!("dumps match");
+    }
+    for line in differences {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// Movement is held for a while like a player would, presses come now and then
+pub fn random_input(rng: &mut Rng, last: &TickInput) -> TickInput {
+    let axis = |rng: &mut Rng| (rng.next_u32() % 3) as i8 - 1;
+    let movement = match last.movement {
+        Some(movement) if !rng.next_u32().is_multiple_of(20) => movement,
+        _ => MovementCommand::Move(Direction::MoveDelta {
+            x: axis(rng),
+            y: axis(rng),
+        }),
+    };
+    let mut actions = Vec::new();
+    if rng.next_u32().is_multiple_of(15) {
+        actions.push(ActionCommand::Shoot(Direction::MoveDelta { x: 0, y: 0 }));
+    }
+    if rng.next_u32().is_multiple_of(40) {
+        actions.push(ActionCommand::Jump);
+    }
+    TickInput {
+        movement: Some(movement),
+        actions,
+    }
+}
+
+// Two peers over a laggy in process connection, fed random input. Every rollback has to
+// land both on the same world, the exchanged checksums tell whether they did
+fn rollback_test(ticks: u64, options: &Options) -> Result<(), String> {
+    let seed = options.seed.unwrap_or(DEFAULT_SEED);
+    let mut config = RollbackConfig::default();
+    if let Some(delay) = options.input_delay {
+        config.input_delay = delay;
+    }
+
+    let (first, second) = LoopbackTransport::pair(TEST_LATENCY);
+    let level = LevelId::new(DEFAULT_LEVEL, &Level::load(DEFAULT_LEVEL)?);
+    let mut peers = [
+        (
+            Simulation::new_multiplayer(seed, 2)?,
+            RollbackSession::new(0, config, first, level.clone()),
+        ),
+        (
+            Simulation::new_multiplayer(seed, 2)?,
+            RollbackSession::new(1, config, second, level),
+        ),
+    ];
+    let mut rngs = [Rng::new(seed ^ 1), Rng::new(seed ^ 2)];
+    let mut inputs = [TickInput::default(), TickInput::default()];
+
+    while peers.iter().any(|(sim, _)| sim.tick < ticks) {
+        for (slot, (sim, session)) in peers.iter_mut().enumerate() {
+            if session.advance(sim, &inputs[slot])? {
+                inputs[slot] = random_input(&mut rngs[slot], &inputs[slot]);
+            }
+            *sim.world.write_resource::<SoundQueue>() = SoundQueue::default();
+        }
+    }
+
+    for (sim, session) in peers.iter() {
+        println!(
+            "peer {}: {} ticks, {} rollbacks, {} frames simulated again, {} checksums compared, \
+             {} packets dropped",
+            session.local_slot,
+            sim.tick,
+            session.rollbacks,
+            session.resimulated,
+            session.checked,
+            session.dropped
+        );
+        if let Some(frame) = session.desync {
+            return Err(format!(
+                "peer {} went out of sync at frame {}",
+                session.local_slot, frame
+            ));
+        }
+    }
+    println!("peers stayed in sync");
+    Ok(())
+}
+
+// Runs at the simulation rate until killed, or for --ticks ticks
+fn serve(options: &Options) -> Result<(), String> {
+    let bind = options
+        .bind
+        .clone()
+        .unwrap_or_else(|| format!("0.0.0.0:{}", SERVER_PORT));
+    let seed = options.seed.unwrap_or(DEFAULT_SEED);
+    let mut server = NetServer::new(&bind)?;
+    let mut sim = Simulation::new_server(seed)?;
+    println!("listening on {}", server.local_addr()?);
+
+    let limit = options.ticks.unwrap_or(u64::MAX);
+    let mut next = Instant::now();
+    while sim.tick < limit {
+        server.tick(&mut sim)?;
+        for event in server.take_events() {
+            println!("{}", event);
+        }
+        next += SIM_RATE;
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+    Ok(())
+}
+
+// A server and one client with random input talking over localhost UDP, both stepped from
+// this thread. Reports how often prediction was corrected and what the snapshots cost
+fn net_test(ticks: u64, options: &Options) -> Result<(), String> {
+    let seed = options.seed.unwrap_or(DEFAULT_SEED);
+    let mut server = NetServer::new("127.0.0.1:0")?;
+    let mut sim = Simulation::new_server(seed)?;
+    let mut client = NetClient::new(UdpTransport::new(
+        "127.0.0.1:0",
+        &server.local_addr()?.to_string(),
+    )?)?;
+
+    let mut rng = Rng::new(seed);
+    let mut input = TickInput::default();
+    let mut next = Instant::now();
+    while sim.tick < ticks {
+        client.tick(&input)?;
+        input = random_input(&mut rng, &input);
+        server.tick(&mut sim)?;
+        for event in server.take_events() {
+            println!("server: {}", event);
+        }
+        for event in client.take_events() {
+            println!("client: {}", event);
+        }
+        // Real time between ticks so packets have a chance to arrive
+        next += SIM_RATE / 4;
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+
+    if client.slot.is_none() || client.server_tick().is_none() {
+        return Err("the client never heard from the server".to_string());
+    }
+    println!(
+        "{} ticks, {} snapshots averaging {} bytes, {} prediction corrections, {} packets dropped",
+        sim.tick,
+        server.snapshots_sent,
+        server.bytes_sent / server.snapshots_sent.max(1),
+        client.corrections,
+        client.dropped
+    );
+    Ok(())
+}
+
+// Joins a server and plays with random input in real time, until killed or for --ticks
+// ticks. A handful of these is a cheap load test
+fn bot(server: &str, options: &Options) -> Result<(), String> {
+    let bind = options.bind.as_deref().unwrap_or("0.0.0.0:0");
+    let mut client = NetClient::new(UdpTransport::new(bind, server)?)?;
+    let mut rng = Rng::new(options.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(DEFAULT_SEED, |d| d.as_nanos() as u64)
+    }));
+
+    let limit = options.ticks.unwrap_or(u64::MAX);
+    let mut input = TickInput::default();
+    let mut next = Instant::now();
+    let mut ticks = 0;
+    while ticks < limit {
+        client.tick(&input)?;
+        for event in client.take_events() {
+            println!("{}", event);
+        }
+        if client.slot.is_some() {
+            input = random_input(&mut rng, &input);
+        }
+        ticks += 1;
+        next += SIM_RATE;
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+
+    if client.server_tick().is_none() {
+        return Err(format!("{} never answered", server));
+    }
+    println!(
+        "played {} ticks, {} prediction corrections, {} packets dropped",
+        ticks, client.corrections, client.dropped
+    );
+    Ok(())
+}
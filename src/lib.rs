@@ -0,0 +1,27 @@
+pub mod entities;
+pub mod headless;
+pub mod library;
+pub mod options;
+pub mod simulation;
+pub mod systems;
+
+use std::time::Duration;
+
+// The modules refer to each other through the crate root, like crate::commands
+use entities::*;
+use entity_flags::*;
+use library::globalcomponents::*;
+use library::*;
+use sdl2::rect::Rect;
+use systems::*;
+
+pub const WINDOW_HEIGHT: u32 = 600;
+pub const WINDOW_WIDTH: u32 = 800;
+// Length of one simulation tick, speeds and timers are tuned for 60 ticks a second
+pub const SIM_RATE: Duration = Duration::new(0, 1_000_000_000u32 / 60);
+pub const MAX_TICKS_PER_FRAME: u32 = 5;
+pub const FRAME_RATE: Duration = Duration::new(0, 1_000_000_000u32 / 60);
+// Slot n of a local netplay session listens on this port plus n
+pub const NETPLAY_PORT: u16 = 7000;
+// Port a server listens on unless told otherwise
+pub const SERVER_PORT: u16 = 7100;
@@ -0,0 +1,671 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use sdl2::rect::Rect;
+use specs::prelude::*;
+
+use crate::entity_ai::AI;
+use crate::entity_animation::Animation;
+use crate::entity_components::*;
+use crate::entity_platform::PlatformPath;
+use crate::entity_script::Script;
+use crate::rng::Rng;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// FNV-1a, unlike the std hashers it gives the same result on every run and platform
+#[derive(Debug, Clone)]
+pub struct StateHasher {
+    hash: u64,
+    // Hash which id a referenced entity has but not its generation
+    ignore_generations: bool,
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        StateHasher {
+            hash: FNV_OFFSET,
+            ignore_generations: false,
+        }
+    }
+}
+
+impl StateHasher {
+    pub fn ignoring_generations() -> StateHasher {
+        StateHasher {
+            ignore_generations: true,
+            ..StateHasher::default()
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.hash ^= *b as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        self.write_bytes(&v.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.write_bytes(&v.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, v: i32) {
+        self.write_bytes(&v.to_le_bytes());
+    }
+
+    // Bit exact, two floats only hash alike if the simulation really agrees
+    pub fn write_f32(&mut self, v: f32) {
+        self.write_u32(v.to_bits());
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_bytes(&[v as u8]);
+    }
+
+    pub fn write_rect(&mut self, r: Rect) {
+        self.write_i32(r.x());
+        self.write_i32(r.y());
+        self.write_u32(r.width());
+        self.write_u32(r.height());
+    }
+
+    // Length first, so two lists never run into each other
+    pub fn write_entities(&mut self, entities: &[Entity]) {
+        self.write_u32(entities.len() as u32);
+        for e in entities.iter() {
+            self.write_entity(Some(*e));
+        }
+    }
+
+    pub fn write_entity(&mut self, e: Option<Entity>) {
+        match e {
+            Some(e) if self.ignore_generations => self.write_u32(e.id()),
+            Some(e) => {
+                self.write_u32(e.id());
+                self.write_i32(e.gen().id());
+            }
+            None => self.write_u32(u32::MAX),
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+// Implemented by everything whose value decides how the simulation continues
+pub trait StateHash {
+    fn hash_state(&self, h: &mut StateHasher);
+}
+
+impl StateHash for Position {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_i32(self.point.x());
+        h.write_i32(self.point.y());
+    }
+}
+
+impl StateHash for Velocity {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.speed as u32);
+        h.write_i32(self.max_speed as i32);
+        h.write_u32(self.acc);
+        h.write_f32(self.carry.0);
+        h.write_f32(self.carry.1);
+        match self.last_dir {
+            Some(Direction::MoveDelta { x, y }) => {
+                h.write_i32(x as i32);
+                h.write_i32(y as i32);
+            }
+            None => h.write_u32(u32::MAX),
+        }
+    }
+}
+
+impl StateHash for Collideable {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_rect(self.col_box);
+        h.write_f32(self.friction);
+    }
+}
+
+impl StateHash for GravityAfflicted {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_i32(self.max_vel);
+        h.write_bool(self.grounded);
+        match self.grounded_rect {
+            Some(rect) => h.write_rect(rect),
+            None => h.write_u32(u32::MAX),
+        }
+        h.write_f32(self.ground_friction);
+        h.write_i32(self.jump_speed as i32);
+    }
+}
+
+impl StateHash for Health {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.hp);
+        h.write_u32(self.max_hp);
+        h.write_entity(self.last_attacker);
+    }
+}
+
+impl StateHash for Invulnerability {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.remaining);
+    }
+}
+
+impl StateHash for DecayLife {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.life);
+    }
+}
+
+impl StateHash for ProjectileProperties {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_entity(self.owner);
+        h.write_u32(self.team.unwrap_or(u32::MAX));
+        h.write_f32(self.heading);
+        h.write_i32(self.speed as i32);
+        h.write_f32(self.homing);
+        h.write_u32(self.pierce);
+        h.write_entities(&self.hits);
+        h.write_bool(self.launched);
+    }
+}
+
+impl StateHash for Team {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.id);
+    }
+}
+
+impl StateHash for Score {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.kills);
+        h.write_u32(self.damage_dealt);
+    }
+}
+
+impl StateHash for Damage {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.dmg);
+        h.write_u32(self.dmg_boxes.len() as u32);
+        for b in self.dmg_boxes.iter() {
+            h.write_rect(*b);
+        }
+        h.write_u32(self.kind as u32);
+        h.write_entities(&self.hits);
+        h.write_i32(self.knockback as i32);
+    }
+}
+
+impl StateHash for Weapon {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.fire_delay);
+        h.write_u32(self.cooldown);
+        h.write_u32(self.projectile_count);
+        h.write_f32(self.spread);
+        h.write_i32(self.speed as i32);
+        h.write_u32(self.damage);
+        h.write_u32(self.kind as u32);
+        h.write_i32(self.knockback as i32);
+        h.write_u32(self.lifetime);
+        h.write_u32(self.ammo_per_shot);
+        h.write_u32(self.pierce);
+        h.write_bool(self.gravity);
+        h.write_f32(self.homing);
+        // The sprite region sizes the damage box of every projectile fired
+        h.write_u32(self.sprite.spritesheet as u32);
+        h.write_rect(self.sprite.region);
+    }
+}
+
+impl StateHash for Pickup {
+    fn hash_state(&self, h: &mut StateHasher) {
+        match &self.kind {
+            PickupKind::Heal(amount) => {
+                h.write_u32(0);
+                h.write_u32(*amount);
+            }
+            PickupKind::Item(stack) => {
+                h.write_u32(1);
+                h.write_u32(stack.item as u32);
+                h.write_u32(stack.count);
+            }
+            PickupKind::Weapon(weapon) => {
+                h.write_u32(2);
+                weapon.hash_state(h);
+            }
+        }
+        h.write_rect(self.area);
+    }
+}
+
+impl StateHash for Inventory {
+    fn hash_state(&self, h: &mut StateHasher) {
+        for stack in self.slots.iter() {
+            h.write_u32(stack.item as u32);
+            h.write_u32(stack.count);
+        }
+    }
+}
+
+impl StateHash for PlatformerController {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_bool(self.jump_held);
+        h.write_u32(self.coyote);
+        h.write_u32(self.buffer);
+        h.write_u32(self.air_jumps);
+        h.write_bool(self.jumping);
+        h.write_f32(self.air_accumulator);
+    }
+}
+
+impl StateHash for Mass {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_f32(self.mass);
+    }
+}
+
+impl StateHash for Friction {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_f32(self.friction);
+    }
+}
+
+impl StateHash for GravityScale {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_f32(self.scale);
+    }
+}
+
+impl StateHash for GravityZone {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_rect(self.area);
+        h.write_f32(self.scale);
+    }
+}
+
+impl StateHash for RigidBody {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_f32(self.velocity.0);
+        h.write_f32(self.velocity.1);
+        h.write_f32(self.subpixel.0);
+        h.write_f32(self.subpixel.1);
+    }
+}
+
+impl StateHash for PlatformPath {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.waypoints.len() as u32);
+        for point in self.waypoints.iter() {
+            h.write_i32(point.x());
+            h.write_i32(point.y());
+        }
+        h.write_f32(self.speed);
+        h.write_u32(self.easing as u32);
+        h.write_u32(self.mode as u32);
+        h.write_u32(self.from as u32);
+        h.write_u32(self.to as u32);
+        h.write_f32(self.progress);
+        h.write_bool(self.forward);
+        h.write_i32(self.velocity.0);
+        h.write_i32(self.velocity.1);
+        h.write_entities(&self.riders);
+    }
+}
+
+impl StateHash for AI {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.current as u32);
+        h.write_u32(self.ticks_in_state);
+        h.write_u32(self.patrol_index as u32);
+        h.write_u32(self.cooldown);
+        h.write_u32(self.path.len() as u32);
+        for (point, jump) in self.path.iter() {
+            h.write_i32(point.x());
+            h.write_i32(point.y());
+            h.write_bool(*jump);
+        }
+        match self.path_goal {
+            Some((x, y)) => {
+                h.write_i32(x);
+                h.write_i32(y);
+            }
+            None => h.write_u32(u32::MAX),
+        }
+        h.write_u64(self.path_grid);
+    }
+}
+
+impl StateHash for Animation {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u32(self.current as u32);
+        h.write_u32(self.elapsed);
+    }
+}
+
+impl StateHash for Script {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_bytes(self.path.as_bytes());
+        h.write_bool(self.started);
+        // Maps iterate in key order, so equal states print the same
+        h.write_bytes(format!("{:?}", self.state).as_bytes());
+        h.write_u32(self.touching.len() as u32);
+    }
+}
+
+impl StateHash for Rng {
+    fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u64(self.state());
+    }
+}
+
+// Hash of one component of one entity, or of a resource when entity is None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateEntry {
+    pub entity: Option<u32>,
+    pub component: String,
+    pub hash: u64,
+    // Debug print of the value, only filled in for dumps
+    pub detail: String,
+}
+
+impl StateEntry {
+    pub fn label(&self) -> String {
+        match self.entity {
+            Some(id) => format!("entity {} {}", id, self.component),
+            None => self.component.clone(),
+        }
+    }
+}
+
+fn entry<T: StateHash + Debug>(
+    mut h: StateHasher,
+    entity: Option<u32>,
+    name: &str,
+    value: &T,
+    detail: bool,
+) -> StateEntry {
+    value.hash_state(&mut h);
+    StateEntry {
+        entity,
+        component: name.to_string(),
+        hash: h.finish(),
+        detail: if detail {
+            format!("{:?}", value)
+        } else {
+            String::new()
+        },
+    }
+}
+
+fn collect<T: Component + StateHash + Debug>(
+    world: &World,
+    name: &str,
+    hasher: &StateHasher,
+    detail: bool,
+    out: &mut Vec<StateEntry>,
+) {
+    let storage = world.read_storage::<T>();
+    for (e, value) in (&world.entities(), &storage).join() {
+        out.push(entry(hasher.clone(), Some(e.id()), name, value, detail));
+    }
+}
+
+// Entries in a fixed order: component by component, entities by id
+fn collect_all(world: &World, detail: bool) -> Vec<StateEntry> {
+    collect_with(world, &StateHasher::default(), detail)
+}
+
+fn collect_with(world: &World, hasher: &StateHasher, detail: bool) -> Vec<StateEntry> {
+    let mut out = Vec::new();
+    collect::<Position>(world, "Position", hasher, detail, &mut out);
+    collect::<Velocity>(world, "Velocity", hasher, detail, &mut out);
+    collect::<Collideable>(world, "Collideable", hasher, detail, &mut out);
+    collect::<GravityAfflicted>(world, "GravityAfflicted", hasher, detail, &mut out);
+    collect::<Health>(world, "Health", hasher, detail, &mut out);
+    collect::<Invulnerability>(world, "Invulnerability", hasher, detail, &mut out);
+    collect::<DecayLife>(world, "DecayLife", hasher, detail, &mut out);
+    collect::<ProjectileProperties>(world, "ProjectileProperties", hasher, detail, &mut out);
+    collect::<Team>(world, "Team", hasher, detail, &mut out);
+    collect::<Score>(world, "Score", hasher, detail, &mut out);
+    collect::<Damage>(world, "Damage", hasher, detail, &mut out);
+    collect::<Weapon>(world, "Weapon", hasher, detail, &mut out);
+    collect::<Pickup>(world, "Pickup", hasher, detail, &mut out);
+    collect::<Inventory>(world, "Inventory", hasher, detail, &mut out);
+    collect::<PlatformerController>(world, "PlatformerController", hasher, detail, &mut out);
+    collect::<Mass>(world, "Mass", hasher, detail, &mut out);
+    collect::<Friction>(world, "Friction", hasher, detail, &mut out);
+    collect::<GravityScale>(world, "GravityScale", hasher, detail, &mut out);
+    collect::<GravityZone>(world, "GravityZone", hasher, detail, &mut out);
+    collect::<RigidBody>(world, "RigidBody", hasher, detail, &mut out);
+    collect::<PlatformPath>(world, "PlatformPath", hasher, detail, &mut out);
+    collect::<AI>(world, "AI", hasher, detail, &mut out);
+    collect::<Animation>(world, "Animation", hasher, detail, &mut out);
+    collect::<Script>(world, "Script", hasher, detail, &mut out);
+    out.push(entry(
+        hasher.clone(),
+        None,
+        "Rng",
+        &*world.read_resource::<Rng>(),
+        detail,
+    ));
+    out
+}
+
+fn combine(entries: &[StateEntry]) -> u64 {
+    let mut h = StateHasher::default();
+    for e in entries {
+        h.write_u32(e.entity.unwrap_or(u32::MAX));
+        h.write_bytes(e.component.as_bytes());
+        h.write_u64(e.hash);
+    }
+    h.finish()
+}
+
+// Checksum of all simulation relevant state, equal on two machines only if they agree
+pub fn world_checksum(world: &World) -> u64 {
+    combine(&collect_all(world, false))
+}
+
+// Same as world_checksum but blind to entity generations. Peers hand out the same ids,
+// but rolling back bumps the generations of the ids it frees and takes again
+pub fn portable_checksum(world: &World) -> u64 {
+    combine(&collect_with(
+        world,
+        &StateHasher::ignoring_generations(),
+        false,
+    ))
+}
+
+// Per entity and component hashes of one tick, compared between runs to find a desync
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateDump {
+    pub tick: u64,
+    pub entries: Vec<StateEntry>,
+}
+
+impl StateDump {
+    pub fn capture(world: &World, tick: u64) -> StateDump {
+        StateDump {
+            tick,
+            entries: collect_all(world, true),
+        }
+    }
+
+    // One line per entry: `<entity or -> <component> <hash> <detail>`
+    pub fn to_text(&self) -> String {
+        let mut out = format!("tick {}\n", self.tick);
+        for e in self.entries.iter() {
+            let entity = e.entity.map_or("-".to_string(), |id| id.to_string());
+            out.push_str(&format!(
+                "{} {} {:016x} {}\n",
+                entity, e.component, e.hash, e.detail
+            ));
+        }
+        out
+    }
+
+    pub fn parse(source: &str) -> Result<StateDump, String> {
+        let mut lines = source.lines().enumerate();
+        let tick = match lines.next() {
+            Some((_, line)) => line
+                .strip_prefix("tick ")
+                .and_then(|t| t.trim().parse::<u64>().ok())
+                .ok_or_else(|| "line 1: expected `tick <number>`".to_string())?,
+            None => return Err("empty dump".to_string()),
+        };
+
+        let mut entries = Vec::new();
+        for (line_no, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", line_no + 1, msg);
+            let mut parts = line.splitn(4, ' ');
+            let entity = match parts.next() {
+                Some("-") => None,
+                Some(id) => Some(id.parse::<u32>().map_err(|_| err("bad entity id"))?),
+                None => return Err(err("missing entity")),
+            };
+            let component = parts.next().ok_or_else(|| err("missing component"))?;
+            let hash = parts
+                .next()
+                .and_then(|h| u64::from_str_radix(h, 16).ok())
+                .ok_or_else(|| err("bad hash"))?;
+            entries.push(StateEntry {
+                entity,
+                component: component.to_string(),
+                hash,
+                detail: parts.next().unwrap_or("").to_string(),
+            });
+        }
+
+        Ok(StateDump { tick, entries })
+    }
+
+    pub fn load(path: &str) -> Result<StateDump, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        StateDump::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_text()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Every entity and component that differs between the two dumps, empty if they agree
+    pub fn diff(&self, other: &StateDump) -> Vec<String> {
+        let key = |e: &StateEntry| (e.entity.unwrap_or(u32::MAX), e.component.clone());
+        let mine: BTreeMap<_, _> = self.entries.iter().map(|e| (key(e), e)).collect();
+        let theirs: BTreeMap<_, _> = other.entries.iter().map(|e| (key(e), e)).collect();
+
+        let mut out = Vec::new();
+        if self.tick != other.tick {
+            out.push(format!("tick {} vs {}", self.tick, other.tick));
+        }
+        for (k, a) in mine.iter() {
+            match theirs.get(k) {
+                Some(b) if a.hash == b.hash => {}
+                Some(b) => out.push(format!("{}: {} vs {}", a.label(), a.detail, b.detail)),
+                None => out.push(format!("{}: only in the first dump", a.label())),
+            }
+        }
+        for (k, b) in theirs.iter() {
+            if !mine.contains_key(k) {
+                out.push(format!("{}: only in the second dump", b.label()));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_flags::Player;
+    use crate::simulation::Simulation;
+
+    fn player(sim: &Simulation) -> Entity {
+        let players = sim.world.read_storage::<Player>();
+        (&sim.world.entities(), &players).join().next().unwrap().0
+    }
+
+    #[test]
+    fn same_world_has_no_diff() {
+        let sim = Simulation::new(3, true).unwrap();
+        let a = StateDump::capture(&sim.world, 0);
+        let b = StateDump::capture(&sim.world, 0);
+        assert!(a.diff(&b).is_empty());
+    }
+
+    #[test]
+    fn diff_names_the_changed_component() {
+        let sim = Simulation::new(3, true).unwrap();
+        let before = StateDump::capture(&sim.world, 5);
+        let player = player(&sim);
+        sim.world
+            .write_storage::<Team>()
+            .get_mut(player)
+            .unwrap()
+            .id = 7;
+        sim.world
+            .write_storage::<Collideable>()
+            .get_mut(player)
+            .unwrap()
+            .friction = 0.5;
+        let after = StateDump::capture(&sim.world, 5);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.len(), 2, "{:?}", diff);
+        assert!(diff[0].starts_with(&format!("entity {} Collideable: ", player.id())));
+        assert!(diff[1].starts_with(&format!("entity {} Team: ", player.id())));
+        assert!(diff[1].ends_with("Team { id: 7 }"), "{}", diff[1]);
+    }
+
+    #[test]
+    fn diff_lists_missing_entries_and_ticks() {
+        let sim = Simulation::new(3, true).unwrap();
+        let player = player(&sim);
+        sim.world
+            .write_storage::<Mass>()
+            .insert(player, Mass { mass: 2.0 })
+            .unwrap();
+        let before = StateDump::capture(&sim.world, 5);
+        sim.world.write_storage::<Mass>().remove(player);
+        sim.world
+            .write_storage::<GravityScale>()
+            .insert(player, GravityScale { scale: 0.5 })
+            .unwrap();
+        let after = StateDump::capture(&sim.world, 6);
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff,
+            vec![
+                "tick 5 vs 6".to_string(),
+                format!("entity {} Mass: only in the first dump", player.id()),
+                format!(
+                    "entity {} GravityScale: only in the second dump",
+                    player.id()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn dump_survives_text() {
+        let sim = Simulation::new(3, true).unwrap();
+        let dump = StateDump::capture(&sim.world, 12);
+        assert_eq!(StateDump::parse(&dump.to_text()), Ok(dump));
+        assert!(StateDump::parse("").is_err());
+        assert!(StateDump::parse("tick 1\n0 Position zz").is_err());
+    }
+}
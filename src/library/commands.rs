@@ -1,11 +1,28 @@
 use crate::entity_components::Direction;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MovementCommand {
     Move(Direction),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ActionCommand {
     Shoot(Direction),
+    // Uses the first usable item in the inventory
+    Use,
+    // Press of the jump button, holding it is read from the movement command
+    Jump,
+}
+
+// Everything the players fed into one tick of the simulation
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TickInput {
+    pub movement: Option<MovementCommand>,
+    pub actions: Vec<ActionCommand>,
+}
+
+// Input of every NetPlayer for the current tick, indexed by slot
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PlayerInputs {
+    pub inputs: Vec<TickInput>,
 }
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use sdl2::rect::Point;
+use specs::prelude::*;
+
+use crate::entity_components::Position;
+use crate::entity_flags::*;
+use crate::inspect::*;
+use crate::prefabs::*;
+
+// Lines of output kept for the overlay
+const MAX_LOG: usize = 200;
+
+// What a command gets to work with
+pub struct ConsoleContext<'w> {
+    pub world: &'w mut World,
+    // World point under the mouse
+    pub cursor: Point,
+    // Length of a simulation tick, the game loop picks up changes on its next frame
+    pub sim_rate: &'w mut Duration,
+}
+
+type CommandFn = Box<dyn Fn(&mut ConsoleContext, &[&str]) -> Result<String, String>>;
+
+struct ConsoleCommand {
+    usage: String,
+    help: String,
+    run: CommandFn,
+}
+
+// Developer console. The window feeds it typed text and draws it, commands are plain
+// functions over the world that game code can add to with register
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+    pub log: Vec<String>,
+    commands: BTreeMap<String, ConsoleCommand>,
+    history: Vec<String>,
+    // Position while browsing the history, None when editing a new line
+    browsing: Option<usize>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console::new()
+    }
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let mut console = Console {
+            open: false,
+            input: String::new(),
+            log: Vec::new(),
+            commands: BTreeMap::new(),
+            history: Vec::new(),
+            browsing: None,
+        };
+        console.register_builtins();
+        console
+    }
+
+    // Usage is the part after the name, e.g. "<x> <y>", shown by help
+    pub fn register<F>(&mut self, name: &str, usage: &str, help: &str, run: F)
+    where
+        F: Fn(&mut ConsoleContext, &[&str]) -> Result<String, String> + 'static,
+    {
+        self.commands.insert(
+            name.to_string(),
+            ConsoleCommand {
+                usage: usage.to_string(),
+                help: help.to_string(),
+                run: Box::new(run),
+            },
+        );
+    }
+
+    pub fn print(&mut self, text: &str) {
+        self.log.extend(text.lines().map(|l| l.to_string()));
+        if self.log.len() > MAX_LOG {
+            self.log.drain(..self.log.len() - MAX_LOG);
+        }
+    }
+
+    // Runs the line being typed
+    pub fn submit(&mut self, ctx: &mut ConsoleContext) {
+        let line = std::mem::take(&mut self.input);
+        self.browsing = None;
+        if line.trim().is_empty() {
+            return;
+        }
+        self.history.push(line.clone());
+        self.execute(&line, ctx);
+    }
+
+    pub fn execute(&mut self, line: &str, ctx: &mut ConsoleContext) {
+        self.print(&format!("> {}", line));
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return,
+        };
+
+        let result = if name == "help" {
+            Ok(self.help())
+        } else {
+            match self.commands.get(name) {
+                Some(command) => (command.run)(ctx, args),
+                None => Err(format!("unknown command `{}`, try help", name)),
+            }
+        };
+        match result {
+            Ok(output) => self.print(&output),
+            Err(e) => self.print(&format!("error: {}", e)),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut lines = vec!["help - lists the commands".to_string()];
+        for (name, command) in self.commands.iter() {
+            lines.push(format!("{} {} - {}", name, command.usage, command.help));
+        }
+        lines.join("\n")
+    }
+
+    // Up and down arrows walk through earlier lines
+    pub fn history_back(&mut self) {
+        let index = match self.browsing {
+            Some(i) => i.saturating_sub(1),
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.browsing = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    pub fn history_forward(&mut self) {
+        match self.browsing {
+            Some(i) if i + 1 < self.history.len() => {
+                self.browsing = Some(i + 1);
+                self.input = self.history[i + 1].clone();
+            }
+            _ => {
+                self.browsing = None;
+                self.input.clear();
+            }
+        }
+    }
+
+    fn register_builtins(&mut self) {
+        self.register(
+            "spawn",
+            "<prefab>",
+            "builds a prefab at the mouse cursor",
+            |ctx, args| {
+                let name = args.first().ok_or("which prefab?")?;
+                let prefab = ctx.world.read_resource::<PrefabLibrary>().get(name)?;
+                let entity = prefab.build(ctx.world.create_entity(), ctx.cursor);
+                Ok(format!("spawned {} as entity {}", name, entity.id()))
+            },
+        );
+        self.register(
+            "set",
+            "<entity> <Component>.<field> <value>",
+            "changes one field of a component",
+            |ctx, args| {
+                let (entity, path, value) = match args {
+                    [entity, path, value] => (find_entity(ctx.world, entity)?, *path, *value),
+                    _ => return Err("expected an entity, Component.field and a value".to_string()),
+                };
+                let (component, field) = path
+                    .split_once('.')
+                    .ok_or_else(|| format!("`{}` is not Component.field", path))?;
+                set_field(ctx.world, entity, component, field, value)?;
+                Ok(format!(
+                    "{} of entity {} is now {}",
+                    path,
+                    entity.id(),
+                    value
+                ))
+            },
+        );
+        self.register(
+            "god",
+            "[entity]",
+            "toggles damage immunity, the player's without an entity",
+            |ctx, args| {
+                let entity = match args.first() {
+                    Some(id) => find_entity(ctx.world, id)?,
+                    None => player(ctx.world)?,
+                };
+                let mut god = ctx.world.write_storage::<GodMode>();
+                if god.remove(entity).is_some() {
+                    Ok(format!("god mode off for entity {}", entity.id()))
+                } else {
+                    god.insert(entity, GodMode).map_err(|e| e.to_string())?;
+                    Ok(format!("god mode on for entity {}", entity.id()))
+                }
+            },
+        );
+        self.register(
+            "rate",
+            "<ticks per second>",
+            "changes how fast the simulation runs, 60 is normal",
+            |ctx, args| {
+                let rate: u32 = args
+                    .first()
+                    .ok_or("how many ticks per second?")?
+                    .parse()
+                    .map_err(|_| "not a number".to_string())?;
+                if !(1..=1000).contains(&rate) {
+                    return Err("between 1 and 1000 ticks per second".to_string());
+                }
+                *ctx.sim_rate = Duration::from_secs(1) / rate;
+                Ok(format!("simulating {} ticks per second", rate))
+            },
+        );
+        self.register(
+            "teleport",
+            "[x y]",
+            "moves the player, to the mouse cursor without a point",
+            |ctx, args| {
+                let point = match args {
+                    [] => ctx.cursor,
+                    [x, y] => Point::new(
+                        x.parse().map_err(|_| format!("`{}` is not a number", x))?,
+                        y.parse().map_err(|_| format!("`{}` is not a number", y))?,
+                    ),
+                    _ => return Err("expected nothing or x and y".to_string()),
+                };
+                let entity = player(ctx.world)?;
+                if let Some(pos) = ctx.world.write_storage::<Position>().get_mut(entity) {
+                    pos.point = point;
+                }
+                Ok(format!("teleported to {}, {}", point.x(), point.y()))
+            },
+        );
+        self.register(
+            "list",
+            "[component]",
+            "lists entities with their components, only those with one if given",
+            |ctx, args| {
+                let mut lines = Vec::new();
+                for entity in ctx.world.entities().join() {
+                    let components: Vec<&str> = inspect_entity(ctx.world, entity)
+                        .into_iter()
+                        .map(|(name, _)| name)
+                        .collect();
+                    if let Some(filter) = args.first() {
+                        if !components.iter().any(|c| c.eq_ignore_ascii_case(filter)) {
+                            continue;
+                        }
+                    }
+                    lines.push(format!("{}: {}", entity.id(), components.join(" ")));
+                }
+                Ok(format!("{}\n{} entities", lines.join("\n"), lines.len()))
+            },
+        );
+        self.register(
+            "inspect",
+            "<entity>",
+            "shows the fields of an entity's components",
+            |ctx, args| {
+                let entity = find_entity(ctx.world, args.first().ok_or("which entity?")?)?;
+                let lines: Vec<String> = inspect_entity(ctx.world, entity)
+                    .into_iter()
+                    .map(|(name, fields)| {
+                        let fields: Vec<String> =
+                            fields.iter().map(|(f, v)| format!("{}={}", f, v)).collect();
+                        format!("{} {}", name, fields.join(" "))
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            },
+        );
+        self.register("prefabs", "", "lists what spawn can build", |_, _| {
+            Ok(PREFAB_NAMES.join(" "))
+        });
+    }
+}
+
+fn find_entity(world: &World, id: &str) -> Result<Entity, String> {
+    let id = id
+        .parse()
+        .map_err(|_| format!("`{}` is not an entity id", id))?;
+    entity_by_id(world, id).ok_or_else(|| format!("no entity {}", id))
+}
+
+// The keyboard controlled entity of a local game
+fn player(world: &World) -> Result<Entity, String> {
+    (
+        &world.entities(),
+        &world.read_storage::<KeyboardControlled>(),
+    )
+        .join()
+        .map(|(e, _)| e)
+        .next()
+        .ok_or_else(|| "there is no player".to_string())
+}
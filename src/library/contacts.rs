@@ -0,0 +1,45 @@
+use sdl2::rect::Rect;
+use specs::prelude::*;
+
+// Overlap between a rigid body and another collideable, found by CollisionSys
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Contact {
+    pub a: Entity,
+    pub b: Entity,
+    // Unit axis pointing from a towards b
+    pub normal: (f32, f32),
+    pub depth: f32,
+}
+
+// Contacts of the current tick, resolved by ContactSys
+#[derive(Debug, Clone, Default)]
+pub struct Contacts {
+    pub contacts: Vec<Contact>,
+}
+
+// Axis of least penetration between two boxes given by center and half extents,
+// None when they only touch or are apart
+pub fn box_overlap(
+    a_center: (f32, f32),
+    a_half: (f32, f32),
+    b_center: (f32, f32),
+    b_half: (f32, f32),
+) -> Option<((f32, f32), f32)> {
+    let dx = b_center.0 - a_center.0;
+    let dy = b_center.1 - a_center.1;
+    let overlap_x = a_half.0 + b_half.0 - dx.abs();
+    let overlap_y = a_half.1 + b_half.1 - dy.abs();
+    if overlap_x <= 0.0 || overlap_y <= 0.0 {
+        return None;
+    }
+
+    if overlap_x < overlap_y {
+        Some(((if dx < 0.0 { -1.0 } else { 1.0 }, 0.0), overlap_x))
+    } else {
+        Some(((0.0, if dy < 0.0 { -1.0 } else { 1.0 }), overlap_y))
+    }
+}
+
+pub fn half_extents(rect: Rect) -> (f32, f32) {
+    (rect.width() as f32 / 2.0, rect.height() as f32 / 2.0)
+}
@@ -0,0 +1,56 @@
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugShape {
+    Line {
+        from: Point,
+        to: Point,
+        color: Color,
+    },
+    Rect {
+        rect: Rect,
+        color: Color,
+    },
+    Text {
+        point: Point,
+        text: String,
+        color: Color,
+    },
+}
+
+// Shapes in world coordinates that systems want to see drawn, cleared at the start of every
+// tick. Only collected while the debug overlay is showing, so pushing costs nothing otherwise
+#[derive(Debug, Clone, Default)]
+pub struct DebugDraw {
+    pub enabled: bool,
+    pub shapes: Vec<DebugShape>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: Point, to: Point, color: Color) {
+        if self.enabled {
+            self.shapes.push(DebugShape::Line { from, to, color });
+        }
+    }
+
+    pub fn rect(&mut self, rect: Rect, color: Color) {
+        if self.enabled {
+            self.shapes.push(DebugShape::Rect { rect, color });
+        }
+    }
+
+    pub fn text(&mut self, point: Point, text: &str, color: Color) {
+        if self.enabled {
+            self.shapes.push(DebugShape::Text {
+                point,
+                text: text.to_string(),
+                color,
+            });
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+    }
+}
@@ -0,0 +1,348 @@
+use sdl2::rect::{Point, Rect};
+
+use crate::level::*;
+use crate::prefabs::PREFAB_NAMES;
+
+// Grid positions and sizes snap to
+pub const GRID: i32 = 16;
+
+// Pixels around a block's bottom right corner that grab it for resizing
+const RESIZE_HANDLE: i32 = 6;
+
+// Smallest block the editor makes, a click without dragging gives one of these
+const MIN_BLOCK: u32 = GRID as u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Drag {
+    Create { start: Point, end: Point },
+    // Offset from the grabbed point to the item's top left corner
+    Move { item: usize, offset: Point },
+    Resize { item: usize },
+}
+
+// Level editor: blocks and prefabs are placed, moved and resized with the mouse on a level that
+// is not running. Every change keeps the layout before it for undo
+pub struct Editor {
+    pub level: Level,
+    pub path: String,
+    // 0 places blocks, after that the prefabs in PREFAB_NAMES order
+    pub brush: usize,
+    pub snap: bool,
+    pub camera: Point,
+    pub selected: Option<usize>,
+    pub message: String,
+    drag: Option<Drag>,
+    undo: Vec<Level>,
+    redo: Vec<Level>,
+}
+
+impl Editor {
+    pub fn new(level: Level, path: &str) -> Editor {
+        Editor {
+            level,
+            path: path.to_string(),
+            brush: 0,
+            snap: true,
+            camera: Point::new(0, 0),
+            selected: None,
+            message: String::new(),
+            drag: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn brush_name(&self) -> &'static str {
+        match self.brush {
+            0 => "block",
+            n => PREFAB_NAMES[n - 1],
+        }
+    }
+
+    pub fn next_brush(&mut self) {
+        self.brush = (self.brush + 1) % (PREFAB_NAMES.len() + 1);
+    }
+
+    pub fn toggle_snap(&mut self) {
+        self.snap = !self.snap;
+    }
+
+    fn snapped(&self, point: Point) -> Point {
+        if !self.snap {
+            return point;
+        }
+        let snap = |v: i32| (v as f32 / GRID as f32).round() as i32 * GRID;
+        Point::new(snap(point.x()), snap(point.y()))
+    }
+
+    // Topmost item under a point, later lines of the level are drawn over earlier ones
+    pub fn item_at(&self, point: Point) -> Option<usize> {
+        self.level
+            .items
+            .iter()
+            .rposition(|item| item.bounds().contains_point(point))
+    }
+
+    // Block being dragged out, for the window to draw
+    pub fn preview(&self) -> Option<Rect> {
+        match self.drag {
+            Some(Drag::Create { start, end }) => Some(span(start, end)),
+            _ => None,
+        }
+    }
+
+    // Left button: grab the corner of a block to resize it, grab an item to move it, or place
+    // the brush on empty ground
+    pub fn press(&mut self, point: Point) {
+        let resize = self.level.items.iter().rposition(|item| {
+            let corner = item.bounds().bottom_right();
+            item.resizable()
+                && (point.x() - corner.x()).abs() <= RESIZE_HANDLE
+                && (point.y() - corner.y()).abs() <= RESIZE_HANDLE
+        });
+        // Redo survives until release shows the grab changed something
+        if let Some(item) = resize {
+            self.undo.push(self.level.clone());
+            self.selected = Some(item);
+            self.drag = Some(Drag::Resize { item });
+        } else if let Some(item) = self.item_at(point) {
+            self.undo.push(self.level.clone());
+            self.selected = Some(item);
+            self.drag = Some(Drag::Move {
+                item,
+                offset: self.level.items[item].bounds().top_left() - point,
+            });
+        } else if self.brush == 0 {
+            let start = self.snapped(point);
+            self.drag = Some(Drag::Create { start, end: start });
+        } else {
+            self.checkpoint();
+            self.level.items.push(LevelItem::Prefab {
+                name: self.brush_name().to_string(),
+                point: self.snapped(point),
+                script: None,
+            });
+            self.selected = Some(self.level.items.len() - 1);
+        }
+    }
+
+    pub fn drag_to(&mut self, point: Point) {
+        let snapped = self.snapped(point);
+        match self.drag {
+            Some(Drag::Create { start, .. }) => {
+                self.drag = Some(Drag::Create {
+                    start,
+                    end: snapped,
+                })
+            }
+            Some(Drag::Move { item, offset }) => {
+                let top_left = self.snapped(point + offset);
+                self.level.items[item].move_to(top_left);
+            }
+            Some(Drag::Resize { item }) => {
+                match &mut self.level.items[item] {
+                    LevelItem::Block { rect, .. } => resize(rect, snapped),
+                    // The path runs between centres, `to` moves along so it keeps its shape
+                    LevelItem::Platform { rect, to, .. } => {
+                        let center = rect.center();
+                        resize(rect, snapped);
+                        *to += rect.center() - center;
+                    }
+                    LevelItem::Prefab { .. } => {}
+                }
+            }
+            None => {}
+        }
+    }
+
+    pub fn release(&mut self, point: Point) {
+        self.drag_to(point);
+        match self.drag.take() {
+            Some(Drag::Create { start, end }) => {
+                self.checkpoint();
+                self.level.items.push(LevelItem::Block {
+                    rect: span(start, end),
+                    friction: 1.0,
+                    textured: false,
+                });
+                self.selected = Some(self.level.items.len() - 1);
+            }
+            // A click that only selected leaves nothing to undo
+            Some(_) if self.undo.last() == Some(&self.level) => {
+                self.undo.pop();
+            }
+            Some(_) => self.redo.clear(),
+            None => {}
+        }
+    }
+
+    // Right button
+    pub fn remove_at(&mut self, point: Point) {
+        if let Some(item) = self.item_at(point) {
+            self.checkpoint();
+            self.level.items.remove(item);
+            self.selected = None;
+        }
+    }
+
+    // Flips the selected block between textured and invisible terrain
+    pub fn toggle_texture(&mut self) {
+        if let Some(item) = self.selected {
+            self.checkpoint();
+            if let Some(LevelItem::Block { textured, .. }) = self.level.items.get_mut(item) {
+                *textured = !*textured;
+            }
+        }
+    }
+
+    pub fn set_spawn(&mut self, point: Point) {
+        self.checkpoint();
+        self.level.spawn = self.snapped(point);
+    }
+
+    fn checkpoint(&mut self) {
+        self.undo.push(self.level.clone());
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(level) = self.undo.pop() {
+            self.redo.push(std::mem::replace(&mut self.level, level));
+            self.selected = None;
+            self.drag = None;
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(level) = self.redo.pop() {
+            self.undo.push(std::mem::replace(&mut self.level, level));
+            self.selected = None;
+            self.drag = None;
+        }
+    }
+
+    pub fn save(&mut self) {
+        self.message = match self.level.save(&self.path) {
+            Ok(()) => format!("saved {}", self.path),
+            Err(e) => format!("error: {}", e),
+        };
+    }
+
+    // Back to what is on disk, undoable like any other change
+    pub fn load(&mut self) {
+        match Level::load(&self.path) {
+            Ok(level) => {
+                self.checkpoint();
+                self.level = level;
+                self.selected = None;
+                self.message = format!("loaded {}", self.path);
+            }
+            Err(e) => self.message = format!("error: {}", e),
+        }
+    }
+}
+
+// Block covering two corners in either order, never smaller than MIN_BLOCK
+fn span(a: Point, b: Point) -> Rect {
+    Rect::new(
+        a.x().min(b.x()),
+        a.y().min(b.y()),
+        ((a.x() - b.x()).unsigned_abs()).max(MIN_BLOCK),
+        ((a.y() - b.y()).unsigned_abs()).max(MIN_BLOCK),
+    )
+}
+
+// Moves the bottom right corner to the point, the top left stays put
+fn resize(rect: &mut Rect, corner: Point) {
+    rect.set_width((corner.x() - rect.x()).max(MIN_BLOCK as i32) as u32);
+    rect.set_height((corner.y() - rect.y()).max(MIN_BLOCK as i32) as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> Level {
+        Level::parse("spawn 0,0").unwrap()
+    }
+
+    fn editor() -> Editor {
+        Editor::new(empty(), "unused.level")
+    }
+
+    fn rects(editor: &Editor) -> Vec<Rect> {
+        editor
+            .level
+            .items
+            .iter()
+            .map(|item| item.bounds())
+            .collect()
+    }
+
+    #[test]
+    fn drag_places_and_moves_a_snapped_block() {
+        let mut editor = editor();
+        editor.press(Point::new(1, -2));
+        editor.drag_to(Point::new(40, 20));
+        assert_eq!(editor.preview(), Some(Rect::new(0, 0, 48, 16)));
+        editor.release(Point::new(63, 33));
+        assert_eq!(rects(&editor), [Rect::new(0, 0, 64, 32)]);
+        assert_eq!(editor.preview(), None);
+
+        // Grabbed in the middle, the top left corner keeps its offset to the mouse
+        editor.press(Point::new(10, 10));
+        editor.release(Point::new(110, 45));
+        assert_eq!(rects(&editor), [Rect::new(96, 32, 64, 32)]);
+    }
+
+    #[test]
+    fn undo_and_redo_step_through_changes() {
+        let mut editor = editor();
+        editor.press(Point::new(0, 0));
+        editor.release(Point::new(32, 32));
+        editor.set_spawn(Point::new(100, 100));
+        editor.remove_at(Point::new(5, 5));
+        assert!(editor.level.items.is_empty());
+
+        editor.undo();
+        assert_eq!(rects(&editor), [Rect::new(0, 0, 32, 32)]);
+        assert_eq!(editor.level.spawn, Point::new(96, 96));
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.level, empty());
+        // Nothing left to undo
+        editor.undo();
+        assert_eq!(editor.level, empty());
+
+        editor.redo();
+        editor.redo();
+        assert_eq!(editor.level.spawn, Point::new(96, 96));
+        assert_eq!(editor.level.items.len(), 1);
+
+        // A new change drops what could have been redone
+        editor.toggle_snap();
+        editor.set_spawn(Point::new(5, 5));
+        editor.redo();
+        assert_eq!(editor.level.items.len(), 1);
+        assert_eq!(editor.level.spawn, Point::new(5, 5));
+    }
+
+    #[test]
+    fn selecting_leaves_nothing_to_undo() {
+        let mut editor = editor();
+        editor.press(Point::new(0, 0));
+        editor.release(Point::new(32, 32));
+        editor.set_spawn(Point::new(64, 64));
+        editor.undo();
+
+        // Clicking the block without moving it keeps the redo and adds no step
+        editor.press(Point::new(5, 5));
+        editor.release(Point::new(5, 5));
+        assert_eq!(editor.selected, Some(0));
+        editor.redo();
+        assert_eq!(editor.level.spawn, Point::new(64, 64));
+        editor.undo();
+        editor.undo();
+        assert!(editor.level.items.is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use sdl2::rect::Point;
+use specs::Entity;
+
+use crate::entity_components::{DamageKind, Item};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaySound {
+    pub sound: String,
+    pub position: Option<Point>,
+    pub volume: f32,
+}
+
+impl PlaySound {
+    // Sound played at a world position, panned and attenuated around the camera
+    pub fn at(sound: &str, position: Point) -> PlaySound {
+        PlaySound {
+            sound: sound.to_string(),
+            position: Some(position),
+            volume: 1.0,
+        }
+    }
+
+    // Sound with no position, e.g. menu or ui feedback
+    pub fn global(sound: &str) -> PlaySound {
+        PlaySound {
+            sound: sound.to_string(),
+            position: None,
+            volume: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MusicCommand {
+    Play { track: String, fade_ms: i32 },
+    Stop { fade_ms: i32 },
+}
+
+// Lines kept in the ScriptLog until someone shows them
+const MAX_SCRIPT_LOG: usize = 100;
+
+// Script errors and notices. Systems can not print, the game moves them to the console
+#[derive(Debug, Clone, Default)]
+pub struct ScriptLog {
+    pub lines: Vec<String>,
+}
+
+impl ScriptLog {
+    pub fn push(&mut self, line: String) {
+        self.lines.push(line);
+        if self.lines.len() > MAX_SCRIPT_LOG {
+            self.lines.drain(..self.lines.len() - MAX_SCRIPT_LOG);
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SoundQueue {
+    pub sounds: Vec<PlaySound>,
+    pub music: Option<MusicCommand>,
+}
+
+impl SoundQueue {
+    pub fn push(&mut self, sound: PlaySound) {
+        self.sounds.push(sound);
+    }
+
+    // Switches tracks in sequence, the playing one fades out over the first half of fade_ms
+    // and the new one fades in over the second half. It is not a crossfade, there is only one
+    // music stream
+    pub fn play_music(&mut self, track: &str, fade_ms: i32) {
+        self.music = Some(MusicCommand::Play {
+            track: track.to_string(),
+            fade_ms,
+        });
+    }
+
+    pub fn stop_music(&mut self, fade_ms: i32) {
+        self.music = Some(MusicCommand::Stop { fade_ms });
+    }
+}
+
+// Damage after resistances, sent once it has been taken off the target's health
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamageDealt {
+    pub attacker: Option<Entity>,
+    pub target: Entity,
+    pub amount: u32,
+    pub kind: DamageKind,
+}
+
+// Sent when health reaches zero, DeathSys deletes the entity at the end of the tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Died {
+    pub entity: Entity,
+    pub killer: Option<Entity>,
+    pub position: Point,
+}
+
+// Sent for every pickup taken, partial item pickups report only what fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItemGained {
+    pub entity: Entity,
+    pub gained: Gained,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gained {
+    Item { item: Item, count: u32 },
+    // Health actually restored, capped by max_hp
+    Heal(u32),
+    Weapon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItemUsed {
+    pub entity: Entity,
+    pub item: Item,
+}
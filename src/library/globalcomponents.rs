@@ -1,3 +1,5 @@
+use sdl2::rect::Point;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ScreenInfo {
     pub screen_size: ScreenSize,
@@ -7,3 +9,76 @@ pub struct ScreenInfo {
 pub enum ScreenSize {
     Size { width: u32, height: u32 },
 }
+
+// World point drawn at the center of the screen
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Camera {
+    pub point: Point,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            point: Point::new(0, 0),
+        }
+    }
+}
+
+// Volume buses, each in the range 0.0..=1.0
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioVolume {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+}
+
+impl Default for AudioVolume {
+    fn default() -> Self {
+        AudioVolume {
+            master: 1.0,
+            music: 1.0,
+            sfx: 1.0,
+        }
+    }
+}
+
+// Whether ScriptSys picks up edited script files. Reading file times would make the tick
+// depend on the disk, so only local games that are neither deterministic nor recorded do
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct HotReload(pub bool);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct TeamRules {
+    pub friendly_fire: bool,
+}
+
+impl TeamRules {
+    // Entities without a team can hurt and be hurt by anyone
+    pub fn can_damage(&self, attacker: Option<u32>, target: Option<u32>) -> bool {
+        match (attacker, target) {
+            (Some(a), Some(t)) if a == t => self.friendly_fire,
+            _ => true,
+        }
+    }
+}
+
+// World wide physics tuning, entities adjust it with Mass, Friction and GravityScale
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicsConfig {
+    // Speed added to airborne entities every tick
+    pub gravity: (f32, f32),
+    // Speed lost per tick on a surface with friction 1.0
+    pub friction: f32,
+    // Speed lost per tick in the air, falling entities are capped by max_vel instead
+    pub air_drag: f32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            gravity: (0.0, 1.0),
+            friction: 1.0,
+            air_drag: 1.0,
+        }
+    }
+}
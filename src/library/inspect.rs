@@ -0,0 +1,333 @@
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+
+use crate::entity_ai::AI;
+use crate::entity_animation::Animation;
+use crate::entity_components::*;
+use crate::entity_flags::*;
+use crate::entity_platform::PlatformPath;
+use crate::entity_script::Script;
+
+// Fields of a component as text, so tools can show and edit components without knowing
+// their types. Components without editable fields keep the defaults and are only listed
+pub trait Inspect {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    fn set_field(&mut self, field: &str, _value: &str) -> Result<(), String> {
+        Err(no_field(field))
+    }
+}
+
+fn no_field(field: &str) -> String {
+    format!("no editable field `{}`", field)
+}
+
+fn parse<T: FromStr>(field: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid {}", value, field))
+}
+
+// Inspect for components whose fields all print with Display and parse with FromStr
+macro_rules! inspect_fields {
+    ($type:ty { $($field:ident),* }) => {
+        impl Inspect for $type {
+            fn fields(&self) -> Vec<(&'static str, String)> {
+                vec![$((stringify!($field), self.$field.to_string())),*]
+            }
+
+            fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+                match field {
+                    $(stringify!($field) => self.$field = parse(field, value)?,)*
+                    _ => return Err(no_field(field)),
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+inspect_fields!(GravityAfflicted {
+    max_vel,
+    grounded,
+    ground_friction,
+    jump_speed
+});
+inspect_fields!(DecayLife { life });
+inspect_fields!(Damage { dmg, knockback });
+inspect_fields!(Invulnerability {
+    duration,
+    remaining
+});
+inspect_fields!(Team { id });
+inspect_fields!(Score {
+    kills,
+    damage_dealt
+});
+inspect_fields!(Weapon {
+    fire_delay,
+    projectile_count,
+    spread,
+    speed,
+    damage,
+    knockback,
+    lifetime,
+    ammo_per_shot,
+    pierce,
+    gravity,
+    homing
+});
+inspect_fields!(PlatformerController {
+    jump_speed,
+    jump_cut,
+    coyote_ticks,
+    buffer_ticks,
+    air_control,
+    max_air_jumps,
+    wall_jump,
+    wall_jump_push
+});
+inspect_fields!(Mass { mass });
+inspect_fields!(Friction { friction });
+inspect_fields!(GravityScale { scale });
+inspect_fields!(RigidBody {
+    restitution,
+    damping
+});
+inspect_fields!(NetPlayer { slot });
+
+impl Inspect for Position {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("x", self.point.x().to_string()),
+            ("y", self.point.y().to_string()),
+        ]
+    }
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        match field {
+            "x" => self.point = Point::new(parse(field, value)?, self.point.y()),
+            "y" => self.point = Point::new(self.point.x(), parse(field, value)?),
+            _ => return Err(no_field(field)),
+        }
+        Ok(())
+    }
+}
+
+impl Inspect for Velocity {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let (x, y) = self.unencode_speed();
+        vec![
+            ("vx", x.to_string()),
+            ("vy", y.to_string()),
+            ("max_speed", self.max_speed.to_string()),
+            ("acc", self.acc.to_string()),
+        ]
+    }
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let (x, y) = self.unencode_speed();
+        match field {
+            "vx" => self.encode_speed(parse(field, value)?, y),
+            "vy" => self.encode_speed(x, parse(field, value)?),
+            "max_speed" => self.max_speed = parse(field, value)?,
+            "acc" => self.acc = parse(field, value)?,
+            _ => return Err(no_field(field)),
+        }
+        Ok(())
+    }
+}
+
+impl Inspect for Collideable {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("width", self.col_box.width().to_string()),
+            ("height", self.col_box.height().to_string()),
+            ("friction", self.friction.to_string()),
+        ]
+    }
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let b = self.col_box;
+        match field {
+            "width" => self.col_box = Rect::new(b.x(), b.y(), parse(field, value)?, b.height()),
+            "height" => self.col_box = Rect::new(b.x(), b.y(), b.width(), parse(field, value)?),
+            "friction" => self.friction = parse(field, value)?,
+            _ => return Err(no_field(field)),
+        }
+        Ok(())
+    }
+}
+
+impl Inspect for Health {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("hp", self.hp.to_string()),
+            ("max_hp", self.max_hp.to_string()),
+        ]
+    }
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        match field {
+            "hp" => self.hp = parse::<u32>(field, value)?.min(self.max_hp),
+            "max_hp" => {
+                self.max_hp = parse::<u32>(field, value)?.max(1);
+                self.hp = self.hp.min(self.max_hp);
+            }
+            _ => return Err(no_field(field)),
+        }
+        Ok(())
+    }
+}
+
+impl Inspect for AI {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![("state", self.states[self.current].name.clone())]
+    }
+}
+
+impl Inspect for Script {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![("path", self.path.clone())]
+    }
+}
+
+impl Inspect for SpriteVec {}
+impl Inspect for ActionQueue {}
+impl Inspect for Inventory {}
+impl Inspect for Pickup {}
+impl Inspect for PlatformPath {}
+impl Inspect for Animation {}
+impl Inspect for Player {}
+impl Inspect for KeyboardControlled {}
+impl Inspect for GodMode {}
+
+trait InspectStorage {
+    fn name(&self) -> &'static str;
+    fn fields(&self, world: &World, entity: Entity) -> Option<Vec<(&'static str, String)>>;
+    fn set_field(
+        &self,
+        world: &World,
+        entity: Entity,
+        field: &str,
+        value: &str,
+    ) -> Result<(), String>;
+}
+
+struct Inspected<T> {
+    name: &'static str,
+    marker: PhantomData<T>,
+}
+
+impl<T: Component + Inspect + Clone> InspectStorage for Inspected<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn fields(&self, world: &World, entity: Entity) -> Option<Vec<(&'static str, String)>> {
+        world.read_storage::<T>().get(entity).map(|c| c.fields())
+    }
+
+    fn set_field(
+        &self,
+        world: &World,
+        entity: Entity,
+        field: &str,
+        value: &str,
+    ) -> Result<(), String> {
+        let mut storage = world.write_storage::<T>();
+        let mut component = storage
+            .get(entity)
+            .cloned()
+            .ok_or_else(|| format!("entity {} has no {}", entity.id(), self.name))?;
+        component.set_field(field, value)?;
+        storage
+            .insert(entity, component)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn inspected<T: Component + Inspect + Clone + 'static>(
+    name: &'static str,
+) -> Box<dyn InspectStorage> {
+    Box::new(Inspected::<T> {
+        name,
+        marker: PhantomData,
+    })
+}
+
+// Every component tools can see, in the order they are listed
+fn registry() -> Vec<Box<dyn InspectStorage>> {
+    vec![
+        inspected::<Position>("Position"),
+        inspected::<Velocity>("Velocity"),
+        inspected::<Collideable>("Collideable"),
+        inspected::<GravityAfflicted>("GravityAfflicted"),
+        inspected::<Health>("Health"),
+        inspected::<Damage>("Damage"),
+        inspected::<DecayLife>("DecayLife"),
+        inspected::<Invulnerability>("Invulnerability"),
+        inspected::<Team>("Team"),
+        inspected::<Score>("Score"),
+        inspected::<Weapon>("Weapon"),
+        inspected::<PlatformerController>("PlatformerController"),
+        inspected::<Mass>("Mass"),
+        inspected::<Friction>("Friction"),
+        inspected::<GravityScale>("GravityScale"),
+        inspected::<RigidBody>("RigidBody"),
+        inspected::<NetPlayer>("NetPlayer"),
+        inspected::<AI>("AI"),
+        inspected::<Script>("Script"),
+        inspected::<SpriteVec>("SpriteVec"),
+        inspected::<ActionQueue>("ActionQueue"),
+        inspected::<Inventory>("Inventory"),
+        inspected::<Pickup>("Pickup"),
+        inspected::<PlatformPath>("PlatformPath"),
+        inspected::<Animation>("Animation"),
+        inspected::<Player>("Player"),
+        inspected::<KeyboardControlled>("KeyboardControlled"),
+        inspected::<GodMode>("GodMode"),
+    ]
+}
+
+// Components of one entity with their fields
+pub fn inspect_entity(
+    world: &World,
+    entity: Entity,
+) -> Vec<(&'static str, Vec<(&'static str, String)>)> {
+    registry()
+        .iter()
+        .filter_map(|c| c.fields(world, entity).map(|fields| (c.name(), fields)))
+        .collect()
+}
+
+pub fn set_field(
+    world: &World,
+    entity: Entity,
+    component: &str,
+    field: &str,
+    value: &str,
+) -> Result<(), String> {
+    let registry = registry();
+    let storage = registry
+        .iter()
+        .find(|c| c.name().eq_ignore_ascii_case(component))
+        .ok_or_else(|| format!("unknown component `{}`", component))?;
+    storage.set_field(world, entity, field, value)
+}
+
+// Live entity with this id, ids are what tools show and take
+pub fn entity_by_id(world: &World, id: u32) -> Option<Entity> {
+    let entity = world.entities().entity(id);
+    if world.is_alive(entity) {
+        Some(entity)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,216 @@
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+
+use crate::entity_components::*;
+use crate::inspect::*;
+
+// Entities listed around the selected one
+const LISTED_ENTITIES: usize = 12;
+
+// One line of the inspector panel. The window draws them top to bottom and hands clicks
+// back by row, so what is shown and what a click hits can not drift apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum InspectorRow {
+    Text(String),
+    Entity {
+        entity: Entity,
+        selected: bool,
+        summary: String,
+    },
+    Component(&'static str),
+    Field {
+        component: &'static str,
+        field: &'static str,
+        value: String,
+        editing: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldEdit {
+    pub component: &'static str,
+    pub field: &'static str,
+    pub text: String,
+}
+
+// Entity inspector: pick an entity, see all of its components and change their fields while
+// the game runs, or pause it and step a tick at a time
+#[derive(Debug, Clone, Default)]
+pub struct Inspector {
+    pub open: bool,
+    pub paused: bool,
+    // Ticks asked for while paused, not run yet
+    pub steps: u32,
+    pub selected: Option<Entity>,
+    pub editing: Option<FieldEdit>,
+    // Outcome of the last edit
+    pub message: Option<String>,
+}
+
+impl Inspector {
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.steps = 0;
+    }
+
+    pub fn step(&mut self) {
+        if self.paused {
+            self.steps += 1;
+        }
+    }
+
+    // Steps to run this frame, at most limit
+    pub fn take_steps(&mut self, limit: u32) -> u32 {
+        let steps = self.steps.min(limit);
+        self.steps -= steps;
+        steps
+    }
+
+    pub fn select(&mut self, entity: Option<Entity>) {
+        self.selected = entity;
+        self.editing = None;
+        self.message = None;
+    }
+
+    // Previous or next entity by id, wrapping around
+    pub fn select_next(&mut self, world: &World, forward: bool) {
+        let entities: Vec<Entity> = world.entities().join().collect();
+        if entities.is_empty() {
+            return self.select(None);
+        }
+        let current = self
+            .selected
+            .and_then(|s| entities.iter().position(|e| *e == s));
+        let next = match (current, forward) {
+            (None, _) => 0,
+            (Some(i), true) => (i + 1) % entities.len(),
+            (Some(i), false) => (i + entities.len() - 1) % entities.len(),
+        };
+        self.select(Some(entities[next]));
+    }
+
+    pub fn rows(&self, world: &World) -> Vec<InspectorRow> {
+        let mut rows = vec![InspectorRow::Text(format!(
+            "inspector{}  [ ] select  P pause  N step",
+            if self.paused { " (paused)" } else { "" }
+        ))];
+
+        let entities: Vec<Entity> = world.entities().join().collect();
+        let at = self
+            .selected
+            .and_then(|s| entities.iter().position(|e| *e == s))
+            .unwrap_or(0);
+        let first = at
+            .saturating_sub(LISTED_ENTITIES / 2)
+            .min(entities.len().saturating_sub(LISTED_ENTITIES));
+        for entity in entities.iter().skip(first).take(LISTED_ENTITIES) {
+            let summary: Vec<&str> = inspect_entity(world, *entity)
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| *name != "Position")
+                .take(3)
+                .collect();
+            rows.push(InspectorRow::Entity {
+                entity: *entity,
+                selected: Some(*entity) == self.selected,
+                summary: summary.join(" "),
+            });
+        }
+        rows.push(InspectorRow::Text(format!("{} entities", entities.len())));
+
+        let selected = match self.selected {
+            Some(entity) if world.is_alive(entity) => entity,
+            Some(_) => {
+                rows.push(InspectorRow::Text(
+                    "the selected entity is gone".to_string(),
+                ));
+                return rows;
+            }
+            None => {
+                rows.push(InspectorRow::Text(
+                    "click an entity to inspect it".to_string(),
+                ));
+                return rows;
+            }
+        };
+        rows.push(InspectorRow::Text(format!("entity {}", selected.id())));
+        if let Some(message) = &self.message {
+            rows.push(InspectorRow::Text(message.clone()));
+        }
+        for (component, fields) in inspect_entity(world, selected) {
+            rows.push(InspectorRow::Component(component));
+            for (field, value) in fields {
+                let edit = self
+                    .editing
+                    .as_ref()
+                    .filter(|e| e.component == component && e.field == field);
+                rows.push(InspectorRow::Field {
+                    component,
+                    field,
+                    value: edit.map_or(value, |e| format!("{}_", e.text)),
+                    editing: edit.is_some(),
+                });
+            }
+        }
+        rows
+    }
+
+    // Selects a listed entity or starts editing a field
+    pub fn click_row(&mut self, world: &World, row: usize) {
+        match self.rows(world).into_iter().nth(row) {
+            Some(InspectorRow::Entity { entity, .. }) => self.select(Some(entity)),
+            Some(InspectorRow::Field {
+                component,
+                field,
+                value,
+                ..
+            }) => {
+                self.editing = Some(FieldEdit {
+                    component,
+                    field,
+                    text: value,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    pub fn commit_edit(&mut self, world: &World) {
+        let (edit, entity) = match (self.editing.take(), self.selected) {
+            (Some(edit), Some(entity)) => (edit, entity),
+            _ => return,
+        };
+        self.message = Some(
+            match set_field(world, entity, edit.component, edit.field, edit.text.trim()) {
+                Ok(()) => format!("{}.{} = {}", edit.component, edit.field, edit.text.trim()),
+                Err(e) => format!("error: {}", e),
+            },
+        );
+    }
+}
+
+// World space box an entity takes up: its collider, else its pickup area, else its sprite
+pub fn bounds(world: &World, entity: Entity) -> Option<Rect> {
+    let pos = world.read_storage::<Position>().get(entity)?.point;
+    let (width, height) = if let Some(coll) = world.read_storage::<Collideable>().get(entity) {
+        (coll.col_box.width(), coll.col_box.height())
+    } else if let Some(pickup) = world.read_storage::<Pickup>().get(entity) {
+        (pickup.area.width(), pickup.area.height())
+    } else {
+        let sprites = world.read_storage::<SpriteVec>();
+        let region = sprites.get(entity)?.sprite_vec.first()?.region;
+        (region.width(), region.height())
+    };
+    Some(Rect::from_center(pos, width, height))
+}
+
+// Smallest entity covering a world point, so pickups win over the ground they lie on
+pub fn pick(world: &World, point: Point) -> Option<Entity> {
+    world
+        .entities()
+        .join()
+        .filter_map(|entity| bounds(world, entity).map(|rect| (rect, entity)))
+        .filter(|(rect, _)| rect.contains_point(point))
+        .min_by_key(|(rect, _)| rect.width() * rect.height())
+        .map(|(_, entity)| entity)
+}
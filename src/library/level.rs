@@ -0,0 +1,436 @@
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+use std::fmt;
+
+use crate::checksum::StateHasher;
+use crate::entity_components::*;
+use crate::entity_platform::*;
+use crate::entity_script::Script;
+use crate::prefabs::*;
+use crate::rect;
+
+pub const DEFAULT_LEVEL: &str = "assets/levels/default.level";
+
+// Width and height of a prefab's handle in the editor, the prefab itself may be bigger
+pub const PREFAB_HANDLE: u32 = 24;
+
+// One line of a level file, in world coordinates. Rects have x,y at their top left corner,
+// points like a prefab's position or a platform's `to` are centres
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelItem {
+    Block {
+        rect: Rect,
+        friction: f32,
+        // Drawn with the block texture instead of being invisible terrain
+        textured: bool,
+    },
+    // Textured block moving back and forth between where it starts and `to`
+    Platform {
+        rect: Rect,
+        to: Point,
+        speed: f32,
+    },
+    Prefab {
+        name: String,
+        point: Point,
+        script: Option<String>,
+    },
+}
+
+impl LevelItem {
+    pub fn bounds(&self) -> Rect {
+        match self {
+            LevelItem::Block { rect, .. } | LevelItem::Platform { rect, .. } => *rect,
+            LevelItem::Prefab { point, .. } => {
+                Rect::from_center(*point, PREFAB_HANDLE, PREFAB_HANDLE)
+            }
+        }
+    }
+
+    pub fn move_to(&mut self, top_left: Point) {
+        match self {
+            LevelItem::Block { rect, .. } => rect.reposition(top_left),
+            LevelItem::Platform { rect, to, .. } => {
+                *to += top_left - rect.top_left();
+                rect.reposition(top_left);
+            }
+            LevelItem::Prefab { point, .. } => {
+                *point = top_left + Point::new(PREFAB_HANDLE as i32 / 2, PREFAB_HANDLE as i32 / 2)
+            }
+        }
+    }
+
+    pub fn resizable(&self) -> bool {
+        !matches!(self, LevelItem::Prefab { .. })
+    }
+}
+
+// Where the player starts and everything else the world is made of
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub spawn: Point,
+    pub items: Vec<LevelItem>,
+}
+
+// Names the level a recording or netplay session was made on. Only the checksum has to
+// match, the path is what gets loaded and shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelId {
+    pub path: String,
+    pub checksum: u64,
+}
+
+impl LevelId {
+    pub fn new(path: &str, level: &Level) -> LevelId {
+        LevelId {
+            path: path.to_string(),
+            checksum: level.checksum(),
+        }
+    }
+}
+
+impl fmt::Display for LevelId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:016x})", self.path, self.checksum)
+    }
+}
+
+impl Level {
+    // Hash of what the level holds, comments and formatting in the file do not count
+    pub fn checksum(&self) -> u64 {
+        let mut h = StateHasher::default();
+        h.write_bytes(self.to_string().as_bytes());
+        h.finish()
+    }
+
+    pub fn load(path: &str) -> Result<Level, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Level::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_string()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(source: &str) -> Result<Level, String> {
+        let mut level = Level {
+            spawn: Point::new(0, 0),
+            items: Vec::new(),
+        };
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line = match line.split('#').next() {
+                Some(line) => line.trim(),
+                None => continue,
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", line_no + 1, msg);
+
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("spawn") => level.spawn = parse_point(words.next()).map_err(err)?,
+                Some("block") => {
+                    let rect = parse_rect(words.next()).map_err(err)?;
+                    let mut friction = 1.0;
+                    let mut textured = false;
+                    while let Some(word) = words.next() {
+                        match word {
+                            "friction" => friction = parse_number(words.next()).map_err(err)?,
+                            "textured" => textured = true,
+                            other => return Err(err(format!("unknown block option `{}`", other))),
+                        }
+                    }
+                    level.items.push(LevelItem::Block {
+                        rect,
+                        friction,
+                        textured,
+                    });
+                }
+                Some("platform") => {
+                    let rect = parse_rect(words.next()).map_err(err)?;
+                    if words.next() != Some("to") {
+                        return Err(err("expected `to x,y` after the platform".to_string()));
+                    }
+                    let to = parse_point(words.next()).map_err(err)?;
+                    if words.next() != Some("speed") {
+                        return Err(err("expected `speed` after the destination".to_string()));
+                    }
+                    let speed = parse_number(words.next()).map_err(err)?;
+                    level.items.push(LevelItem::Platform { rect, to, speed });
+                }
+                Some("prefab") => {
+                    let name = words
+                        .next()
+                        .ok_or_else(|| err("missing prefab name".to_string()))?;
+                    if !PREFAB_NAMES.contains(&name) {
+                        return Err(err(format!("unknown prefab `{}`", name)));
+                    }
+                    let point = parse_point(words.next()).map_err(err)?;
+                    let script = match words.next() {
+                        Some("script") => Some(
+                            words
+                                .next()
+                                .ok_or_else(|| err("missing script path".to_string()))?
+                                .to_string(),
+                        ),
+                        Some(other) => {
+                            return Err(err(format!("unknown prefab option `{}`", other)))
+                        }
+                        None => None,
+                    };
+                    level.items.push(LevelItem::Prefab {
+                        name: name.to_string(),
+                        point,
+                        script,
+                    });
+                }
+                Some(other) => return Err(err(format!("unknown keyword `{}`", other))),
+                None => {}
+            }
+        }
+        Ok(level)
+    }
+
+    // Creates every item's entity. Players are up to the caller, they start around `spawn`
+    pub fn build(&self, world: &mut World) -> Result<(), String> {
+        for item in self.items.iter() {
+            match item {
+                LevelItem::Block {
+                    rect,
+                    friction,
+                    textured,
+                } => {
+                    let mut builder = world
+                        .create_entity()
+                        .with(Position {
+                            point: rect.center(),
+                        })
+                        .with(Collideable {
+                            col_box: rect!(0, 0, rect.width(), rect.height()),
+                            friction: *friction,
+                        });
+                    if *textured {
+                        builder = builder.with(block_sprite(rect));
+                    }
+                    builder.build();
+                }
+                LevelItem::Platform { rect, to, speed } => {
+                    world
+                        .create_entity()
+                        .with(Position {
+                            point: rect.center(),
+                        })
+                        .with(block_sprite(rect))
+                        .with(Collideable {
+                            col_box: rect!(0, 0, rect.width(), rect.height()),
+                            friction: 1.0,
+                        })
+                        .with(PlatformPath::new(
+                            vec![rect.center(), *to],
+                            *speed,
+                            Easing::EaseInOut,
+                            PathMode::PingPong,
+                        ))
+                        .build();
+                }
+                LevelItem::Prefab {
+                    name,
+                    point,
+                    script,
+                } => {
+                    let prefab = world.read_resource::<PrefabLibrary>().get(name)?;
+                    let entity = prefab.build(world.create_entity(), *point);
+                    if let Some(path) = script {
+                        world
+                            .write_storage::<Script>()
+                            .insert(entity, Script::new(path))
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Writes the same format parse reads
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "spawn {},{}", self.spawn.x(), self.spawn.y())?;
+        for item in self.items.iter() {
+            match item {
+                LevelItem::Block {
+                    rect,
+                    friction,
+                    textured,
+                } => {
+                    write!(f, "block {}", RectWords(rect))?;
+                    if *friction != 1.0 {
+                        write!(f, " friction {}", friction)?;
+                    }
+                    if *textured {
+                        write!(f, " textured")?;
+                    }
+                    writeln!(f)?;
+                }
+                LevelItem::Platform { rect, to, speed } => writeln!(
+                    f,
+                    "platform {} to {},{} speed {}",
+                    RectWords(rect),
+                    to.x(),
+                    to.y(),
+                    speed
+                )?,
+                LevelItem::Prefab {
+                    name,
+                    point,
+                    script,
+                } => {
+                    write!(f, "prefab {} {},{}", name, point.x(), point.y())?;
+                    if let Some(path) = script {
+                        write!(f, " script {}", path)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RectWords<'a>(&'a Rect);
+
+impl fmt::Display for RectWords<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = self.0;
+        write!(f, "{},{},{},{}", r.x(), r.y(), r.width(), r.height())
+    }
+}
+
+fn block_sprite(rect: &Rect) -> SpriteVec {
+    SpriteVec {
+        sprite_vec: vec![Sprite {
+            spritesheet: 2,
+            region: rect!(0, 0, rect.width(), rect.height()),
+            rotation: None,
+        }],
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<&str>) -> Result<T, String> {
+    let word = word.ok_or_else(|| "missing number".to_string())?;
+    word.parse::<T>()
+        .map_err(|_| format!("`{}` is not a valid number", word))
+}
+
+fn parse_point(word: Option<&str>) -> Result<Point, String> {
+    let word = word.ok_or_else(|| "missing x,y point".to_string())?;
+    match word.split(',').collect::<Vec<&str>>()[..] {
+        [x, y] => Ok(Point::new(parse_number(Some(x))?, parse_number(Some(y))?)),
+        _ => Err(format!("`{}` is not an x,y point", word)),
+    }
+}
+
+fn parse_rect(word: Option<&str>) -> Result<Rect, String> {
+    let word = word.ok_or_else(|| "missing x,y,w,h box".to_string())?;
+    let parts: Vec<&str> = word.split(',').collect();
+    if parts.len() != 4 {
+        return Err(format!("`{}` is not an x,y,w,h box", word));
+    }
+    Ok(Rect::new(
+        parse_number(Some(parts[0]))?,
+        parse_number(Some(parts[1]))?,
+        parse_number(Some(parts[2]))?,
+        parse_number(Some(parts[3]))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_item() {
+        let level = Level::parse(
+            "# comment\n\
+             spawn 10,-20\n\
+             block 0,100,200,50 friction 0.2 textured # ice\n\
+             \n\
+             platform -100,54,80,12 to 150,60 speed 1.5\n\
+             prefab crate 330,80\n\
+             prefab grunt 200,0 script assets/scripts/grunt.rhai\n",
+        )
+        .unwrap();
+        assert_eq!(level.spawn, Point::new(10, -20));
+        assert_eq!(
+            level.items,
+            vec![
+                LevelItem::Block {
+                    rect: Rect::new(0, 100, 200, 50),
+                    friction: 0.2,
+                    textured: true,
+                },
+                LevelItem::Platform {
+                    rect: Rect::new(-100, 54, 80, 12),
+                    to: Point::new(150, 60),
+                    speed: 1.5,
+                },
+                LevelItem::Prefab {
+                    name: "crate".to_string(),
+                    point: Point::new(330, 80),
+                    script: None,
+                },
+                LevelItem::Prefab {
+                    name: "grunt".to_string(),
+                    point: Point::new(200, 0),
+                    script: Some("assets/scripts/grunt.rhai".to_string()),
+                },
+            ]
+        );
+
+        // Written back out it parses to the same level, and so hashes the same
+        let again = Level::parse(&level.to_string()).unwrap();
+        assert_eq!(again, level);
+        assert_eq!(again.checksum(), level.checksum());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = |source: &str| Level::parse(source).unwrap_err();
+        assert_eq!(err("spawn 0,0\nwall 1,2"), "line 2: unknown keyword `wall`");
+        assert_eq!(err("spawn 0"), "line 1: `0` is not an x,y point");
+        assert_eq!(
+            err("block 0,0,10"),
+            "line 1: `0,0,10` is not an x,y,w,h box"
+        );
+        assert_eq!(err("block 0,0,10,-5"), "line 1: `-5` is not a valid number");
+        assert_eq!(
+            err("block 0,0,10,10 bouncy"),
+            "line 1: unknown block option `bouncy`"
+        );
+        assert_eq!(
+            err("platform 0,0,10,10 150,60 speed 1"),
+            "line 1: expected `to x,y` after the platform"
+        );
+        assert_eq!(err("prefab dragon 0,0"), "line 1: unknown prefab `dragon`");
+        assert_eq!(
+            err("prefab crate 0,0 script"),
+            "line 1: missing script path"
+        );
+    }
+
+    #[test]
+    fn loads_the_default_level() {
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        assert_eq!(level.spawn, Point::new(0, 0));
+        assert!(level
+            .items
+            .iter()
+            .any(|item| matches!(item, LevelItem::Platform { .. })));
+
+        let err = Level::load("assets/levels/missing.level").unwrap_err();
+        assert!(err.starts_with("assets/levels/missing.level: "), "{}", err);
+    }
+}
@@ -1,3 +1,25 @@
+pub mod checksum;
 pub mod commands;
+pub mod console;
+pub mod contacts;
+pub mod debugdraw;
+pub mod editor;
+pub mod events;
 pub mod globalcomponents;
+pub mod inspect;
+pub mod inspector;
+pub mod level;
 pub mod macros;
+pub mod navigation;
+pub mod netclient;
+pub mod netcode;
+pub mod netserver;
+pub mod netstate;
+pub mod particles;
+pub mod prefabs;
+pub mod profiler;
+pub mod replay;
+pub mod rng;
+pub mod rollback;
+pub mod scripting;
+pub mod snapshot;
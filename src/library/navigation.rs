@@ -0,0 +1,491 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+use sdl2::rect::{Point, Rect};
+
+pub const DEFAULT_CELL_SIZE: i32 = 16;
+
+const STRAIGHT_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+const JUMP_PENALTY: i32 = 20;
+
+type Cell = (i32, i32);
+
+// How far a platformer agent can get off the ground, in pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JumpProfile {
+    pub height: i32,
+    pub reach: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NavLink {
+    Walk,
+    Fall,
+    Jump,
+}
+
+// One waypoint of a platformer path and how to get there from the previous one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NavStep {
+    pub point: Point,
+    pub link: NavLink,
+}
+
+// Walkable grid over the static terrain, rebuilt by NavSys whenever it is marked dirty
+#[derive(Debug, Clone)]
+pub struct NavGrid {
+    pub cell_size: i32,
+    pub origin: Point,
+    pub width: i32,
+    pub height: i32,
+    pub dirty: bool,
+    // Counts rebuilds, paths planned on an older grid are planned again
+    pub version: u64,
+    solid: Vec<bool>,
+    // What the grid was last built from
+    terrain: Vec<Rect>,
+}
+
+impl Default for NavGrid {
+    fn default() -> Self {
+        NavGrid {
+            cell_size: DEFAULT_CELL_SIZE,
+            origin: Point::new(0, 0),
+            width: 0,
+            height: 0,
+            dirty: true,
+            version: 0,
+            solid: Vec::new(),
+            terrain: Vec::new(),
+        }
+    }
+}
+
+impl NavGrid {
+    // Terrain spawned, moved or resized since the last rebuild, by the console or a script
+    pub fn terrain_changed(&self, terrain: &[Rect]) -> bool {
+        self.terrain != terrain
+    }
+
+    pub fn rebuild(&mut self, bounds: Rect, terrain: &[Rect]) {
+        self.origin = bounds.top_left();
+        self.width = (bounds.width() as i32 + self.cell_size - 1) / self.cell_size;
+        self.height = (bounds.height() as i32 + self.cell_size - 1) / self.cell_size;
+        self.solid = vec![false; (self.width * self.height) as usize];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = Rect::new(
+                    self.origin.x() + x * self.cell_size,
+                    self.origin.y() + y * self.cell_size,
+                    self.cell_size as u32,
+                    self.cell_size as u32,
+                );
+                self.solid[(y * self.width + x) as usize] =
+                    terrain.iter().any(|r| r.has_intersection(cell));
+            }
+        }
+
+        self.terrain = terrain.to_vec();
+        self.dirty = false;
+        self.version += 1;
+    }
+
+    pub fn cell_of(&self, point: Point) -> Option<Cell> {
+        let x = (point.x() - self.origin.x()).div_euclid(self.cell_size);
+        let y = (point.y() - self.origin.y()).div_euclid(self.cell_size);
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    // Cell a path from this point starts in, walkers start on the ground below it
+    pub fn path_cell(&self, point: Point, walking: bool) -> Option<Cell> {
+        let cell = self.cell_of(point)?;
+        if walking {
+            self.ground_below(cell)
+        } else {
+            Some(cell)
+        }
+    }
+
+    pub fn center_of(&self, (x, y): Cell) -> Point {
+        Point::new(
+            self.origin.x() + x * self.cell_size + self.cell_size / 2,
+            self.origin.y() + y * self.cell_size + self.cell_size / 2,
+        )
+    }
+
+    pub fn is_walkable(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return false;
+        }
+        !self.solid[(y * self.width + x) as usize]
+    }
+
+    // Free cell resting directly on solid ground
+    pub fn is_standable(&self, x: i32, y: i32) -> bool {
+        self.is_walkable(x, y) && !self.is_walkable(x, y + 1) && y + 1 < self.height
+    }
+
+    fn neighbours(&self, (x, y): Cell) -> Vec<(Cell, i32)> {
+        let mut out = Vec::with_capacity(8);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx == 0 && dy == 0) || !self.can_step(x, y, dx, dy) {
+                    continue;
+                }
+                let cost = if dx != 0 && dy != 0 {
+                    DIAGONAL_COST
+                } else {
+                    STRAIGHT_COST
+                };
+                out.push(((x + dx, y + dy), cost));
+            }
+        }
+        out
+    }
+
+    // Diagonal steps may not cut the corner of a solid cell
+    fn can_step(&self, x: i32, y: i32, dx: i32, dy: i32) -> bool {
+        if !self.is_walkable(x + dx, y + dy) {
+            return false;
+        }
+        dx == 0 || dy == 0 || (self.is_walkable(x + dx, y) && self.is_walkable(x, y + dy))
+    }
+
+    // 8-directional A* between two world points, returns cell centers from start to goal
+    pub fn find_path(&self, start: Point, goal: Point) -> Option<Vec<Point>> {
+        let start = self.cell_of(start)?;
+        let goal = self.cell_of(goal)?;
+        let cells = astar(start, goal, |c| self.neighbours(c), |c| octile(c, goal))?;
+        Some(cells.into_iter().map(|c| self.center_of(c)).collect())
+    }
+
+    // Same result as find_path but only expands jump points, returns the jump points
+    pub fn find_path_jps(&self, start: Point, goal: Point) -> Option<Vec<Point>> {
+        let start = self.cell_of(start)?;
+        let goal = self.cell_of(goal)?;
+        let successors = |(x, y): Cell| {
+            let mut out = Vec::new();
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    if dx == 0 && dy == 0 {
+                        continue;
+                    }
+                    if let Some(jp) = self.jump(x, y, dx, dy, goal) {
+                        out.push((jp, octile((x, y), jp)));
+                    }
+                }
+            }
+            out
+        };
+        let cells = astar(start, goal, successors, |c| octile(c, goal))?;
+        Some(cells.into_iter().map(|c| self.center_of(c)).collect())
+    }
+
+    fn jump(&self, x: i32, y: i32, dx: i32, dy: i32, goal: Cell) -> Option<Cell> {
+        let (mut x, mut y) = (x, y);
+        loop {
+            if !self.can_step(x, y, dx, dy) {
+                return None;
+            }
+            let (nx, ny) = (x + dx, y + dy);
+            if (nx, ny) == goal {
+                return Some((nx, ny));
+            }
+
+            if dx != 0 && dy != 0 {
+                if self.jump(nx, ny, dx, 0, goal).is_some()
+                    || self.jump(nx, ny, 0, dy, goal).is_some()
+                {
+                    return Some((nx, ny));
+                }
+            } else if dx != 0 {
+                // A wall that ends opens up a turn we could not take before
+                if (self.is_walkable(nx, ny + 1) && !self.is_walkable(x, y + 1))
+                    || (self.is_walkable(nx, ny - 1) && !self.is_walkable(x, y - 1))
+                {
+                    return Some((nx, ny));
+                }
+            } else if (self.is_walkable(nx + 1, ny) && !self.is_walkable(x + 1, y))
+                || (self.is_walkable(nx - 1, ny) && !self.is_walkable(x - 1, y))
+            {
+                return Some((nx, ny));
+            }
+
+            x = nx;
+            y = ny;
+        }
+    }
+
+    // Drops a cell onto the ground below it, agents are centered above the floor
+    fn ground_below(&self, (x, y): Cell) -> Option<Cell> {
+        (y..self.height)
+            .take_while(|y| self.is_walkable(x, *y))
+            .find(|y| self.is_standable(x, *y))
+            .map(|y| (x, y))
+    }
+
+    fn platform_links(&self, (x, y): Cell, jump: JumpProfile) -> Vec<((Cell, NavLink), i32)> {
+        let mut out = Vec::new();
+
+        for dx in [-1, 1] {
+            if self.is_standable(x + dx, y) {
+                out.push((((x + dx, y), NavLink::Walk), STRAIGHT_COST));
+            } else if self.is_walkable(x + dx, y) {
+                if let Some(landing) = self.ground_below((x + dx, y)) {
+                    out.push((
+                        (landing, NavLink::Fall),
+                        STRAIGHT_COST * (landing.1 - y + 1),
+                    ));
+                }
+            }
+        }
+
+        let rise = jump.height / self.cell_size;
+        let reach = jump.reach / self.cell_size;
+        for ty in (y - rise)..=(y + rise) {
+            for tx in (x - reach)..=(x + reach) {
+                if (tx - x).abs() <= 1 && ty == y {
+                    continue;
+                }
+                if self.is_standable(tx, ty) && self.jump_clear((x, y), (tx, ty)) {
+                    let cost = STRAIGHT_COST * ((tx - x).abs() + (ty - y).abs()) + JUMP_PENALTY;
+                    out.push((((tx, ty), NavLink::Jump), cost));
+                }
+            }
+        }
+
+        out
+    }
+
+    // Approximates the arc as up, across one cell above the higher end, then down
+    fn jump_clear(&self, (x, y): Cell, (tx, ty): Cell) -> bool {
+        let peak = y.min(ty) - 1;
+        (peak..=y).all(|cy| self.is_walkable(x, cy))
+            && (x.min(tx)..=x.max(tx)).all(|cx| self.is_walkable(cx, peak))
+            && (peak..=ty).all(|cy| self.is_walkable(tx, cy))
+    }
+
+    // A* over standable cells linked by walking, falling off ledges and jumping
+    pub fn find_platform_path(
+        &self,
+        start: Point,
+        goal: Point,
+        jump: JumpProfile,
+    ) -> Option<Vec<NavStep>> {
+        let start = self.ground_below(self.cell_of(start)?)?;
+        let goal = self.ground_below(self.cell_of(goal)?)?;
+
+        let nodes = astar(
+            (start, NavLink::Walk),
+            (goal, NavLink::Walk),
+            |(cell, _)| self.platform_links(cell, jump),
+            |(cell, _)| manhattan(cell, goal),
+        )?;
+
+        Some(
+            nodes
+                .into_iter()
+                .map(|(cell, link)| NavStep {
+                    point: self.center_of(cell),
+                    link,
+                })
+                .collect(),
+        )
+    }
+}
+
+fn octile(a: Cell, b: Cell) -> i32 {
+    let dx = (a.0 - b.0).abs();
+    let dy = (a.1 - b.1).abs();
+    STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+fn manhattan(a: Cell, b: Cell) -> i32 {
+    STRAIGHT_COST * ((a.0 - b.0).abs() + (a.1 - b.1).abs())
+}
+
+// Platformer nodes carry the link used to reach them, the goal matches on cell alone
+trait NavNode: Copy + Eq + Hash + Ord {
+    fn same_place(&self, other: &Self) -> bool;
+}
+
+impl NavNode for Cell {
+    fn same_place(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl NavNode for (Cell, NavLink) {
+    fn same_place(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+fn astar<N, FN, FH>(start: N, goal: N, mut neighbours: FN, heuristic: FH) -> Option<Vec<N>>
+where
+    N: NavNode,
+    FN: FnMut(N) -> Vec<(N, i32)>,
+    FH: Fn(N) -> i32,
+{
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<N, N> = HashMap::new();
+    let mut cost: HashMap<N, i32> = HashMap::new();
+
+    open.push(Reverse((heuristic(start), start)));
+    cost.insert(start, 0);
+
+    while let Some(Reverse((_, node))) = open.pop() {
+        if node.same_place(&goal) {
+            let mut path = vec![node];
+            let mut cur = node;
+            while let Some(prev) = came_from.get(&cur) {
+                path.push(*prev);
+                cur = *prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let node_cost = cost[&node];
+        for (next, step) in neighbours(node) {
+            let next_cost = node_cost + step;
+            if cost.get(&next).is_none_or(|c| next_cost < *c) {
+                cost.insert(next, next_cost);
+                came_from.insert(next, node);
+                open.push(Reverse((next_cost + heuristic(next), next)));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One character per cell, # is solid
+    fn grid(rows: &[&str]) -> NavGrid {
+        let size = DEFAULT_CELL_SIZE;
+        let mut terrain = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    terrain.push(Rect::new(
+                        x as i32 * size,
+                        y as i32 * size,
+                        size as u32,
+                        size as u32,
+                    ));
+                }
+            }
+        }
+        let bounds = Rect::new(
+            0,
+            0,
+            (rows[0].len() as i32 * size) as u32,
+            (rows.len() as i32 * size) as u32,
+        );
+        let mut nav = NavGrid::default();
+        nav.rebuild(bounds, &terrain);
+        nav
+    }
+
+    fn at(x: i32, y: i32) -> Point {
+        NavGrid::default().center_of((x, y))
+    }
+
+    fn cost(nav: &NavGrid, path: &[Point]) -> i32 {
+        path.windows(2)
+            .map(|w| octile(nav.cell_of(w[0]).unwrap(), nav.cell_of(w[1]).unwrap()))
+            .sum()
+    }
+
+    #[test]
+    fn path_goes_through_the_gap() {
+        let nav = grid(&[
+            "....#....", //
+            "....#....",
+            "....#....",
+            ".........",
+            "....#....",
+        ]);
+        let path = nav.find_path(at(0, 0), at(8, 0)).unwrap();
+        assert_eq!(path.first(), Some(&at(0, 0)));
+        assert_eq!(path.last(), Some(&at(8, 0)));
+        assert!(path.contains(&at(4, 3)));
+        assert!(path.iter().all(|p| {
+            let (x, y) = nav.cell_of(*p).unwrap();
+            nav.is_walkable(x, y)
+        }));
+    }
+
+    #[test]
+    fn blocked_corridor_has_no_path() {
+        let nav = grid(&[
+            "....#....", //
+            "....#....",
+            "....#....",
+            "....#....",
+        ]);
+        assert_eq!(nav.find_path(at(0, 0), at(8, 0)), None);
+        assert_eq!(nav.find_path_jps(at(0, 0), at(8, 0)), None);
+        // Outside the grid
+        assert_eq!(nav.find_path(at(0, 0), at(20, 0)), None);
+    }
+
+    #[test]
+    fn jump_points_cost_the_same_as_astar() {
+        let nav = grid(&[
+            "...........", //
+            ".####..#...",
+            "....#..#.#.",
+            "..#.#....#.",
+            "..#...####.",
+            "..#........",
+        ]);
+        for goal in [at(10, 5), at(3, 2), at(8, 2), at(6, 0)] {
+            let astar = nav.find_path(at(0, 5), goal).unwrap();
+            let jps = nav.find_path_jps(at(0, 5), goal).unwrap();
+            assert_eq!(cost(&nav, &astar), cost(&nav, &jps), "goal {:?}", goal);
+            assert!(jps.len() <= astar.len());
+        }
+    }
+
+    #[test]
+    fn jump_link_needs_the_height() {
+        let nav = grid(&[
+            "..........", //
+            "..........",
+            "......####",
+            "..........",
+            "..........",
+            "##########",
+        ]);
+        // The ledge is three cells above the floor
+        let high = JumpProfile {
+            height: 3 * DEFAULT_CELL_SIZE,
+            reach: 4 * DEFAULT_CELL_SIZE,
+        };
+        let path = nav.find_platform_path(at(1, 4), at(8, 1), high).unwrap();
+        assert_eq!(path.first().map(|s| s.point), Some(at(1, 4)));
+        assert_eq!(path.last().map(|s| s.point), Some(at(8, 1)));
+        assert_eq!(path.iter().filter(|s| s.link == NavLink::Jump).count(), 1);
+
+        let short = JumpProfile {
+            height: 3 * DEFAULT_CELL_SIZE - 1,
+            ..high
+        };
+        assert_eq!(nav.find_platform_path(at(1, 4), at(8, 1), short), None);
+
+        // Stepping off the ledge needs no jump at all
+        let down = nav.find_platform_path(at(8, 1), at(1, 4), short).unwrap();
+        assert!(down.iter().all(|s| s.link != NavLink::Jump));
+    }
+}
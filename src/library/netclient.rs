@@ -0,0 +1,399 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::time::Instant;
+
+use sdl2::rect::Point;
+use specs::prelude::*;
+
+use crate::collisionsys::CollisionSys;
+use crate::commands::*;
+use crate::debugdraw::DebugDraw;
+use crate::entity_components::*;
+use crate::entity_flags::*;
+use crate::globalcomponents::*;
+use crate::keyboard::Keyboard;
+use crate::netcode::*;
+use crate::netserver::*;
+use crate::netstate::*;
+use crate::physics::Physics;
+use crate::profiler::*;
+use crate::simulation::{build_dispatcher, spawn_player};
+use crate::systems::controllersys::ControllerSys;
+use crate::systems::gravitysys::GravitySys;
+
+// Remote entities are drawn this many ticks in the past, between two snapshots
+const INTERPOLATION_DELAY: f64 = (SNAPSHOT_INTERVAL * 2) as f64;
+// Further behind than this and the render clock jumps instead of catching up smoothly
+const MAX_INTERPOLATION_LAG: f64 = 30.0;
+const HELLO_INTERVAL: u64 = 30;
+// Inputs kept while the server does not answer, older ones are given up on
+const MAX_PENDING: usize = 120;
+// Most inputs one message carries, the rest follow in the next one
+const MAX_INPUTS_PER_MESSAGE: usize = 64;
+
+// What happened to the client, for the binary running it to log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Joined { slot: usize },
+}
+
+impl fmt::Display for ClientEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientEvent::Joined { slot } => write!(f, "joined as player {}", slot),
+        }
+    }
+}
+
+// Client of a NetServer. The world only holds what the server sends, drawn slightly in the
+// past, plus the player's own entity which is predicted from local input right away and
+// corrected whenever the server disagrees
+pub struct NetClient<'a, 'b, T: Transport> {
+    pub world: World,
+    // Only the systems that move the local player
+    prediction: Dispatcher<'a, 'b>,
+    transport: T,
+    pub slot: Option<usize>,
+    player: Entity,
+    // Server id of the entity the player controls
+    owner: Option<u64>,
+    seq: u64,
+    // Inputs the server has not simulated yet, replayed on top of every correction
+    pending: VecDeque<(u64, TickInput)>,
+    snapshots: BTreeMap<u64, NetSnapshot>,
+    mirrored: HashMap<u64, Entity>,
+    render_tick: f64,
+    ticks: u64,
+    events: Vec<ClientEvent>,
+    pub corrections: u64,
+    // Packets from the server that could not be read and were skipped
+    pub dropped: u64,
+}
+
+impl<'a, 'b, T: Transport> NetClient<'a, 'b, T> {
+    pub fn new(transport: T) -> Result<NetClient<'a, 'b, T>, String> {
+        let mut world = World::new();
+        let profiler = Profiler::default();
+        // Registers every component and resource the server side uses
+        build_dispatcher(&profiler).setup(&mut world);
+        let mut prediction = DispatcherBuilder::new()
+            .with_timed(&profiler, Keyboard, "Keyboard", &[])
+            .with_timed(&profiler, ControllerSys, "ControllerSys", &["Keyboard"])
+            .with_timed(&profiler, GravitySys, "GravitySys", &["ControllerSys"])
+            .with_timed(&profiler, CollisionSys, "CollisionSys", &["GravitySys"])
+            .with_timed(&profiler, Physics, "Physics", &["CollisionSys"])
+            .build();
+        prediction.setup(&mut world);
+        world.insert::<Option<MovementCommand>>(None);
+        world.insert::<Vec<ActionCommand>>(Vec::new());
+        world.insert(Camera::default());
+        world.insert(AudioVolume::default());
+        world.insert(DebugDraw::default());
+        world.insert(profiler);
+
+        let player = spawn_player(&mut world, Point::new(0, 0), 0);
+        world
+            .write_storage::<KeyboardControlled>()
+            .insert(player, KeyboardControlled)
+            .map_err(|e| e.to_string())?;
+
+        Ok(NetClient {
+            world,
+            prediction,
+            transport,
+            slot: None,
+            player,
+            owner: None,
+            seq: 0,
+            pending: VecDeque::new(),
+            snapshots: BTreeMap::new(),
+            mirrored: HashMap::new(),
+            render_tick: 0.0,
+            ticks: 0,
+            events: Vec::new(),
+            corrections: 0,
+            dropped: 0,
+        })
+    }
+
+    pub fn player_position(&self) -> Option<Point> {
+        self.world
+            .read_storage::<Position>()
+            .get(self.player)
+            .map(|p| p.point)
+    }
+
+    pub fn take_events(&mut self) -> Vec<ClientEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Newest tick the server told us about
+    pub fn server_tick(&self) -> Option<u64> {
+        self.snapshots.keys().next_back().copied()
+    }
+
+    pub fn tick(&mut self, input: &TickInput) -> Result<(), String> {
+        self.ticks += 1;
+        self.receive()?;
+
+        if self.slot.is_none() {
+            if self.ticks % HELLO_INTERVAL == 1 {
+                self.transport.send(&[HELLO_MESSAGE])?;
+            }
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        self.world.write_resource::<DebugDraw>().clear();
+        self.seq += 1;
+        self.pending.push_back((self.seq, input.clone()));
+        if self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.predict(input);
+        self.send_inputs()?;
+        self.interpolate();
+        // Corrections replayed since the last tick count towards this one
+        let entities = self.world.entities().join().count();
+        self.world
+            .read_resource::<Profiler>()
+            .end_tick(self.ticks, start, entities);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<(), String> {
+        while let Some(packet) = self.transport.receive()? {
+            // A datagram that can not be read is dropped, the next snapshot replaces it
+            if self.read_packet(&packet).is_err() {
+                self.dropped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_packet(&mut self, packet: &[u8]) -> Result<(), String> {
+        let mut r = Reader::new(packet);
+        match r.u8()? {
+            WELCOME_MESSAGE => {
+                let slot = r.u8()? as usize;
+                if self.slot.is_none() {
+                    self.events.push(ClientEvent::Joined { slot });
+                }
+                self.slot = Some(slot);
+            }
+            SNAPSHOT_MESSAGE => self.read_snapshot(&mut r)?,
+            other => return Err(format!("unknown message {}", other)),
+        }
+        Ok(())
+    }
+
+    fn read_snapshot(&mut self, r: &mut Reader) -> Result<(), String> {
+        let tick = r.u64()?;
+        let baseline = r.u64()?;
+        // Arrived out of order, a newer one already went through
+        if self.server_tick().is_some_and(|latest| tick <= latest) {
+            return Ok(());
+        }
+        let baseline = match baseline {
+            NO_TICK => None,
+            baseline => match self.snapshots.get(&baseline) {
+                Some(snapshot) => Some(snapshot),
+                // Already forgotten, the next delta will be against something newer
+                None => return Ok(()),
+            },
+        };
+
+        let processed = r.u64()?;
+        let owner = r.u64()?;
+        let owner_state = if r.bool()? {
+            Some(OwnerState::read(r)?)
+        } else {
+            None
+        };
+        let snapshot = NetSnapshot::read_delta(r, tick, baseline)?;
+        self.snapshots.insert(tick, snapshot);
+
+        self.owner = Some(owner);
+        if let Some(state) = owner_state {
+            self.reconcile(processed, &state);
+        }
+        Ok(())
+    }
+
+    // Rewinds the player to what the server says and plays the unanswered inputs again
+    fn reconcile(&mut self, processed: u64, state: &OwnerState) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|(seq, _)| *seq <= processed)
+        {
+            self.pending.pop_front();
+        }
+
+        let predicted = self.player_position();
+        state.apply(&self.world, self.player);
+        let pending: Vec<TickInput> = self.pending.iter().map(|(_, i)| i.clone()).collect();
+        for input in pending.iter() {
+            self.predict(input);
+        }
+        if self.player_position() != predicted {
+            self.corrections += 1;
+        }
+    }
+
+    fn predict(&mut self, input: &TickInput) {
+        *self.world.write_resource() = input.movement;
+        *self.world.write_resource() = input.actions.clone();
+        self.prediction.dispatch_seq(&self.world);
+        self.world.maintain();
+        // Shooting and items are left to the server
+        if let Some(queue) = self
+            .world
+            .write_storage::<ActionQueue>()
+            .get_mut(self.player)
+        {
+            queue.actions.clear();
+        }
+    }
+
+    fn send_inputs(&mut self) -> Result<(), String> {
+        let first = match self.pending.front() {
+            Some((seq, _)) => *seq,
+            None => return Ok(()),
+        };
+        let count = self.pending.len().min(MAX_INPUTS_PER_MESSAGE);
+
+        let mut w = Writer::default();
+        w.u8(INPUTS_MESSAGE);
+        w.u64(self.server_tick().unwrap_or(NO_TICK));
+        w.u64(first);
+        w.u8(count as u8);
+        for (_, input) in self.pending.iter().take(count) {
+            write_input(&mut w, input);
+        }
+        self.transport.send(&w.bytes)
+    }
+
+    // Moves the mirrored entities to where they were at the render clock
+    fn interpolate(&mut self) {
+        let latest = match self.server_tick() {
+            Some(latest) => latest as f64,
+            None => return,
+        };
+        let target = latest - INTERPOLATION_DELAY;
+        self.render_tick += 1.0;
+        if self.render_tick > target || self.render_tick < target - MAX_INTERPOLATION_LAG {
+            self.render_tick = target;
+        }
+
+        let render_tick = self.render_tick.max(0.0) as u64;
+        let from = match self
+            .snapshots
+            .range(..=render_tick)
+            .next_back()
+            .or_else(|| self.snapshots.iter().next())
+        {
+            Some((_, from)) => from,
+            None => return,
+        };
+        let to = self.snapshots.range(from.tick + 1..).next().map(|(_, s)| s);
+        let t = match to {
+            Some(to) => ((self.render_tick - from.tick as f64) / (to.tick - from.tick) as f64)
+                .clamp(0.0, 1.0),
+            None => 0.0,
+        };
+
+        let gone: Vec<u64> = self
+            .mirrored
+            .keys()
+            .filter(|id| !from.entities.contains_key(id) || Some(**id) == self.owner)
+            .copied()
+            .collect();
+        for id in gone {
+            if let Some(entity) = self.mirrored.remove(&id) {
+                let _ = self.world.delete_entity(entity);
+            }
+        }
+
+        for (id, state) in from.entities.iter() {
+            if Some(*id) == self.owner {
+                continue;
+            }
+            let end = to
+                .and_then(|to| to.entities.get(id))
+                .map_or(state.position, |s| s.position);
+            let point = Point::new(
+                lerp(state.position.x(), end.x(), t),
+                lerp(state.position.y(), end.y(), t),
+            );
+            let entity = *self
+                .mirrored
+                .entry(*id)
+                .or_insert_with(|| self.world.create_entity().build());
+            mirror(&self.world, entity, state, point);
+        }
+
+        // The player's own looks still come from the server
+        let newest = self.snapshots.values().next_back();
+        if let Some(state) = newest.and_then(|s| self.owner.and_then(|id| s.entities.get(&id))) {
+            if let Some(sprites) = self.world.write_storage::<SpriteVec>().get_mut(self.player) {
+                sprites.sprite_vec = state.sprites.clone();
+            }
+            if let (Some(health), Some((hp, max_hp))) = (
+                self.world.write_storage::<Health>().get_mut(self.player),
+                state.health,
+            ) {
+                health.hp = hp;
+                health.max_hp = max_hp;
+            }
+        }
+
+        // Kept back far enough to interpolate from and to use as delta baselines
+        let keep_from = from
+            .tick
+            .min((latest as u64).saturating_sub(SNAPSHOT_INTERVAL * 32));
+        self.snapshots = self.snapshots.split_off(&keep_from);
+    }
+}
+
+fn lerp(a: i32, b: i32, t: f64) -> i32 {
+    a + ((b - a) as f64 * t).round() as i32
+}
+
+fn mirror(world: &World, entity: Entity, state: &NetEntityState, point: Point) {
+    let _ = world
+        .write_storage::<Position>()
+        .insert(entity, Position { point });
+    let _ = world.write_storage::<SpriteVec>().insert(
+        entity,
+        SpriteVec {
+            sprite_vec: state.sprites.clone(),
+        },
+    );
+    match state.health {
+        Some((hp, max_hp)) => {
+            let _ = world.write_storage::<Health>().insert(
+                entity,
+                Health {
+                    hp,
+                    max_hp,
+                    hurt_boxes: Vec::new(),
+                    last_attacker: None,
+                },
+            );
+        }
+        None => {
+            world.write_storage::<Health>().remove(entity);
+        }
+    }
+    match &state.collider {
+        Some(coll) => {
+            let _ = world
+                .write_storage::<Collideable>()
+                .insert(entity, coll.clone());
+        }
+        None => {
+            world.write_storage::<Collideable>().remove(entity);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::commands::*;
+use crate::entity_components::Direction;
+
+// Largest datagram UDP can carry, the receive buffers are this big
+pub const MAX_PACKET: usize = 65507;
+
+// Little endian byte buffer the messages are written into
+#[derive(Debug, Clone, Default)]
+pub struct Writer {
+    pub bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    pub fn i8(&mut self, v: i8) {
+        self.bytes.push(v as u8);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.bytes.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, v: i32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.u32(v.to_bits());
+    }
+
+    // Length first, cut short at 65535 bytes
+    pub fn str(&mut self, v: &str) {
+        let bytes = &v.as_bytes()[..v.len().min(u16::MAX as usize)];
+        self.u16(bytes.len() as u16);
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, at: 0 }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let end = self.at + N;
+        let slice = self
+            .bytes
+            .get(self.at..end)
+            .ok_or_else(|| "message ended early".to_string())?;
+        self.at = end;
+        let mut out = [0; N];
+        out.copy_from_slice(slice);
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn i8(&mut self) -> Result<i8, String> {
+        Ok(self.take::<1>()?[0] as i8)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    pub fn str(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let end = self.at + len;
+        let slice = self
+            .bytes
+            .get(self.at..end)
+            .ok_or_else(|| "message ended early".to_string())?;
+        self.at = end;
+        String::from_utf8(slice.to_vec()).map_err(|_| "text is not utf-8".to_string())
+    }
+}
+
+pub fn write_direction(w: &mut Writer, Direction::MoveDelta { x, y }: Direction) {
+    w.i8(x);
+    w.i8(y);
+}
+
+pub fn read_direction(r: &mut Reader) -> Result<Direction, String> {
+    Ok(Direction::MoveDelta {
+        x: r.i8()?,
+        y: r.i8()?,
+    })
+}
+
+// Movement flag and direction, then the action count and one tag per action
+pub fn write_input(w: &mut Writer, input: &TickInput) {
+    match input.movement {
+        Some(MovementCommand::Move(dir)) => {
+            w.u8(1);
+            write_direction(w, dir);
+        }
+        None => w.u8(0),
+    }
+    w.u8(input.actions.len() as u8);
+    for action in input.actions.iter() {
+        match action {
+            ActionCommand::Shoot(dir) => {
+                w.u8(0);
+                write_direction(w, *dir);
+            }
+            ActionCommand::Use => w.u8(1),
+            ActionCommand::Jump => w.u8(2),
+        }
+    }
+}
+
+pub fn read_input(r: &mut Reader) -> Result<TickInput, String> {
+    let movement = match r.u8()? {
+        0 => None,
+        1 => Some(MovementCommand::Move(read_direction(r)?)),
+        other => return Err(format!("bad movement flag {}", other)),
+    };
+    let count = r.u8()?;
+    let mut actions = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        actions.push(match r.u8()? {
+            0 => ActionCommand::Shoot(read_direction(r)?),
+            1 => ActionCommand::Use,
+            2 => ActionCommand::Jump,
+            other => return Err(format!("bad action tag {}", other)),
+        });
+    }
+    Ok(TickInput { movement, actions })
+}
+
+// Unreliable and unordered datagrams between two peers, the layers above cope with loss
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), String>;
+    // None once nothing more has arrived
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, String>;
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn new(bind: &str, peer: &str) -> Result<UdpTransport, String> {
+        let socket = UdpSocket::bind(bind).map_err(|e| format!("{}: {}", bind, e))?;
+        socket
+            .connect(peer)
+            .map_err(|e| format!("{}: {}", peer, e))?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(UdpTransport { socket })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), String> {
+        match self.socket.send(packet) {
+            Ok(_) => Ok(()),
+            // Nobody listening yet, the peer catches up from later packets
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let mut buf = vec![0; MAX_PACKET];
+        match self.socket.recv(&mut buf) {
+            Ok(len) => Ok(Some(buf[..len].to_vec())),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+// One socket talking to any number of clients, for the authoritative server
+pub struct UdpServer {
+    socket: UdpSocket,
+}
+
+impl UdpServer {
+    pub fn new(bind: &str) -> Result<UdpServer, String> {
+        let socket = UdpSocket::bind(bind).map_err(|e| format!("{}: {}", bind, e))?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(UdpServer { socket })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr().map_err(|e| e.to_string())
+    }
+
+    pub fn send_to(&mut self, addr: SocketAddr, packet: &[u8]) -> Result<(), String> {
+        match self.socket.send_to(packet, addr) {
+            Ok(_) => Ok(()),
+            // A client that went away, the server notices when it stops hearing from it
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn receive_from(&mut self) -> Result<Option<(SocketAddr, Vec<u8>)>, String> {
+        let mut buf = vec![0; MAX_PACKET];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) => return Ok(Some((addr, buf[..len].to_vec()))),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+                // Windows reports an earlier send that bounced, skip it
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+}
+
+// In process pair of peers. A packet only shows up after the receiver has drained its
+// queue `delay` times, which stands in for latency and makes the other side roll back
+pub struct LoopbackTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    delay: u32,
+    in_flight: VecDeque<(u32, Vec<u8>)>,
+}
+
+impl LoopbackTransport {
+    pub fn pair(delay: u32) -> (LoopbackTransport, LoopbackTransport) {
+        let (a_send, b_receive) = channel();
+        let (b_send, a_receive) = channel();
+        let new = |sender, receiver| LoopbackTransport {
+            sender,
+            receiver,
+            delay,
+            in_flight: VecDeque::new(),
+        };
+        (new(a_send, a_receive), new(b_send, b_receive))
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), String> {
+        self.sender
+            .send(packet.to_vec())
+            .map_err(|_| "peer is gone".to_string())
+    }
+
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+        while let Ok(packet) = self.receiver.try_recv() {
+            self.in_flight.push_back((self.delay, packet));
+        }
+        match self.in_flight.front_mut() {
+            Some((0, _)) => Ok(self.in_flight.pop_front().map(|(_, packet)| packet)),
+            Some(_) => {
+                for (wait, _) in self.in_flight.iter_mut() {
+                    *wait = wait.saturating_sub(1);
+                }
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> TickInput {
+        TickInput {
+            movement: Some(MovementCommand::Move(Direction::MoveDelta { x: -1, y: 1 })),
+            actions: vec![
+                ActionCommand::Shoot(Direction::MoveDelta { x: 1, y: 0 }),
+                ActionCommand::Use,
+                ActionCommand::Jump,
+            ],
+        }
+    }
+
+    #[test]
+    fn input_round_trip() {
+        let mut w = Writer::default();
+        write_input(&mut w, &input());
+        write_input(&mut w, &TickInput::default());
+        let mut r = Reader::new(&w.bytes);
+        assert_eq!(read_input(&mut r), Ok(input()));
+        assert_eq!(read_input(&mut r), Ok(TickInput::default()));
+        assert!(r.u8().is_err());
+    }
+
+    #[test]
+    fn cut_short_input_is_an_error() {
+        let mut w = Writer::default();
+        write_input(&mut w, &input());
+        for len in 0..w.bytes.len() {
+            let mut r = Reader::new(&w.bytes[..len]);
+            assert!(read_input(&mut r).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn unknown_tags_are_errors() {
+        assert!(read_input(&mut Reader::new(&[2, 0])).is_err());
+        assert!(read_input(&mut Reader::new(&[0, 1, 9])).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let mut w = Writer::default();
+        w.str("assets/levels/default.level");
+        w.str(&"x".repeat(70000));
+        let mut r = Reader::new(&w.bytes);
+        assert_eq!(r.str().unwrap(), "assets/levels/default.level");
+        assert_eq!(r.str().unwrap().len(), u16::MAX as usize);
+
+        // Longer than what is left, and not utf-8
+        assert!(Reader::new(&[5, 0, b'a', b'b']).str().is_err());
+        assert!(Reader::new(&[2, 0, 0xff, 0xfe]).str().is_err());
+    }
+}
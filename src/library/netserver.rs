@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+use specs::prelude::*;
+
+use crate::commands::*;
+use crate::events::SoundQueue;
+use crate::netcode::*;
+use crate::netstate::*;
+use crate::simulation::Simulation;
+
+pub const HELLO_MESSAGE: u8 = 10;
+pub const WELCOME_MESSAGE: u8 = 11;
+pub const INPUTS_MESSAGE: u8 = 12;
+pub const SNAPSHOT_MESSAGE: u8 = 13;
+// Stands in for a tick when there is none, like a client that has no snapshot yet
+pub const NO_TICK: u64 = u64::MAX;
+
+// Snapshots go out every other tick
+pub const SNAPSHOT_INTERVAL: u64 = 2;
+// Ticks of silence before a client is dropped
+const CLIENT_TIMEOUT: u64 = 300;
+// Queued inputs beyond this are merged so a client that sent a burst does not lag behind
+const MAX_INPUT_BACKLOG: usize = 4;
+// Sent snapshots kept around as delta baselines
+const SNAPSHOT_HISTORY: u64 = 64;
+// Snapshot datagrams stay below a typical MTU so they are never fragmented, entities that
+// do not fit go out with the next one
+const MAX_SNAPSHOT_BYTES: usize = 1200;
+// Events kept until whoever runs the server takes them
+const MAX_EVENTS: usize = 100;
+
+// What happened to the clients, for the binary running the server to log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    Joined { addr: SocketAddr, slot: usize },
+    TimedOut { addr: SocketAddr },
+    BadInput { addr: SocketAddr, error: String },
+    SendFailed { addr: SocketAddr, error: String },
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerEvent::Joined { addr, slot } => write!(f, "{} joined as player {}", addr, slot),
+            ServerEvent::TimedOut { addr } => write!(f, "{} timed out", addr),
+            ServerEvent::BadInput { addr, error } => {
+                write!(f, "bad input from {}: {}", addr, error)
+            }
+            ServerEvent::SendFailed { addr, error } => {
+                write!(f, "could not send to {}: {}", addr, error)
+            }
+        }
+    }
+}
+
+struct ServerClient {
+    addr: SocketAddr,
+    slot: usize,
+    entity: Entity,
+    // Received inputs by sequence number, not simulated yet
+    inputs: BTreeMap<u64, TickInput>,
+    // Sequence number of the last input simulated
+    processed: u64,
+    last_movement: Option<MovementCommand>,
+    // Newest snapshot the client has, the baseline for its next delta
+    acked: u64,
+    // What the client got of each recent snapshot, a delta can leave entities out
+    history: BTreeMap<u64, NetSnapshot>,
+    // Id the next delta starts at, so entities left out go first
+    next_entity: u64,
+    last_heard: u64,
+}
+
+fn push_event(events: &mut Vec<ServerEvent>, event: ServerEvent) {
+    events.push(event);
+    if events.len() > MAX_EVENTS {
+        events.drain(..events.len() - MAX_EVENTS);
+    }
+}
+
+// Authoritative server. Runs the simulation with one NetPlayer per client, feeds it the
+// inputs the clients send and answers with delta compressed snapshots
+pub struct NetServer {
+    socket: UdpServer,
+    clients: Vec<ServerClient>,
+    events: Vec<ServerEvent>,
+    pub snapshots_sent: u64,
+    pub bytes_sent: u64,
+}
+
+impl NetServer {
+    pub fn new(bind: &str) -> Result<NetServer, String> {
+        Ok(NetServer {
+            socket: UdpServer::new(bind)?,
+            clients: Vec::new(),
+            events: Vec::new(),
+            snapshots_sent: 0,
+            bytes_sent: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr()
+    }
+
+    pub fn take_events(&mut self) -> Vec<ServerEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn tick(&mut self, sim: &mut Simulation) -> Result<(), String> {
+        self.receive(sim)?;
+        self.drop_silent(sim);
+        self.respawn(sim)?;
+
+        let inputs = self.next_inputs();
+        sim.step_players(&inputs);
+        // Sounds are not networked, nothing on the server plays them
+        *sim.world.write_resource::<SoundQueue>() = SoundQueue::default();
+
+        if sim.tick.is_multiple_of(SNAPSHOT_INTERVAL) {
+            self.send_snapshots(sim);
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, sim: &mut Simulation) -> Result<(), String> {
+        while let Some((addr, packet)) = self.socket.receive_from()? {
+            let mut r = Reader::new(&packet);
+            // Garbage from strangers is dropped, not worth stopping the server for
+            let tag = match r.u8() {
+                Ok(tag) => tag,
+                Err(_) => continue,
+            };
+            let known = self.clients.iter().position(|c| c.addr == addr);
+            match (tag, known) {
+                (HELLO_MESSAGE, None) => {
+                    let slot = (0..)
+                        .find(|slot| self.clients.iter().all(|c| c.slot != *slot))
+                        .unwrap_or(0);
+                    let entity = sim.add_net_player(slot)?;
+                    push_event(&mut self.events, ServerEvent::Joined { addr, slot });
+                    self.clients.push(ServerClient {
+                        addr,
+                        slot,
+                        entity,
+                        inputs: BTreeMap::new(),
+                        processed: 0,
+                        last_movement: None,
+                        acked: NO_TICK,
+                        history: BTreeMap::new(),
+                        next_entity: 0,
+                        last_heard: sim.tick,
+                    });
+                    self.welcome(self.clients.len() - 1);
+                }
+                // The welcome got lost
+                (HELLO_MESSAGE, Some(index)) => self.welcome(index),
+                (INPUTS_MESSAGE, Some(index)) => {
+                    if let Err(e) = self.read_inputs(index, &mut r, sim.tick) {
+                        push_event(&mut self.events, ServerEvent::BadInput { addr, error: e });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Lost like any datagram if sending fails, the client says hello again
+    fn welcome(&mut self, index: usize) {
+        let addr = self.clients[index].addr;
+        let mut w = Writer::default();
+        w.u8(WELCOME_MESSAGE);
+        w.u8(self.clients[index].slot as u8);
+        if let Err(error) = self.socket.send_to(addr, &w.bytes) {
+            push_event(&mut self.events, ServerEvent::SendFailed { addr, error });
+        }
+    }
+
+    // Ack of the newest snapshot, then every input the server has not acknowledged yet
+    fn read_inputs(&mut self, index: usize, r: &mut Reader, tick: u64) -> Result<(), String> {
+        let client = &mut self.clients[index];
+        client.last_heard = tick;
+        let acked = r.u64()?;
+        if acked != NO_TICK && (client.acked == NO_TICK || acked > client.acked) {
+            client.acked = acked;
+        }
+        let first = r.u64()?;
+        let count = r.u8()? as u64;
+        for seq in first..first + count {
+            let input = read_input(r)?;
+            if seq > client.processed {
+                client.inputs.entry(seq).or_insert(input);
+            }
+        }
+        Ok(())
+    }
+
+    fn drop_silent(&mut self, sim: &mut Simulation) {
+        let tick = sim.tick;
+        let (silent, heard): (Vec<_>, Vec<_>) = std::mem::take(&mut self.clients)
+            .into_iter()
+            .partition(|c| tick - c.last_heard > CLIENT_TIMEOUT);
+        self.clients = heard;
+        for client in silent {
+            let _ = sim.world.delete_entity(client.entity);
+            push_event(
+                &mut self.events,
+                ServerEvent::TimedOut { addr: client.addr },
+            );
+        }
+    }
+
+    // Players that died come back at their spawn point
+    fn respawn(&mut self, sim: &mut Simulation) -> Result<(), String> {
+        for client in self.clients.iter_mut() {
+            if !sim.world.is_alive(client.entity) {
+                client.entity = sim.add_net_player(client.slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    // One input per slot. A client whose input is late keeps moving the way it did
+    fn next_inputs(&mut self) -> Vec<TickInput> {
+        let slots = self.clients.iter().map(|c| c.slot + 1).max().unwrap_or(0);
+        let mut inputs = vec![TickInput::default(); slots];
+
+        for client in self.clients.iter_mut() {
+            while client.inputs.len() > MAX_INPUT_BACKLOG {
+                if let Some((seq, dropped)) = client.inputs.pop_first() {
+                    client.processed = seq;
+                    if let Some(next) = client.inputs.values_mut().next() {
+                        next.actions.splice(0..0, dropped.actions);
+                    }
+                }
+            }
+
+            inputs[client.slot] = match client.inputs.remove(&(client.processed + 1)) {
+                Some(input) => {
+                    client.processed += 1;
+                    client.last_movement = input.movement;
+                    input
+                }
+                None => TickInput {
+                    movement: client.last_movement,
+                    actions: Vec::new(),
+                },
+            };
+        }
+        inputs
+    }
+
+    fn send_snapshots(&mut self, sim: &Simulation) {
+        let tick = sim.tick;
+        let snapshot = NetSnapshot::capture(&sim.world, tick);
+
+        for client in self.clients.iter_mut() {
+            let baseline = client.history.get(&client.acked);
+            let mut w = Writer::default();
+            w.u8(SNAPSHOT_MESSAGE);
+            w.u64(tick);
+            w.u64(baseline.map_or(NO_TICK, |b| b.tick));
+            w.u64(client.processed);
+            w.u64(net_id(client.entity));
+            match OwnerState::capture(&sim.world, client.entity) {
+                Some(owner) => {
+                    w.bool(true);
+                    owner.write(&mut w);
+                }
+                None => w.bool(false),
+            }
+            let room = MAX_SNAPSHOT_BYTES.saturating_sub(w.bytes.len());
+            let (sent, next) = snapshot.write_delta(&mut w, baseline, room, client.next_entity);
+            client.next_entity = next;
+            client.history.insert(tick, sent);
+            client.history = client
+                .history
+                .split_off(&tick.saturating_sub(SNAPSHOT_HISTORY));
+
+            // Treated like a lost datagram, the next snapshot carries the same changes
+            if let Err(error) = self.socket.send_to(client.addr, &w.bytes) {
+                let addr = client.addr;
+                push_event(&mut self.events, ServerEvent::SendFailed { addr, error });
+                continue;
+            }
+            self.snapshots_sent += 1;
+            self.bytes_sent += w.bytes.len() as u64;
+        }
+    }
+}
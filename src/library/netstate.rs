@@ -0,0 +1,561 @@
+use std::collections::BTreeMap;
+
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+
+use crate::entity_components::*;
+use crate::netcode::*;
+
+const FIELD_POSITION: u8 = 1;
+const FIELD_SPRITES: u8 = 2;
+const FIELD_HEALTH: u8 = 4;
+const FIELD_COLLIDER: u8 = 8;
+const FIELDS: [u8; 4] = [FIELD_POSITION, FIELD_SPRITES, FIELD_HEALTH, FIELD_COLLIDER];
+
+// Server entities keep their id and generation on the wire, so a reused id is a new entity
+pub fn net_id(entity: Entity) -> u64 {
+    (entity.gen().id() as u32 as u64) << 32 | entity.id() as u64
+}
+
+// What a client sees of one server entity
+#[derive(Debug, Clone)]
+pub struct NetEntityState {
+    pub position: Point,
+    pub sprites: Vec<Sprite>,
+    // Current and maximum hp
+    pub health: Option<(u32, u32)>,
+    // Only for things that do not move by themselves, clients collide against them
+    pub collider: Option<Collideable>,
+}
+
+impl NetEntityState {
+    fn write_field(&self, w: &mut Writer, field: u8) {
+        match field {
+            FIELD_POSITION => write_point(w, self.position),
+            FIELD_SPRITES => {
+                w.u8(self.sprites.len() as u8);
+                for sprite in self.sprites.iter() {
+                    write_sprite(w, sprite);
+                }
+            }
+            FIELD_HEALTH => {
+                w.bool(self.health.is_some());
+                if let Some((hp, max_hp)) = self.health {
+                    w.u32(hp);
+                    w.u32(max_hp);
+                }
+            }
+            _ => {
+                w.bool(self.collider.is_some());
+                if let Some(coll) = &self.collider {
+                    write_rect(w, coll.col_box);
+                    w.f32(coll.friction);
+                }
+            }
+        }
+    }
+
+    fn read_field(&mut self, r: &mut Reader, field: u8) -> Result<(), String> {
+        match field {
+            FIELD_POSITION => self.position = read_point(r)?,
+            FIELD_SPRITES => {
+                let count = r.u8()?;
+                self.sprites = (0..count)
+                    .map(|_| read_sprite(r))
+                    .collect::<Result<_, _>>()?;
+            }
+            FIELD_HEALTH => {
+                self.health = if r.bool()? {
+                    Some((r.u32()?, r.u32()?))
+                } else {
+                    None
+                };
+            }
+            _ => {
+                self.collider = if r.bool()? {
+                    Some(Collideable {
+                        col_box: read_rect(r)?,
+                        friction: r.f32()?,
+                    })
+                } else {
+                    None
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn field_bytes(&self, field: u8) -> Vec<u8> {
+        let mut w = Writer::default();
+        self.write_field(&mut w, field);
+        w.bytes
+    }
+}
+
+impl Default for NetEntityState {
+    fn default() -> Self {
+        NetEntityState {
+            position: Point::new(0, 0),
+            sprites: Vec::new(),
+            health: None,
+            collider: None,
+        }
+    }
+}
+
+// Every networked entity of one server tick
+#[derive(Debug, Clone, Default)]
+pub struct NetSnapshot {
+    pub tick: u64,
+    pub entities: BTreeMap<u64, NetEntityState>,
+}
+
+impl NetSnapshot {
+    // Everything visible or solid is networked, rules, AI and timers stay on the server
+    pub fn capture(world: &World, tick: u64) -> NetSnapshot {
+        let positions = world.read_storage::<Position>();
+        let sprites = world.read_storage::<SpriteVec>();
+        let health = world.read_storage::<Health>();
+        let colliders = world.read_storage::<Collideable>();
+        let velocities = world.read_storage::<Velocity>();
+
+        let mut entities = BTreeMap::new();
+        for (entity, pos, sprites, health, coll, vel) in (
+            &world.entities(),
+            &positions,
+            sprites.maybe(),
+            health.maybe(),
+            colliders.maybe(),
+            velocities.maybe(),
+        )
+            .join()
+        {
+            if sprites.is_none() && coll.is_none() {
+                continue;
+            }
+            entities.insert(
+                net_id(entity),
+                NetEntityState {
+                    position: pos.point,
+                    sprites: sprites.map_or(Vec::new(), |s| s.sprite_vec.clone()),
+                    health: health.map(|h| (h.hp, h.max_hp)),
+                    collider: coll.filter(|_| vel.is_none()).cloned(),
+                },
+            );
+        }
+        NetSnapshot { tick, entities }
+    }
+
+    // Removed ids, then changed entities with a mask of the fields that differ from the
+    // baseline. Without a baseline every field of every entity is sent. Whatever does not
+    // fit in max_bytes is left for a later delta, changed entities are taken round robin
+    // from the id `start` on so all of them get their turn. Returns what the reader ends up
+    // with, the baseline for the next delta, and the id to start the next one at
+    pub fn write_delta(
+        &self,
+        w: &mut Writer,
+        baseline: Option<&NetSnapshot>,
+        max_bytes: usize,
+        start: u64,
+    ) -> (NetSnapshot, u64) {
+        let empty = NetSnapshot::default();
+        let baseline = baseline.unwrap_or(&empty);
+        let mut sent = NetSnapshot {
+            tick: self.tick,
+            entities: baseline.entities.clone(),
+        };
+        // Room for the two counts
+        let mut budget = max_bytes.saturating_sub(8);
+
+        let removed: Vec<u64> = baseline
+            .entities
+            .keys()
+            .filter(|id| !self.entities.contains_key(id))
+            .take(budget / 8)
+            .copied()
+            .collect();
+        budget -= removed.len() * 8;
+        w.u32(removed.len() as u32);
+        for id in removed {
+            w.u64(id);
+            sent.entities.remove(&id);
+        }
+
+        let mut changed = Writer::default();
+        let mut count = 0;
+        let mut next = None;
+        let ordered = self
+            .entities
+            .range(start..)
+            .chain(self.entities.range(..start));
+        for (id, state) in ordered {
+            let old = baseline.entities.get(id);
+            let mask = FIELDS
+                .iter()
+                .filter(|f| old.is_none_or(|old| old.field_bytes(**f) != state.field_bytes(**f)))
+                .fold(0, |mask, f| mask | f);
+            if mask == 0 {
+                continue;
+            }
+            let mut entity = Writer::default();
+            entity.u64(*id);
+            entity.u8(mask);
+            for field in FIELDS.iter().filter(|f| mask & **f != 0) {
+                state.write_field(&mut entity, *field);
+            }
+            // Smaller ones further on may still fit
+            if changed.bytes.len() + entity.bytes.len() > budget {
+                next = next.or(Some(*id));
+                continue;
+            }
+            changed.bytes.extend_from_slice(&entity.bytes);
+            sent.entities.insert(*id, state.clone());
+            count += 1;
+        }
+        w.u32(count);
+        w.bytes.extend_from_slice(&changed.bytes);
+        (sent, next.unwrap_or(0))
+    }
+
+    pub fn read_delta(
+        r: &mut Reader,
+        tick: u64,
+        baseline: Option<&NetSnapshot>,
+    ) -> Result<NetSnapshot, String> {
+        let mut snapshot = NetSnapshot {
+            tick,
+            entities: baseline.map_or(BTreeMap::new(), |b| b.entities.clone()),
+        };
+        for _ in 0..r.u32()? {
+            snapshot.entities.remove(&r.u64()?);
+        }
+        for _ in 0..r.u32()? {
+            let id = r.u64()?;
+            let mask = r.u8()?;
+            let state = snapshot.entities.entry(id).or_default();
+            for field in FIELDS.iter().filter(|f| mask & **f != 0) {
+                state.read_field(r, *field)?;
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+// Full movement state of the entity a client controls, sent only to that client so it
+// can correct its prediction
+#[derive(Debug, Clone)]
+pub struct OwnerState {
+    pub position: Point,
+    pub velocity: Velocity,
+    pub gravity: Option<GravityAfflicted>,
+    pub controller: Option<PlatformerController>,
+}
+
+impl OwnerState {
+    pub fn capture(world: &World, entity: Entity) -> Option<OwnerState> {
+        Some(OwnerState {
+            position: world.read_storage::<Position>().get(entity)?.point,
+            velocity: world.read_storage::<Velocity>().get(entity)?.clone(),
+            gravity: world
+                .read_storage::<GravityAfflicted>()
+                .get(entity)
+                .cloned(),
+            controller: world
+                .read_storage::<PlatformerController>()
+                .get(entity)
+                .cloned(),
+        })
+    }
+
+    // Overwrites the predicted entity. Controller tuning is the same on both sides,
+    // only what changes while playing is taken over
+    pub fn apply(&self, world: &World, entity: Entity) {
+        if let Some(pos) = world.write_storage::<Position>().get_mut(entity) {
+            pos.point = self.position;
+        }
+        if let Some(vel) = world.write_storage::<Velocity>().get_mut(entity) {
+            *vel = self.velocity.clone();
+        }
+        if let (Some(grav), Some(state)) = (
+            world.write_storage::<GravityAfflicted>().get_mut(entity),
+            &self.gravity,
+        ) {
+            *grav = state.clone();
+        }
+        if let (Some(ctrl), Some(state)) = (
+            world
+                .write_storage::<PlatformerController>()
+                .get_mut(entity),
+            &self.controller,
+        ) {
+            ctrl.jump_held = state.jump_held;
+            ctrl.coyote = state.coyote;
+            ctrl.buffer = state.buffer;
+            ctrl.air_jumps = state.air_jumps;
+            ctrl.jumping = state.jumping;
+            ctrl.air_accumulator = state.air_accumulator;
+        }
+    }
+
+    pub fn write(&self, w: &mut Writer) {
+        write_point(w, self.position);
+        let vel = &self.velocity;
+        w.u16(vel.speed);
+        w.i8(vel.max_speed);
+        w.u32(vel.acc);
+        w.bool(vel.last_dir.is_some());
+        if let Some(dir) = vel.last_dir {
+            write_direction(w, dir);
+        }
+        w.f32(vel.carry.0);
+        w.f32(vel.carry.1);
+
+        w.bool(self.gravity.is_some());
+        if let Some(grav) = &self.gravity {
+            w.i32(grav.max_vel);
+            w.bool(grav.grounded);
+            w.bool(grav.grounded_rect.is_some());
+            if let Some(rect) = grav.grounded_rect {
+                write_rect(w, rect);
+            }
+            w.f32(grav.ground_friction);
+            w.i8(grav.jump_speed);
+        }
+
+        w.bool(self.controller.is_some());
+        if let Some(ctrl) = &self.controller {
+            w.bool(ctrl.jump_held);
+            w.u32(ctrl.coyote);
+            w.u32(ctrl.buffer);
+            w.u32(ctrl.air_jumps);
+            w.bool(ctrl.jumping);
+            w.f32(ctrl.air_accumulator);
+        }
+    }
+
+    pub fn read(r: &mut Reader) -> Result<OwnerState, String> {
+        let position = read_point(r)?;
+        let velocity = Velocity {
+            speed: r.u16()?,
+            max_speed: r.i8()?,
+            acc: r.u32()?,
+            last_dir: if r.bool()? {
+                Some(read_direction(r)?)
+            } else {
+                None
+            },
+            carry: (r.f32()?, r.f32()?),
+        };
+
+        let gravity = if r.bool()? {
+            Some(GravityAfflicted {
+                max_vel: r.i32()?,
+                grounded: r.bool()?,
+                grounded_rect: if r.bool()? { Some(read_rect(r)?) } else { None },
+                ground_friction: r.f32()?,
+                jump_speed: r.i8()?,
+            })
+        } else {
+            None
+        };
+
+        let controller = if r.bool()? {
+            Some(PlatformerController {
+                jump_held: r.bool()?,
+                coyote: r.u32()?,
+                buffer: r.u32()?,
+                air_jumps: r.u32()?,
+                jumping: r.bool()?,
+                air_accumulator: r.f32()?,
+                ..PlatformerController::default()
+            })
+        } else {
+            None
+        };
+
+        Ok(OwnerState {
+            position,
+            velocity,
+            gravity,
+            controller,
+        })
+    }
+}
+
+fn write_point(w: &mut Writer, point: Point) {
+    w.i32(point.x());
+    w.i32(point.y());
+}
+
+fn read_point(r: &mut Reader) -> Result<Point, String> {
+    Ok(Point::new(r.i32()?, r.i32()?))
+}
+
+fn write_rect(w: &mut Writer, rect: Rect) {
+    w.i32(rect.x());
+    w.i32(rect.y());
+    w.u32(rect.width());
+    w.u32(rect.height());
+}
+
+fn read_rect(r: &mut Reader) -> Result<Rect, String> {
+    Ok(Rect::new(r.i32()?, r.i32()?, r.u32()?, r.u32()?))
+}
+
+fn write_sprite(w: &mut Writer, sprite: &Sprite) {
+    w.u8(sprite.spritesheet as u8);
+    write_rect(w, sprite.region);
+    w.bool(sprite.rotation.is_some());
+    if let Some(rotation) = &sprite.rotation {
+        w.f32(rotation.rotation as f32);
+        w.bool(rotation.rot_point.is_some());
+        if let Some(point) = rotation.rot_point {
+            write_point(w, point);
+        }
+    }
+}
+
+fn read_sprite(r: &mut Reader) -> Result<Sprite, String> {
+    let spritesheet = r.u8()? as usize;
+    let region = read_rect(r)?;
+    let rotation = if r.bool()? {
+        Some(Rotation {
+            rotation: r.f32()? as f64,
+            rot_point: if r.bool()? {
+                Some(read_point(r)?)
+            } else {
+                None
+            },
+        })
+    } else {
+        None
+    };
+    Ok(Sprite {
+        spritesheet,
+        region,
+        rotation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(x: i32, hp: Option<(u32, u32)>) -> NetEntityState {
+        NetEntityState {
+            position: Point::new(x, 20),
+            sprites: vec![Sprite {
+                spritesheet: 1,
+                region: Rect::new(0, 0, 16, 16),
+                rotation: None,
+            }],
+            health: hp,
+            collider: None,
+        }
+    }
+
+    fn snapshot(tick: u64, count: u64) -> NetSnapshot {
+        NetSnapshot {
+            tick,
+            entities: (0..count)
+                .map(|id| (id, state(id as i32 * 10, Some((100, 100)))))
+                .collect(),
+        }
+    }
+
+    fn same(a: &NetSnapshot, b: &NetSnapshot) -> bool {
+        a.entities.len() == b.entities.len()
+            && a.entities
+                .iter()
+                .zip(b.entities.iter())
+                .all(|((ia, a), (ib, b))| {
+                    ia == ib
+                        && FIELDS
+                            .iter()
+                            .all(|f| a.field_bytes(*f) == b.field_bytes(*f))
+                })
+    }
+
+    fn send(
+        snapshot: &NetSnapshot,
+        baseline: Option<&NetSnapshot>,
+        max_bytes: usize,
+        start: u64,
+    ) -> (NetSnapshot, NetSnapshot, u64, usize) {
+        let mut w = Writer::default();
+        let (sent, next) = snapshot.write_delta(&mut w, baseline, max_bytes, start);
+        let mut r = Reader::new(&w.bytes);
+        let read = NetSnapshot::read_delta(&mut r, snapshot.tick, baseline).unwrap();
+        assert!(r.u8().is_err(), "bytes left over");
+        (sent, read, next, w.bytes.len())
+    }
+
+    #[test]
+    fn full_snapshot_round_trip() {
+        let snapshot = snapshot(4, 5);
+        let (sent, read, next, _) = send(&snapshot, None, usize::MAX, 0);
+        assert!(same(&read, &snapshot));
+        assert!(same(&sent, &snapshot));
+        assert_eq!(next, 0);
+    }
+
+    #[test]
+    fn delta_against_a_baseline() {
+        let baseline = snapshot(4, 5);
+        let mut current = baseline.clone();
+        current.tick = 6;
+        current.entities.remove(&1);
+        current.entities.insert(3, state(31, None));
+        current.entities.insert(9, state(90, Some((5, 10))));
+
+        let (_, _, _, full) = send(&current, None, usize::MAX, 0);
+        let (sent, read, _, delta) = send(&current, Some(&baseline), usize::MAX, 0);
+        assert!(same(&read, &current));
+        assert!(same(&sent, &current));
+        assert!(delta < full, "delta {} full {}", delta, full);
+
+        // Nothing changed, only the two counts
+        let (_, read, _, empty) = send(&current, Some(&current), usize::MAX, 0);
+        assert!(same(&read, &current));
+        assert_eq!(empty, 8);
+    }
+
+    #[test]
+    fn capped_deltas_catch_up() {
+        let current = snapshot(2, 50);
+        let (sent, read, next, len) = send(&current, None, 200, 0);
+        assert!(len <= 200);
+        assert!(same(&read, &sent));
+        assert!(sent.entities.len() < 50);
+        assert_ne!(next, 0);
+
+        // Each delta goes on from where the last one stopped, against what arrived
+        let mut client = sent;
+        let mut start = next;
+        for _ in 0..50 {
+            if same(&client, &current) {
+                break;
+            }
+            let (sent, read, next, len) = send(&current, Some(&client), 200, start);
+            assert!(len <= 200);
+            assert!(same(&read, &sent));
+            client = sent;
+            start = next;
+        }
+        assert!(same(&client, &current));
+    }
+
+    #[test]
+    fn cut_short_delta_is_an_error() {
+        let mut w = Writer::default();
+        snapshot(4, 3).write_delta(&mut w, None, usize::MAX, 0);
+        for len in 0..w.bytes.len() {
+            let mut r = Reader::new(&w.bytes[..len]);
+            assert!(
+                NetSnapshot::read_delta(&mut r, 4, None).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+    }
+}
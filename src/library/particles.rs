@@ -0,0 +1,195 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::rng::Rng;
+
+pub const DEFAULT_PARTICLE_CAPACITY: usize = 8192;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParticleShape {
+    // Filled circle drawn with the gfx primitives, radius in pixels at scale 1
+    Circle(f32),
+    // Region of a spritesheet, tinted and faded by the particle color
+    Textured { spritesheet: usize, region: Rect },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitterConfig {
+    // Particles per tick for continuous emitters, fractions accumulate
+    pub rate: f32,
+    // Particles spawned at once by a burst
+    pub burst: u32,
+    pub lifetime: u32,
+    // Degrees, 0 points right and positive turns clockwise on screen
+    pub direction: f32,
+    // Degrees either side of direction
+    pub spread: f32,
+    pub speed_min: f32,
+    pub speed_max: f32,
+    // Pixels per tick added to the y velocity every tick
+    pub gravity: f32,
+    pub start_color: Color,
+    pub end_color: Color,
+    pub start_scale: f32,
+    pub end_scale: f32,
+    pub shape: ParticleShape,
+}
+
+impl EmitterConfig {
+    pub fn muzzle_flash(direction: f32) -> EmitterConfig {
+        EmitterConfig {
+            rate: 0.0,
+            burst: 8,
+            lifetime: 6,
+            direction,
+            spread: 25.0,
+            speed_min: 1.5,
+            speed_max: 4.0,
+            gravity: 0.0,
+            start_color: Color::RGBA(255, 240, 150, 255),
+            end_color: Color::RGBA(255, 90, 0, 0),
+            start_scale: 1.5,
+            end_scale: 0.3,
+            shape: ParticleShape::Circle(2.0),
+        }
+    }
+
+    pub fn impact() -> EmitterConfig {
+        EmitterConfig {
+            rate: 0.0,
+            burst: 14,
+            lifetime: 20,
+            direction: -90.0,
+            spread: 180.0,
+            speed_min: 0.5,
+            speed_max: 3.0,
+            gravity: 0.15,
+            start_color: Color::RGBA(200, 20, 20, 255),
+            end_color: Color::RGBA(90, 0, 0, 0),
+            start_scale: 1.0,
+            end_scale: 0.5,
+            shape: ParticleShape::Circle(1.5),
+        }
+    }
+
+    pub fn dust() -> EmitterConfig {
+        EmitterConfig {
+            rate: 0.0,
+            burst: 10,
+            lifetime: 25,
+            direction: -90.0,
+            spread: 80.0,
+            speed_min: 0.3,
+            speed_max: 1.2,
+            gravity: 0.02,
+            start_color: Color::RGBA(160, 140, 120, 180),
+            end_color: Color::RGBA(160, 140, 120, 0),
+            start_scale: 1.0,
+            end_scale: 2.5,
+            shape: ParticleShape::Circle(2.0),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Particle {
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+    pub gravity: f32,
+    pub age: u32,
+    pub lifetime: u32,
+    pub start_color: Color,
+    pub end_color: Color,
+    pub start_scale: f32,
+    pub end_scale: f32,
+    pub shape: ParticleShape,
+}
+
+impl Particle {
+    // 0.0 when born, 1.0 when it expires
+    pub fn life(&self) -> f32 {
+        self.age as f32 / self.lifetime.max(1) as f32
+    }
+
+    pub fn color(&self) -> Color {
+        let t = self.life();
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t) as u8;
+        Color::RGBA(
+            lerp(self.start_color.r, self.end_color.r),
+            lerp(self.start_color.g, self.end_color.g),
+            lerp(self.start_color.b, self.end_color.b),
+            lerp(self.start_color.a, self.end_color.a),
+        )
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.start_scale + (self.end_scale - self.start_scale) * self.life()
+    }
+}
+
+// Fixed size pool so effects never create specs entities, full pools drop new particles.
+// Has its own generator so purely visual effects never shift the simulation's random numbers
+#[derive(Debug, Clone)]
+pub struct ParticlePool {
+    pub particles: Vec<Particle>,
+    pub capacity: usize,
+    pub rng: Rng,
+}
+
+impl Default for ParticlePool {
+    fn default() -> Self {
+        ParticlePool::new(DEFAULT_PARTICLE_CAPACITY)
+    }
+}
+
+impl ParticlePool {
+    pub fn new(capacity: usize) -> ParticlePool {
+        ParticlePool {
+            particles: Vec::with_capacity(capacity),
+            capacity,
+            rng: Rng::default(),
+        }
+    }
+
+    pub fn emit(&mut self, config: &EmitterConfig, x: f32, y: f32) {
+        if self.particles.len() >= self.capacity {
+            return;
+        }
+
+        let angle = (config.direction + config.spread * self.rng.range(-1.0, 1.0)).to_radians();
+        let speed = self.rng.range(config.speed_min, config.speed_max);
+
+        self.particles.push(Particle {
+            x,
+            y,
+            vx: angle.cos() * speed,
+            vy: angle.sin() * speed,
+            gravity: config.gravity,
+            age: 0,
+            lifetime: config.lifetime,
+            start_color: config.start_color,
+            end_color: config.end_color,
+            start_scale: config.start_scale,
+            end_scale: config.end_scale,
+            shape: config.shape,
+        });
+    }
+
+    pub fn burst(&mut self, config: &EmitterConfig, x: f32, y: f32) {
+        for _ in 0..config.burst {
+            self.emit(config, x, y);
+        }
+    }
+
+    pub fn update(&mut self) {
+        for p in self.particles.iter_mut() {
+            p.vy += p.gravity;
+            p.x += p.vx;
+            p.y += p.vy;
+            p.age += 1;
+        }
+        self.particles.retain(|p| p.age < p.lifetime);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+
+use crate::entity_ai::AI;
+use crate::entity_animation::Animation;
+use crate::entity_components::*;
+use crate::rect;
+
+// Names Prefab::load knows, for listings and error messages
+pub const PREFAB_NAMES: [&str; 7] = [
+    "grunt", "crate", "heal", "medkit", "coins", "ammo", "shotgun",
+];
+
+// Entity template that can be built by name, into the world directly or through LazyUpdate
+// from inside a system. Loading reads whatever files the template needs, building can not fail
+#[derive(Debug, Clone)]
+pub enum Prefab {
+    Grunt { ai: AI, walk: Animation },
+    Crate,
+    Pickup(PickupKind),
+}
+
+impl Prefab {
+    pub fn load(name: &str) -> Result<Prefab, String> {
+        let read =
+            |path: &str| std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e));
+        Ok(match name {
+            "grunt" => Prefab::Grunt {
+                ai: AI::parse(&read("assets/ai/grunt.fsm")?)?,
+                walk: Animation::parse(&read("assets/anim/reaper_walk.anim")?)?,
+            },
+            "crate" => Prefab::Crate,
+            "heal" => Prefab::Pickup(PickupKind::Heal(20)),
+            "medkit" => Prefab::Pickup(PickupKind::Item(ItemStack {
+                item: Item::Medkit,
+                count: 1,
+            })),
+            "coins" => Prefab::Pickup(PickupKind::Item(ItemStack {
+                item: Item::Coin,
+                count: 10,
+            })),
+            "ammo" => Prefab::Pickup(PickupKind::Item(ItemStack {
+                item: Item::Ammo,
+                count: 30,
+            })),
+            "shotgun" => Prefab::Pickup(PickupKind::Weapon(Box::new(Weapon {
+                fire_delay: 30,
+                projectile_count: 5,
+                spread: 40.0,
+                lifetime: 15,
+                ammo_per_shot: 1,
+                ..Weapon::default()
+            }))),
+            other => return Err(unknown(other)),
+        })
+    }
+
+    pub fn build<B: Builder>(self, builder: B, point: Point) -> Entity {
+        match self {
+            Prefab::Grunt { ai, walk } => builder
+                .with(ai)
+                .with(walk)
+                .with(ActionQueue::default())
+                .with(Weapon {
+                    fire_delay: 30,
+                    speed: 8,
+                    gravity: true,
+                    ..Weapon::default()
+                })
+                .with(GravityAfflicted {
+                    max_vel: 20,
+                    grounded: false,
+                    grounded_rect: None,
+                    ground_friction: 1.0,
+                    jump_speed: 10,
+                })
+                .with(Velocity {
+                    speed: 0,
+                    max_speed: 2,
+                    acc: 1,
+                    last_dir: None,
+                    carry: (0.0, 0.0),
+                })
+                .with(Position { point })
+                .with(SpriteVec {
+                    sprite_vec: vec![Sprite {
+                        spritesheet: 0,
+                        region: rect!(0, 0, 26, 36),
+                        rotation: None,
+                    }],
+                })
+                .with(Collideable {
+                    col_box: rect!(10, 10, 16, 36),
+                    friction: 1.0,
+                })
+                .with(Health {
+                    hp: 50,
+                    max_hp: 50,
+                    hurt_boxes: vec![rect!(0, 0, 16, 36)],
+                    last_attacker: None,
+                })
+                .with(Team { id: 1 })
+                .build(),
+            Prefab::Crate => builder
+                .with(Position { point })
+                .with(SpriteVec {
+                    sprite_vec: vec![Sprite {
+                        spritesheet: 2,
+                        region: rect!(0, 0, 20, 20),
+                        rotation: None,
+                    }],
+                })
+                .with(Collideable {
+                    col_box: rect!(0, 0, 20, 20),
+                    friction: 1.0,
+                })
+                .with(RigidBody::default())
+                .with(Mass { mass: 2.0 })
+                .build(),
+            Prefab::Pickup(kind) => builder
+                .with(Position { point })
+                .with(Pickup {
+                    kind,
+                    area: rect!(0, 0, 12, 12),
+                })
+                .with(SpriteVec {
+                    sprite_vec: vec![Sprite {
+                        spritesheet: 2,
+                        region: rect!(0, 0, 12, 12),
+                        rotation: None,
+                    }],
+                })
+                .build(),
+        }
+    }
+}
+
+fn unknown(name: &str) -> String {
+    format!(
+        "unknown prefab `{}`, expected one of {}",
+        name,
+        PREFAB_NAMES.join(", ")
+    )
+}
+
+// Every prefab, loaded once with the world so spawning one never touches the disk
+#[derive(Debug, Clone, Default)]
+pub struct PrefabLibrary {
+    prefabs: HashMap<String, Prefab>,
+}
+
+impl PrefabLibrary {
+    pub fn load() -> Result<PrefabLibrary, String> {
+        let mut prefabs = HashMap::new();
+        for name in PREFAB_NAMES {
+            prefabs.insert(name.to_string(), Prefab::load(name)?);
+        }
+        Ok(PrefabLibrary { prefabs })
+    }
+
+    pub fn get(&self, name: &str) -> Result<Prefab, String> {
+        self.prefabs.get(name).cloned().ok_or_else(|| unknown(name))
+    }
+}
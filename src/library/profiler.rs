@@ -0,0 +1,359 @@
+use specs::prelude::*;
+use specs::shred::RunningTime;
+use std::alloc::{GlobalAlloc, Layout, System as Heap};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+
+// Ticks and frames kept for the on-screen graph
+pub const HISTORY: usize = 240;
+
+thread_local! {
+    // Allocations and bytes asked for on this thread so far
+    static ALLOCATIONS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+// The same over all threads, rayon workers of a par_join included
+static ALL_ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALL_BYTES: AtomicU64 = AtomicU64::new(0);
+
+// Counts allocations per thread and overall. Binaries that want allocation counts in the
+// profiler install it with #[global_allocator], without it every count stays 0. Const thread
+// locals have no lazy setup, so using one here can not recurse
+pub struct CountingAlloc;
+
+fn count(bytes: usize) {
+    let _ = ALLOCATIONS.try_with(|counts| {
+        let (allocations, total) = counts.get();
+        counts.set((allocations + 1, total + bytes as u64));
+    });
+    ALL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALL_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        Heap.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        Heap.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        Heap.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        Heap.dealloc(ptr, layout)
+    }
+}
+
+pub fn thread_allocations() -> (u64, u64) {
+    ALLOCATIONS
+        .try_with(|counts| counts.get())
+        .unwrap_or((0, 0))
+}
+
+pub fn all_allocations() -> (u64, u64) {
+    (
+        ALL_ALLOCATIONS.load(Ordering::Relaxed),
+        ALL_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+// One timed piece of work, start is relative to when the profiler was made
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub name: &'static str,
+    pub start: Duration,
+    pub duration: Duration,
+    // Small number standing for the thread it ran on, 0 is the first one seen
+    pub thread: usize,
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TickStats {
+    pub tick: u64,
+    pub duration: Duration,
+    // Time per system in dispatch order, a system that ran more than once is summed
+    pub systems: Vec<(&'static str, Duration)>,
+    pub entities: usize,
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+struct Inner {
+    epoch: Instant,
+    threads: Vec<ThreadId>,
+    // Systems of the tick that is running
+    current: Vec<Span>,
+    ticks: VecDeque<TickStats>,
+    frames: VecDeque<Duration>,
+    // Everything since start_trace, for write_trace
+    trace: Option<Vec<Span>>,
+    entity_counts: Vec<(Duration, usize)>,
+    // Systems run one after another, see Timed
+    sequential: bool,
+}
+
+// Per world timing of every system, whole ticks and rendered frames. Clones share the same
+// data, the dispatcher's Timed systems write to it and the world keeps one as a resource
+#[derive(Clone)]
+pub struct Profiler(Arc<Mutex<Inner>>);
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler(Arc::new(Mutex::new(Inner {
+            epoch: Instant::now(),
+            threads: Vec::new(),
+            current: Vec::new(),
+            ticks: VecDeque::with_capacity(HISTORY),
+            frames: VecDeque::with_capacity(HISTORY),
+            trace: None,
+            entity_counts: Vec::new(),
+            sequential: false,
+        })))
+    }
+}
+
+impl Profiler {
+    // A panicking system poisons the lock, the numbers are still good to show
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_sequential(&self, sequential: bool) {
+        self.lock().sequential = sequential;
+    }
+
+    pub fn sequential(&self) -> bool {
+        self.lock().sequential
+    }
+
+    fn span(inner: &mut Inner, name: &'static str, start: Instant) -> Span {
+        let id = std::thread::current().id();
+        let thread = match inner.threads.iter().position(|t| *t == id) {
+            Some(thread) => thread,
+            None => {
+                inner.threads.push(id);
+                inner.threads.len() - 1
+            }
+        };
+        Span {
+            name,
+            start: start.saturating_duration_since(inner.epoch),
+            duration: start.elapsed(),
+            thread,
+            allocations: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn record(&self, name: &'static str, start: Instant, allocations: u64, bytes: u64) {
+        let mut inner = self.lock();
+        let span = Span {
+            allocations,
+            bytes,
+            ..Profiler::span(&mut inner, name, start)
+        };
+        inner.current.push(span);
+        if let Some(trace) = &mut inner.trace {
+            trace.push(span);
+        }
+    }
+
+    // Closes the tick started at `start`, its systems' spans become one line of history
+    pub fn end_tick(&self, tick: u64, start: Instant, entities: usize) {
+        let mut inner = self.lock();
+        let span = Profiler::span(&mut inner, "tick", start);
+        let mut stats = TickStats {
+            tick,
+            duration: span.duration,
+            entities,
+            ..TickStats::default()
+        };
+        for system in inner.current.drain(..) {
+            match stats
+                .systems
+                .iter_mut()
+                .find(|(name, _)| *name == system.name)
+            {
+                Some((_, duration)) => *duration += system.duration,
+                None => stats.systems.push((system.name, system.duration)),
+            }
+            stats.allocations += system.allocations;
+            stats.bytes += system.bytes;
+        }
+        let allocations = (stats.allocations, stats.bytes);
+
+        if inner.ticks.len() == HISTORY {
+            inner.ticks.pop_front();
+        }
+        inner.ticks.push_back(stats);
+        if let Some(trace) = &mut inner.trace {
+            trace.push(Span {
+                allocations: allocations.0,
+                bytes: allocations.1,
+                ..span
+            });
+            inner.entity_counts.push((span.start, entities));
+        }
+    }
+
+    pub fn record_frame(&self, start: Instant) {
+        let mut inner = self.lock();
+        let span = Profiler::span(&mut inner, "render", start);
+        if inner.frames.len() == HISTORY {
+            inner.frames.pop_front();
+        }
+        inner.frames.push_back(span.duration);
+        if let Some(trace) = &mut inner.trace {
+            trace.push(span);
+        }
+    }
+
+    pub fn ticks(&self) -> Vec<TickStats> {
+        self.lock().ticks.iter().cloned().collect()
+    }
+
+    pub fn frames(&self) -> Vec<Duration> {
+        self.lock().frames.iter().copied().collect()
+    }
+
+    pub fn start_trace(&self) {
+        let mut inner = self.lock();
+        inner.trace = Some(Vec::new());
+        inner.entity_counts.clear();
+    }
+
+    // Chrome trace event JSON, opens in chrome://tracing or Perfetto
+    pub fn write_trace(&self, path: &str) -> Result<(), String> {
+        let inner = self.lock();
+        let spans = inner
+            .trace
+            .as_ref()
+            .ok_or_else(|| "no trace was started".to_string())?;
+
+        let mut events: Vec<String> = Vec::new();
+        for thread in 0..inner.threads.len() {
+            events.push(format!(
+                r#"{{"name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":"thread {}"}}}}"#,
+                thread, thread
+            ));
+        }
+        for span in spans.iter() {
+            let mut event = String::new();
+            let _ = write!(
+                event,
+                r#"{{"name":"{}","cat":"{}","ph":"X","pid":1,"tid":{},"ts":{},"dur":{},"args":{{"allocations":{},"bytes":{}}}}}"#,
+                span.name,
+                match span.name {
+                    "tick" | "render" => span.name,
+                    _ => "system",
+                },
+                span.thread,
+                span.start.as_micros(),
+                span.duration.as_micros(),
+                span.allocations,
+                span.bytes
+            );
+            events.push(event);
+        }
+        for (at, entities) in inner.entity_counts.iter() {
+            events.push(format!(
+                r#"{{"name":"entities","ph":"C","pid":1,"ts":{},"args":{{"entities":{}}}}}"#,
+                at.as_micros(),
+                entities
+            ));
+        }
+
+        let json = format!(
+            "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n{}\n]}}\n",
+            events.join(",\n")
+        );
+        std::fs::write(path, json).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+// Runs a system and reports how long it took and what it allocated to the profiler. With
+// systems run one after another every allocation during the run is the system's, par_join
+// workers included. Run in parallel only the calling thread's are, the counts of systems
+// running next to each other would mix otherwise
+pub struct Timed<S> {
+    system: S,
+    name: &'static str,
+    profiler: Profiler,
+}
+
+impl<'a, S: System<'a>> System<'a> for Timed<S> {
+    type SystemData = S::SystemData;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let counts = match self.profiler.sequential() {
+            true => all_allocations,
+            false => thread_allocations,
+        };
+        let (allocations, bytes) = counts();
+        let start = Instant::now();
+        self.system.run(data);
+        let (allocations_after, bytes_after) = counts();
+        self.profiler.record(
+            self.name,
+            start,
+            allocations_after - allocations,
+            bytes_after - bytes,
+        );
+    }
+
+    fn running_time(&self) -> RunningTime {
+        self.system.running_time()
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        self.system.setup(world);
+    }
+
+    fn dispose(self, world: &mut World) {
+        self.system.dispose(world);
+    }
+}
+
+// DispatcherBuilder::with for a system that should show up in the profiler under its name
+pub trait WithTimed<'a> {
+    fn with_timed<S>(
+        self,
+        profiler: &Profiler,
+        system: S,
+        name: &'static str,
+        dep: &[&str],
+    ) -> Self
+    where
+        S: for<'c> System<'c> + Send + 'a;
+}
+
+impl<'a, 'b> WithTimed<'a> for DispatcherBuilder<'a, 'b> {
+    fn with_timed<S>(self, profiler: &Profiler, system: S, name: &'static str, dep: &[&str]) -> Self
+    where
+        S: for<'c> System<'c> + Send + 'a,
+    {
+        self.with(
+            Timed {
+                system,
+                name,
+                profiler: profiler.clone(),
+            },
+            name,
+            dep,
+        )
+    }
+}
@@ -0,0 +1,342 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+
+use crate::commands::*;
+use crate::entity_components::Direction;
+use crate::level::LevelId;
+
+// Seed, level and per tick input of a deterministic session, enough to replay it exactly.
+//
+// Text format, one tick per line after the header, `-` for a tick without input and an
+// optional `= <checksum>` of the world after the tick to verify replays against. The level
+// line names the file and the checksum it had, replays refuse to run on a changed level:
+//   seed 42
+//   level assets/levels/default.level 5b0e7d21c4a9f3e8
+//   move 1,0 jump = 8c2f1e07a9b3d415
+//   move 1,0 shoot 1,-1 use
+//   -
+// Hand written scripts can hold a line for several ticks with `* <count>`, a checksum after
+// it is checked on the last of them. Without a level line they play the default level:
+//   move 1,0 * 120
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct InputRecording {
+    pub seed: u64,
+    pub level: Option<LevelId>,
+    pub ticks: Vec<RecordedTick>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RecordedTick {
+    pub input: TickInput,
+    pub checksum: Option<u64>,
+}
+
+impl InputRecording {
+    pub fn new(seed: u64) -> InputRecording {
+        InputRecording {
+            seed,
+            level: None,
+            ticks: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, input: &TickInput, checksum: Option<u64>) {
+        self.ticks.push(RecordedTick {
+            input: input.clone(),
+            checksum,
+        });
+    }
+
+    pub fn load(path: &str) -> Result<InputRecording, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        InputRecording::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_text()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = header_text(self.seed, self.level.as_ref());
+        for tick in self.ticks.iter() {
+            out.push_str(&tick_text(&tick.input, tick.checksum));
+        }
+        out
+    }
+
+    pub fn parse(source: &str) -> Result<InputRecording, String> {
+        let mut seed = None;
+        let mut level = None;
+        let mut ticks = Vec::new();
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line = match line.split('#').next() {
+                Some(line) => line.trim(),
+                None => continue,
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", line_no + 1, msg);
+
+            if let Some(rest) = line.strip_prefix("seed ") {
+                seed = Some(
+                    rest.trim()
+                        .parse::<u64>()
+                        .map_err(|_| err("seed is not a valid number"))?,
+                );
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("level ") {
+                let (path, checksum) = rest
+                    .trim()
+                    .rsplit_once(' ')
+                    .ok_or_else(|| err("expected `level <path> <checksum>`"))?;
+                level = Some(LevelId {
+                    path: path.trim().to_string(),
+                    checksum: u64::from_str_radix(checksum, 16)
+                        .map_err(|_| err("level checksum is not a hex number"))?,
+                });
+                continue;
+            }
+            if seed.is_none() {
+                return Err(err("expected `seed <number>` before the first tick"));
+            }
+            let (line, checksum) = match line.split_once('=') {
+                Some((line, checksum)) => (
+                    line.trim(),
+                    Some(
+                        u64::from_str_radix(checksum.trim(), 16)
+                            .map_err(|_| err("checksum is not a hex number"))?,
+                    ),
+                ),
+                None => (line, None),
+            };
+            let (line, repeat) = match line.split_once('*') {
+                Some((line, count)) => (
+                    line.trim(),
+                    count
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or_else(|| err("repeat count is not a positive number"))?,
+                ),
+                None => (line, 1),
+            };
+            let mut input = TickInput::default();
+            let mut words = line.split_whitespace();
+            if line == "-" {
+                words.next();
+            }
+            while let Some(word) = words.next() {
+                match word {
+                    "move" => {
+                        let dir = parse_direction(words.next()).map_err(|e| err(&e))?;
+                        input.movement = Some(MovementCommand::Move(dir));
+                    }
+                    "shoot" => {
+                        let dir = parse_direction(words.next()).map_err(|e| err(&e))?;
+                        input.actions.push(ActionCommand::Shoot(dir));
+                    }
+                    "use" => input.actions.push(ActionCommand::Use),
+                    "jump" => input.actions.push(ActionCommand::Jump),
+                    other => return Err(err(&format!("unknown input `{}`", other))),
+                }
+            }
+            for _ in 1..repeat {
+                ticks.push(RecordedTick {
+                    input: input.clone(),
+                    checksum: None,
+                });
+            }
+            ticks.push(RecordedTick { input, checksum });
+        }
+
+        Ok(InputRecording {
+            seed: seed.ok_or_else(|| "recording has no seed".to_string())?,
+            level,
+            ticks,
+        })
+    }
+}
+
+// Writes the recording while it is made, every tick is on disk as soon as it is recorded so
+// a crash or an error that ends the game early keeps everything up to it
+pub struct RecordingWriter {
+    path: String,
+    file: LineWriter<File>,
+}
+
+impl RecordingWriter {
+    pub fn create(path: &str, seed: u64, level: &LevelId) -> Result<RecordingWriter, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut writer = RecordingWriter {
+            path: path.to_string(),
+            file: LineWriter::new(file),
+        };
+        writer.write(&header_text(seed, Some(level)))?;
+        Ok(writer)
+    }
+
+    pub fn record(&mut self, input: &TickInput, checksum: Option<u64>) -> Result<(), String> {
+        self.write(&tick_text(input, checksum))
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), String> {
+        self.file
+            .write_all(text.as_bytes())
+            .map_err(|e| format!("{}: {}", self.path, e))
+    }
+}
+
+fn header_text(seed: u64, level: Option<&LevelId>) -> String {
+    let mut out = format!("seed {}\n", seed);
+    if let Some(level) = level {
+        out.push_str(&format!("level {} {:016x}\n", level.path, level.checksum));
+    }
+    out
+}
+
+fn tick_text(input: &TickInput, checksum: Option<u64>) -> String {
+    let mut words: Vec<String> = Vec::new();
+    if let Some(MovementCommand::Move(dir)) = input.movement {
+        words.push(format!("move {}", direction_text(dir)));
+    }
+    for action in input.actions.iter() {
+        words.push(match action {
+            ActionCommand::Shoot(dir) => format!("shoot {}", direction_text(*dir)),
+            ActionCommand::Use => "use".to_string(),
+            ActionCommand::Jump => "jump".to_string(),
+        });
+    }
+    let mut out = if words.is_empty() {
+        "-".to_string()
+    } else {
+        words.join(" ")
+    };
+    if let Some(checksum) = checksum {
+        out.push_str(&format!(" = {:016x}", checksum));
+    }
+    out.push('\n');
+    out
+}
+
+fn direction_text(dir: Direction) -> String {
+    match dir {
+        Direction::MoveDelta { x, y } => format!("{},{}", x, y),
+    }
+}
+
+fn parse_direction(word: Option<&str>) -> Result<Direction, String> {
+    let word = word.ok_or_else(|| "missing direction".to_string())?;
+    let (x, y) = word
+        .split_once(',')
+        .ok_or_else(|| format!("`{}` is not an x,y direction", word))?;
+    let x = x
+        .parse::<i8>()
+        .map_err(|_| format!("`{}` is not a valid number", x))?;
+    let y = y
+        .parse::<i8>()
+        .map_err(|_| format!("`{}` is not a valid number", y))?;
+    Ok(Direction::MoveDelta { x, y })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(input: TickInput, checksum: Option<u64>) -> RecordedTick {
+        RecordedTick { input, checksum }
+    }
+
+    fn moving(x: i8) -> TickInput {
+        TickInput {
+            movement: Some(MovementCommand::Move(Direction::MoveDelta { x, y: 0 })),
+            actions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_the_documented_format() {
+        let recording = InputRecording::parse(
+            "seed 42\n\
+             level assets/levels/default.level 5b0e7d21c4a9f3e8\n\
+             move 1,0 jump = 8c2f1e07a9b3d415\n\
+             move 1,0 shoot 1,-1 use\n\
+             # a comment\n\
+             -\n",
+        )
+        .unwrap();
+        assert_eq!(recording.seed, 42);
+        assert_eq!(
+            recording.level,
+            Some(LevelId {
+                path: "assets/levels/default.level".to_string(),
+                checksum: 0x5b0e7d21c4a9f3e8,
+            })
+        );
+        let mut jump = moving(1);
+        jump.actions.push(ActionCommand::Jump);
+        let mut busy = moving(1);
+        busy.actions
+            .push(ActionCommand::Shoot(Direction::MoveDelta { x: 1, y: -1 }));
+        busy.actions.push(ActionCommand::Use);
+        assert_eq!(
+            recording.ticks,
+            vec![
+                tick(jump, Some(0x8c2f1e07a9b3d415)),
+                tick(busy, None),
+                tick(TickInput::default(), None),
+            ]
+        );
+
+        // Written back out it parses to the same recording
+        assert_eq!(InputRecording::parse(&recording.to_text()), Ok(recording));
+    }
+
+    #[test]
+    fn repeats_check_the_last_tick() {
+        let recording = InputRecording::parse("seed 1\nmove -1,0 * 3 = ff\n- * 2").unwrap();
+        assert_eq!(recording.level, None);
+        assert_eq!(
+            recording.ticks,
+            vec![
+                tick(moving(-1), None),
+                tick(moving(-1), None),
+                tick(moving(-1), Some(0xff)),
+                tick(TickInput::default(), None),
+                tick(TickInput::default(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = |source: &str| InputRecording::parse(source).unwrap_err();
+        assert_eq!(err(""), "recording has no seed");
+        assert_eq!(
+            err("move 1,0"),
+            "line 1: expected `seed <number>` before the first tick"
+        );
+        assert_eq!(err("seed -1"), "line 1: seed is not a valid number");
+        assert_eq!(
+            err("seed 1\nlevel default.level"),
+            "line 2: expected `level <path> <checksum>`"
+        );
+        assert_eq!(
+            err("seed 1\n- = xyz"),
+            "line 2: checksum is not a hex number"
+        );
+        assert_eq!(
+            err("seed 1\n- * 0"),
+            "line 2: repeat count is not a positive number"
+        );
+        assert_eq!(err("seed 1\ndance"), "line 2: unknown input `dance`");
+        assert_eq!(err("seed 1\nmove 1"), "line 2: `1` is not an x,y direction");
+        assert_eq!(
+            err("seed 1\nshoot 1,200"),
+            "line 2: `200` is not a valid number"
+        );
+    }
+}
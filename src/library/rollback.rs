@@ -0,0 +1,382 @@
+use std::collections::BTreeMap;
+
+use specs::prelude::*;
+
+use crate::checksum::portable_checksum;
+use crate::commands::*;
+use crate::events::SoundQueue;
+use crate::level::LevelId;
+use crate::netcode::*;
+use crate::simulation::Simulation;
+use crate::snapshot::*;
+
+const INPUT_MESSAGE: u8 = 1;
+const CHECKSUM_MESSAGE: u8 = 2;
+const HELLO_MESSAGE: u8 = 3;
+// Only every nth confirmed frame is compared, enough to notice a desync quickly
+const CHECKSUM_INTERVAL: u64 = 30;
+// Most inputs one message carries, the rest follow in the next one
+const MAX_INPUTS_PER_MESSAGE: u64 = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RollbackConfig {
+    // Ticks between reading local input and simulating it, hides that much latency
+    // without any rollback
+    pub input_delay: u64,
+    // How far ahead of the last confirmed remote input we predict before waiting
+    pub max_rollback: u64,
+}
+
+impl Default for RollbackConfig {
+    fn default() -> Self {
+        RollbackConfig {
+            input_delay: 2,
+            max_rollback: 8,
+        }
+    }
+}
+
+// What a simulated frame was run with, and the checksum of the world after it
+struct Simulated {
+    inputs: Vec<TickInput>,
+    checksum: u64,
+}
+
+// GGPO style session for two peers. Local input is sent straight away, the remote
+// player's input is predicted until it arrives, and a wrong guess rewinds the world to
+// the frame it was used in and simulates forward again. Nothing is simulated before both
+// peers have said hello with the level they play, different levels end the session
+pub struct RollbackSession<T: Transport> {
+    pub config: RollbackConfig,
+    pub local_slot: usize,
+    transport: T,
+    level: LevelId,
+    // What the peer said hello with, None until it has
+    peer_level: Option<LevelId>,
+    // Known inputs by frame, one per slot
+    inputs: BTreeMap<u64, [Option<TickInput>; 2]>,
+    // First frame whose input is still missing, per slot
+    received_until: [u64; 2],
+    // How far the peer has received our input
+    acked: u64,
+    simulated: BTreeMap<u64, Simulated>,
+    // World before each frame that may still have to be simulated again
+    snapshots: BTreeMap<u64, WorldSnapshot>,
+    remote_checksums: BTreeMap<u64, u64>,
+    checked_until: u64,
+    // First frame both peers have confirmed but disagree about
+    pub desync: Option<u64>,
+    // Frames whose checksums were compared with the peer's
+    pub checked: u64,
+    pub rollbacks: u64,
+    pub resimulated: u64,
+    // Packets from the peer that could not be read and were skipped
+    pub dropped: u64,
+}
+
+impl<T: Transport> RollbackSession<T> {
+    pub fn new(
+        local_slot: usize,
+        config: RollbackConfig,
+        transport: T,
+        level: LevelId,
+    ) -> RollbackSession<T> {
+        // Nobody has input for the frames before the delay runs out
+        let inputs = (0..config.input_delay)
+            .map(|frame| {
+                (
+                    frame,
+                    [Some(TickInput::default()), Some(TickInput::default())],
+                )
+            })
+            .collect();
+        RollbackSession {
+            config,
+            local_slot,
+            transport,
+            level,
+            peer_level: None,
+            inputs,
+            received_until: [config.input_delay; 2],
+            acked: 0,
+            simulated: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            checked_until: 0,
+            desync: None,
+            checked: 0,
+            rollbacks: 0,
+            resimulated: 0,
+            dropped: 0,
+        }
+    }
+
+    fn remote_slot(&self) -> usize {
+        1 - self.local_slot
+    }
+
+    // Every frame before this one has the real input of both players
+    pub fn confirmed_frame(&self) -> u64 {
+        self.received_until[0].min(self.received_until[1])
+    }
+
+    // Runs one frame with the given local input. False means the remote player fell too
+    // far behind, nothing was simulated and the same input should be passed again
+    pub fn advance(
+        &mut self,
+        sim: &mut Simulation,
+        local_input: &TickInput,
+    ) -> Result<bool, String> {
+        let rollback_to = self.receive(sim.tick)?;
+        if self.peer_level.is_none() {
+            self.send_hello()?;
+            return Ok(false);
+        }
+        if let Some(frame) = rollback_to {
+            self.rollback(sim, frame);
+        }
+
+        let remote = self.remote_slot();
+        let stalled = sim.tick >= self.received_until[remote] + self.config.max_rollback;
+        if !stalled {
+            let frame = sim.tick + self.config.input_delay;
+            self.inputs.entry(frame).or_default()[self.local_slot] = Some(local_input.clone());
+            self.update_received(self.local_slot);
+            self.simulate(sim);
+        }
+
+        self.send_inputs()?;
+        self.check_confirmed(sim.tick)?;
+        self.prune(sim.tick);
+        Ok(!stalled)
+    }
+
+    // Takes in everything the peer sent, returns the earliest frame that was simulated
+    // with a guess that turned out wrong
+    fn receive(&mut self, current: u64) -> Result<Option<u64>, String> {
+        let mut rollback_to: Option<u64> = None;
+        while let Some(packet) = self.transport.receive()? {
+            // A corrupt or stray datagram is skipped, the peer resends its inputs anyway
+            if self
+                .read_packet(&packet, current, &mut rollback_to)
+                .is_err()
+            {
+                self.dropped += 1;
+            }
+        }
+        match &self.peer_level {
+            Some(peer) if peer.checksum != self.level.checksum => {
+                Err(format!("the peer plays {}, this side {}", peer, self.level))
+            }
+            _ => Ok(rollback_to),
+        }
+    }
+
+    // Asks for an answer while the peer's hello is still missing
+    fn send_hello(&mut self) -> Result<(), String> {
+        let mut w = Writer::default();
+        w.u8(HELLO_MESSAGE);
+        w.bool(self.peer_level.is_none());
+        w.u64(self.level.checksum);
+        w.str(&self.level.path);
+        self.transport.send(&w.bytes)
+    }
+
+    fn read_packet(
+        &mut self,
+        packet: &[u8],
+        current: u64,
+        rollback_to: &mut Option<u64>,
+    ) -> Result<(), String> {
+        let mut r = Reader::new(packet);
+        match r.u8()? {
+            INPUT_MESSAGE => {
+                let slot = r.u8()? as usize;
+                if slot != self.remote_slot() {
+                    return Err(format!("input for slot {} from the peer", slot));
+                }
+                let acked = r.u64()?;
+                let start = r.u64()?;
+                let count = r.u8()? as u64;
+                // Read everything first, a packet cut short changes nothing
+                let mut received = Vec::new();
+                for _ in 0..count {
+                    received.push(read_input(&mut r)?);
+                }
+                self.acked = self.acked.max(acked);
+                for (frame, input) in (start..).zip(received) {
+                    let known = self.inputs.entry(frame).or_default();
+                    if known[slot].is_some() || frame < self.received_until[slot] {
+                        continue;
+                    }
+                    let mispredicted = frame < current
+                        && self
+                            .simulated
+                            .get(&frame)
+                            .is_some_and(|s| s.inputs[slot] != input);
+                    if mispredicted {
+                        *rollback_to = Some(rollback_to.map_or(frame, |f| f.min(frame)));
+                    }
+                    known[slot] = Some(input);
+                }
+                self.update_received(slot);
+            }
+            CHECKSUM_MESSAGE => {
+                let frame = r.u64()?;
+                self.remote_checksums.insert(frame, r.u64()?);
+            }
+            // The peer keeps asking until an answer gets through
+            HELLO_MESSAGE => {
+                let answer = r.bool()?;
+                let checksum = r.u64()?;
+                let path = r.str()?;
+                self.peer_level = Some(LevelId { path, checksum });
+                if answer {
+                    self.send_hello()?;
+                }
+            }
+            other => return Err(format!("unknown message {}", other)),
+        }
+        Ok(())
+    }
+
+    fn update_received(&mut self, slot: usize) {
+        while self
+            .inputs
+            .get(&self.received_until[slot])
+            .is_some_and(|known| known[slot].is_some())
+        {
+            self.received_until[slot] += 1;
+        }
+    }
+
+    // The real input where it is known, otherwise the player keeps moving the way they
+    // last did. Presses are never guessed, a missed shot is cheaper than a phantom one
+    fn input_for(&self, frame: u64, slot: usize) -> TickInput {
+        if let Some(input) = self
+            .inputs
+            .get(&frame)
+            .and_then(|known| known[slot].clone())
+        {
+            return input;
+        }
+        let movement = self
+            .inputs
+            .range(..frame)
+            .rev()
+            .find_map(|(_, known)| known[slot].as_ref())
+            .and_then(|input| input.movement);
+        TickInput {
+            movement,
+            actions: Vec::new(),
+        }
+    }
+
+    fn simulate(&mut self, sim: &mut Simulation) {
+        let frame = sim.tick;
+        // Both peers hand out the same ids, whatever they rolled back before
+        sort_free_ids(&mut sim.world);
+        self.snapshots
+            .insert(frame, WorldSnapshot::capture(&sim.world));
+        let inputs = vec![self.input_for(frame, 0), self.input_for(frame, 1)];
+        sim.step_players(&inputs);
+        self.simulated.insert(
+            frame,
+            Simulated {
+                inputs,
+                checksum: portable_checksum(&sim.world),
+            },
+        );
+    }
+
+    fn rollback(&mut self, sim: &mut Simulation, frame: u64) {
+        let snapshot = match self.snapshots.remove(&frame) {
+            Some(snapshot) => snapshot,
+            // Already pruned, which only happens for frames both sides had confirmed
+            None => return,
+        };
+        let current = sim.tick;
+        // The sounds of the frames being redone were already played once
+        let sounds = std::mem::take(&mut *sim.world.write_resource::<SoundQueue>());
+
+        snapshot.restore(&mut sim.world);
+        sim.tick = frame;
+        while sim.tick < current {
+            self.simulate(sim);
+            self.resimulated += 1;
+        }
+
+        *sim.world.write_resource::<SoundQueue>() = sounds;
+        self.rollbacks += 1;
+    }
+
+    // Resends everything the peer has not acknowledged yet, so lost packets only cost time
+    fn send_inputs(&mut self) -> Result<(), String> {
+        let until = self.received_until[self.local_slot];
+        let start = self.acked.max(until.saturating_sub(MAX_INPUTS_PER_MESSAGE));
+        if start >= until {
+            return Ok(());
+        }
+
+        let mut w = Writer::default();
+        w.u8(INPUT_MESSAGE);
+        w.u8(self.local_slot as u8);
+        w.u64(self.received_until[self.remote_slot()]);
+        w.u64(start);
+        w.u8((until - start) as u8);
+        for frame in start..until {
+            write_input(&mut w, &self.input_for(frame, self.local_slot));
+        }
+        self.transport.send(&w.bytes)
+    }
+
+    fn check_confirmed(&mut self, current: u64) -> Result<(), String> {
+        let confirmed = self.confirmed_frame().min(current);
+        for frame in self.checked_until..confirmed {
+            if frame % CHECKSUM_INTERVAL != 0 {
+                continue;
+            }
+            let checksum = match self.simulated.get(&frame) {
+                Some(simulated) => simulated.checksum,
+                None => continue,
+            };
+            let mut w = Writer::default();
+            w.u8(CHECKSUM_MESSAGE);
+            w.u64(frame);
+            w.u64(checksum);
+            self.transport.send(&w.bytes)?;
+        }
+        self.checked_until = self.checked_until.max(confirmed);
+
+        let compared: Vec<u64> = self
+            .remote_checksums
+            .range(..self.checked_until)
+            .map(|(frame, _)| *frame)
+            .collect();
+        for frame in compared {
+            let remote = self.remote_checksums.remove(&frame);
+            let local = self.simulated.get(&frame).map(|s| s.checksum);
+            if let (Some(remote), Some(local)) = (remote, local) {
+                self.checked += 1;
+                if remote != local && self.desync.is_none() {
+                    self.desync = Some(frame);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Confirmed frames can never be rolled back to again. Checksums of the last few
+    // confirmed frames wait a while for the peer's to arrive. Without latency input can be
+    // confirmed for frames not simulated yet, those keep it
+    fn prune(&mut self, current: u64) {
+        let confirmed = self.confirmed_frame().min(current);
+        self.snapshots = self.snapshots.split_off(&confirmed);
+        self.simulated = self
+            .simulated
+            .split_off(&confirmed.saturating_sub(CHECKSUM_INTERVAL * 4));
+        // Prediction repeats the last known input and unacknowledged ones are resent
+        let oldest_input = self.acked.min(confirmed.saturating_sub(1));
+        self.inputs = self.inputs.split_off(&oldest_input);
+    }
+}
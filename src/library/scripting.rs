@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+use sdl2::rect::Point;
+
+use crate::commands::ActionCommand;
+use crate::entity_components::Direction;
+use crate::rng::Rng;
+
+// Operations one hook may run before it is stopped, so a runaway loop can not hang the game
+const MAX_OPERATIONS: u64 = 100_000;
+
+struct LoadedScript {
+    // None while the file does not compile, fixed files are picked up by reload_changed
+    ast: Option<AST>,
+    modified: Option<SystemTime>,
+}
+
+// Compiles and runs entity scripts. Besides the hooks, scripts get these functions:
+//   spawn_prefab(name, x, y)   builds a prefab at the end of the tick
+//   random(n)                  0 until n from the simulation's seeded rng
+//   this.steer(x, y)           accelerates towards a direction like AI movement does
+//   this.jump()  this.shoot(x, y)  this.use_item()
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: HashMap<String, LoadedScript>,
+    spawns: Arc<Mutex<Vec<(String, Point)>>>,
+    rng: Arc<Mutex<Rng>>,
+    // Compile and hook errors since the last take_log
+    log: Vec<String>,
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        ScriptHost::new()
+    }
+}
+
+impl ScriptHost {
+    pub fn new() -> ScriptHost {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let spawns: Arc<Mutex<Vec<(String, Point)>>> = Arc::default();
+        let queue = spawns.clone();
+        engine.register_fn("spawn_prefab", move |name: &str, x: i64, y: i64| {
+            if let Ok(mut queue) = queue.lock() {
+                queue.push((name.to_string(), Point::new(x as i32, y as i32)));
+            }
+        });
+
+        let rng: Arc<Mutex<Rng>> = Arc::default();
+        let shared = rng.clone();
+        engine.register_fn("random", move |n: i64| -> i64 {
+            match shared.lock() {
+                Ok(mut rng) if n > 0 => (rng.next_u32() as u64 % n as u64) as i64,
+                _ => 0,
+            }
+        });
+
+        engine.register_fn("steer", |me: &mut Map, x: i64, y: i64| {
+            me.insert("steer".into(), Dynamic::from(direction(x, y)));
+        });
+        engine.register_fn("jump", |me: &mut Map| push_action(me, ActionCommand::Jump));
+        engine.register_fn("shoot", |me: &mut Map, x: i64, y: i64| {
+            push_action(me, ActionCommand::Shoot(direction(x, y)))
+        });
+        engine.register_fn("use_item", |me: &mut Map| {
+            push_action(me, ActionCommand::Use)
+        });
+
+        ScriptHost {
+            engine,
+            scripts: HashMap::new(),
+            spawns,
+            rng,
+            log: Vec::new(),
+        }
+    }
+
+    // Compiles a script the first time an entity uses it
+    pub fn load(&mut self, path: &str) {
+        if !self.scripts.contains_key(path) {
+            let script = self.compile(path);
+            self.scripts.insert(path.to_string(), script);
+        }
+    }
+
+    // Compiles every script again whose file changed since it was loaded
+    pub fn reload_changed(&mut self) {
+        let changed: Vec<String> = self
+            .scripts
+            .iter()
+            .filter(|(path, script)| modified(path) != script.modified)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in changed {
+            let script = self.compile(&path);
+            if script.ast.is_some() {
+                self.log.push(format!("reloaded {}", path));
+            }
+            self.scripts.insert(path, script);
+        }
+    }
+
+    fn compile(&mut self, path: &str) -> LoadedScript {
+        let modified = modified(path);
+        let ast = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| self.engine.compile(source).map_err(|e| e.to_string()));
+        match ast {
+            Ok(ast) => LoadedScript {
+                ast: Some(ast),
+                modified,
+            },
+            Err(e) => {
+                self.log.push(format!("{}: {}", path, e));
+                LoadedScript {
+                    ast: None,
+                    modified,
+                }
+            }
+        }
+    }
+
+    // Runs a hook with `this` bound to the entity. Scripts that do not define the hook are
+    // skipped, errors are logged and leave the entity as far as the hook got
+    pub fn call(&mut self, path: &str, hook: &str, this: &mut Dynamic, args: impl FuncArgs) {
+        let ast = match self.scripts.get(path).and_then(|s| s.ast.as_ref()) {
+            Some(ast) => ast,
+            None => return,
+        };
+        let mut values = Vec::new();
+        args.parse(&mut values);
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == hook && f.params.len() == values.len())
+        {
+            return;
+        }
+
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(this);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            ast,
+            hook,
+            values,
+        );
+        if let Err(e) = result {
+            self.log.push(format!("{} {}: {}", path, hook, e));
+        }
+    }
+
+    pub fn take_log(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+
+    // Scripts draw from the simulation's rng, lent to them for the duration of a tick
+    pub fn lend_rng(&self, rng: &Rng) {
+        if let Ok(mut shared) = self.rng.lock() {
+            *shared = rng.clone();
+        }
+    }
+
+    pub fn return_rng(&self, rng: &mut Rng) {
+        if let Ok(shared) = self.rng.lock() {
+            *rng = shared.clone();
+        }
+    }
+
+    pub fn take_spawns(&self) -> Vec<(String, Point)> {
+        self.spawns
+            .lock()
+            .map(|mut spawns| std::mem::take(&mut *spawns))
+            .unwrap_or_default()
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn direction(x: i64, y: i64) -> Direction {
+    Direction::MoveDelta {
+        x: x.signum() as i8,
+        y: y.signum() as i8,
+    }
+}
+
+fn push_action(me: &mut Map, action: ActionCommand) {
+    let mut actions = me
+        .remove("actions")
+        .and_then(|a| a.try_cast::<Array>())
+        .unwrap_or_default();
+    actions.push(Dynamic::from(action));
+    me.insert("actions".into(), actions.into());
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+
+use specs::prelude::*;
+
+use crate::entity_ai::AI;
+use crate::entity_animation::Animation;
+use crate::entity_components::*;
+use crate::entity_flags::*;
+use crate::entity_platform::PlatformPath;
+use crate::entity_script::Script;
+use crate::particles::ParticlePool;
+use crate::rng::Rng;
+
+// Copy of one component storage, able to write itself back into a world
+trait StoredComponents {
+    fn restore(&self, world: &World, map: &dyn Fn(Entity) -> Option<Entity>);
+}
+
+struct Stored<T> {
+    values: Vec<(Entity, T)>,
+    remap: fn(&mut T, &dyn Fn(Entity) -> Option<Entity>),
+}
+
+impl<T: Component + Clone> StoredComponents for Stored<T> {
+    fn restore(&self, world: &World, map: &dyn Fn(Entity) -> Option<Entity>) {
+        let mut storage = world.write_storage::<T>();
+        storage.clear();
+        for (entity, value) in self.values.iter() {
+            if let Some(entity) = map(*entity) {
+                let mut value = value.clone();
+                (self.remap)(&mut value, map);
+                // Only fails for dead entities, which map never returns
+                let _ = storage.insert(entity, value);
+            }
+        }
+    }
+}
+
+fn store<T: Component + Clone>(world: &World) -> Box<dyn StoredComponents> {
+    let storage = world.read_storage::<T>();
+    Box::new(Stored {
+        values: (&world.entities(), &storage)
+            .join()
+            .map(|(e, v)| (e, v.clone()))
+            .collect(),
+        remap: |_, _| {},
+    })
+}
+
+fn store_refs<T: Component + Clone + EntityRefs>(world: &World) -> Box<dyn StoredComponents> {
+    let storage = world.read_storage::<T>();
+    Box::new(Stored {
+        values: (&world.entities(), &storage)
+            .join()
+            .map(|(e, v)| (e, v.clone()))
+            .collect(),
+        remap: |value, map| value.remap_entities(map),
+    })
+}
+
+// Every id that was handed out before and is free again, taken back off the allocator
+fn take_free_ids(world: &mut World) -> Vec<Entity> {
+    world.maintain();
+    let free = {
+        let entities = world.entities();
+        let alive: BitSet = (&entities).join().map(|e| e.id()).collect();
+        // Ids are handed out in order, so the first id neither alive nor dead was never used.
+        // Dead ids carry a negative generation
+        (0..)
+            .map(|id| (alive.contains(id), entities.entity(id).gen().id() < 0))
+            .take_while(|(alive, dead)| *alive || *dead)
+            .filter(|(_, dead)| *dead)
+            .count()
+    };
+    (0..free).map(|_| world.create_entity().build()).collect()
+}
+
+// Frees the ids highest first, the allocator hands out the last freed id first
+fn release_ids(world: &mut World, mut ids: Vec<Entity>) {
+    ids.sort_unstable_by_key(|e| std::cmp::Reverse(e.id()));
+    // Fails only for entities that are already gone
+    let _ = world.delete_entities(&ids);
+}
+
+// New entities take the lowest free ids from here on, so which ids they get depends only
+// on the entities alive now and not on what was created and deleted before. Join order
+// follows the ids, two peers that rolled back differently still join in the same order
+pub fn sort_free_ids(world: &mut World) {
+    let ids = take_free_ids(world);
+    release_ids(world, ids);
+}
+
+// Everything the simulation needs to continue from a tick, taken between ticks.
+// Entities that died since are recreated under their old ids and references to them
+// rewritten, entities spawned since are deleted
+pub struct WorldSnapshot {
+    entities: Vec<Entity>,
+    storages: Vec<Box<dyn StoredComponents>>,
+    rng: Rng,
+    particles: ParticlePool,
+}
+
+impl WorldSnapshot {
+    pub fn capture(world: &World) -> WorldSnapshot {
+        let storages = vec![
+            store::<Position>(world),
+            store::<Velocity>(world),
+            store::<Collideable>(world),
+            store::<GravityAfflicted>(world),
+            store::<SpriteVec>(world),
+            store::<DecayLife>(world),
+            store_refs::<Health>(world),
+            store_refs::<Damage>(world),
+            store::<Resistances>(world),
+            store::<Invulnerability>(world),
+            store_refs::<ProjectileProperties>(world),
+            store::<Team>(world),
+            store::<Score>(world),
+            store::<ActionQueue>(world),
+            store::<Weapon>(world),
+            store::<Inventory>(world),
+            store::<Pickup>(world),
+            store::<ParticleEmitter>(world),
+            store::<PlatformerController>(world),
+            store::<Mass>(world),
+            store::<Friction>(world),
+            store::<GravityScale>(world),
+            store::<GravityZone>(world),
+            store::<RigidBody>(world),
+            store::<NetPlayer>(world),
+            store_refs::<PlatformPath>(world),
+            store::<AI>(world),
+            store::<Animation>(world),
+            store_refs::<Script>(world),
+            store::<KeyboardControlled>(world),
+            store::<Player>(world),
+            store::<SingleDamage>(world),
+            store::<GodMode>(world),
+        ];
+
+        WorldSnapshot {
+            entities: world.entities().join().collect(),
+            storages,
+            rng: (*world.read_resource::<Rng>()).clone(),
+            particles: (*world.read_resource::<ParticlePool>()).clone(),
+        }
+    }
+
+    pub fn restore(&self, world: &mut World) {
+        let kept: HashSet<Entity> = self.entities.iter().copied().collect();
+        let spawned: Vec<Entity> = world
+            .entities()
+            .join()
+            .filter(|e| !kept.contains(e))
+            .collect();
+        // Fails only for entities that are already gone
+        let _ = world.delete_entities(&spawned);
+
+        // With the spawned ones gone every id a dead entity had is free again
+        let mut map: HashMap<Entity, Entity> = HashMap::new();
+        let mut dead: HashMap<u32, Entity> = HashMap::new();
+        for entity in self.entities.iter() {
+            if world.is_alive(*entity) {
+                map.insert(*entity, *entity);
+            } else {
+                dead.insert(entity.id(), *entity);
+            }
+        }
+        let mut unused = Vec::new();
+        for entity in take_free_ids(world) {
+            match dead.get(&entity.id()) {
+                Some(old) => {
+                    map.insert(*old, entity);
+                }
+                None => unused.push(entity),
+            }
+        }
+        release_ids(world, unused);
+
+        // References to entities that were already dead when the snapshot was taken stay
+        // dangling, just like on a peer that never rolled back
+        let lookup = |e: Entity| Some(map.get(&e).copied().unwrap_or(e));
+        for storage in self.storages.iter() {
+            storage.restore(world, &lookup);
+        }
+
+        *world.write_resource::<Rng>() = self.rng.clone();
+        *world.write_resource::<ParticlePool>() = self.particles.clone();
+        world.maintain();
+    }
+}
@@ -1,40 +1,81 @@
-mod entities;
-mod library;
-mod systems;
-
-use collisionsys::CollisionSys;
-use entities::*;
-use library::globalcomponents::*;
-use library::*;
+use genjin::entities::*;
+use genjin::headless;
+use genjin::library::globalcomponents::*;
+use genjin::library::*;
+use genjin::options::parse_args;
+use genjin::simulation::Simulation;
+use genjin::systems::audio::AudioManager;
+use genjin::systems::*;
+use genjin::*;
+use profiler::CountingAlloc;
 use sdl2::gfx::framerate::FPSManager;
-use sdl2::rect::Rect;
-use std::time::Duration;
-use systems::actionsys::ActionSys;
-use systems::damagesys::DamageSys;
-use systems::decaysys::DecaySys;
-use systems::gravitysys::GravitySys;
-use systems::projectilesys::ProjectileSys;
-use systems::*;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use commands::*;
+use console::*;
+use debugdraw::DebugDraw;
+use editor::*;
 use entity_components::*;
-use entity_flags::*;
-use keyboard::Keyboard;
-use physics::Physics;
+use events::*;
+use inspector::Inspector;
+use level::*;
+use netclient::NetClient;
+use netcode::*;
+use profiler::Profiler;
+use replay::*;
+use rng::*;
+use rollback::*;
 use sdl2::event::Event;
 use sdl2::image::{self, InitFlag, LoadTexture};
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
-use sdl2::render::TextureCreator;
+use sdl2::render::{TextureCreator, WindowCanvas};
 use specs::prelude::*;
 
-pub const WINDOW_HEIGHT: u32 = 600;
-pub const WINDOW_WIDTH: u32 = 800;
-pub const SIM_RATE: Duration = Duration::new(0, 1_000_000_000u32 / 120);
-pub const FRAME_RATE: Duration = Duration::new(0, 1_000_000_000u32 / 60);
+// Gives the profiler its allocation counts
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// What the window shows: a game of its own, one peer of a rollback session, or a client of
+// an authoritative server
+enum Game<'a, 'b> {
+    Local(Simulation<'a, 'b>),
+    Netplay(Simulation<'a, 'b>, RollbackSession<UdpTransport>),
+    Client(NetClient<'a, 'b, UdpTransport>),
+}
+
+impl Game<'_, '_> {
+    // False if the tick could not run yet and its input has to be passed again
+    fn tick(&mut self, input: &TickInput) -> Result<bool, String> {
+        match self {
+            Game::Local(sim) => {
+                sim.step(input);
+                Ok(true)
+            }
+            Game::Netplay(sim, session) => session.advance(sim, input),
+            Game::Client(client) => client.tick(input).map(|_| true),
+        }
+    }
+
+    fn world(&mut self) -> &mut World {
+        match self {
+            Game::Local(sim) | Game::Netplay(sim, _) => &mut sim.world,
+            Game::Client(client) => &mut client.world,
+        }
+    }
+}
 
 pub fn main() -> Result<(), String> {
+    let options = parse_args()?;
+    if headless::run(&options)? {
+        return Ok(());
+    }
+    if options.record.is_some() && (options.netplay.is_some() || options.connect.is_some()) {
+        return Err("--record only works for a local game".to_string());
+    }
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let _image_context = image::init(InitFlag::PNG | InitFlag::JPG)?;
@@ -54,136 +95,66 @@ pub fn main() -> Result<(), String> {
 
     let tc: TextureCreator<_> = canvas.texture_creator();
 
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(Keyboard, "Keyboard", &[])
-        .with(ProjectileSys, "ProjectileSys", &[])
-        .with(GravitySys, "GravitySys", &["Keyboard", "ProjectileSys"])
-        .with(
-            CollisionSys,
-            "CollisionSys",
-            &["Keyboard", "ProjectileSys", "GravitySys"],
-        )
-        .with(
-            Physics,
-            "Physics",
-            &["Keyboard", "ProjectileSys", "GravitySys", "CollisionSys"],
-        )
-        .with(DecaySys, "DecaySys", &[])
-        .with(DamageSys, "DamageSys", &[])
-        .with(ActionSys, "ActionSys", &[])
-        .build();
-
-    let mut event_pump = sdl_context.event_pump()?;
-
-    let mut world = World::new();
-    dispatcher.setup(&mut world);
-    renderer::SystemData::setup(&mut world);
-
-    let movement_command: Option<MovementCommand> = None;
-    let action_command: Option<ActionCommand> = None;
-
-    let screeninfo = Some(ScreenInfo {
-        screen_size: ScreenSize::Size {
+    let mut audio = AudioManager::new(
+        &sdl_context,
+        ScreenSize::Size {
             width: WINDOW_WIDTH,
             height: WINDOW_HEIGHT,
         },
-    });
+    )?;
+    audio.preload("assets/shoot.wav");
+    audio.preload("assets/hit.wav");
+    audio.preload("assets/pickup.wav");
 
-    world.insert(movement_command);
-    world.insert(action_command);
-    world.insert(screeninfo);
+    let mut event_pump = sdl_context.event_pump()?;
+
+    // Recording only makes sense if the run can be reproduced, and peers have to agree
+    let deterministic =
+        options.deterministic || options.record.is_some() || options.netplay.is_some();
+    let seed = options.seed.unwrap_or_else(|| {
+        if deterministic {
+            DEFAULT_SEED
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(DEFAULT_SEED, |d| d.as_nanos() as u64)
+        }
+    });
+    let mut game = match (&options.connect, options.netplay) {
+        (Some(server), _) => {
+            let bind = options.bind.as_deref().unwrap_or("0.0.0.0:0");
+            Game::Client(NetClient::new(UdpTransport::new(bind, server)?)?)
+        }
+        (None, Some(slot)) => {
+            // Two instances on one machine find each other without further flags
+            let port = |slot: usize| format!("127.0.0.1:{}", NETPLAY_PORT + slot as u16);
+            let bind = options.bind.clone().unwrap_or_else(|| port(slot));
+            let peer = options.peer.clone().unwrap_or_else(|| port(1 - slot));
+            let mut config = RollbackConfig::default();
+            if let Some(delay) = options.input_delay {
+                config.input_delay = delay;
+            }
+            let sim = Simulation::new_multiplayer(seed, 2)?;
+            let level = LevelId::new(DEFAULT_LEVEL, &sim.world.read_resource::<Level>());
+            let transport = UdpTransport::new(&bind, &peer)?;
+            Game::Netplay(sim, RollbackSession::new(slot, config, transport, level))
+        }
+        (None, None) => Game::Local(Simulation::new(seed, deterministic)?),
+    };
+    let mut recording = match &options.record {
+        Some(path) => {
+            let level = LevelId::new(DEFAULT_LEVEL, &game.world().read_resource::<Level>());
+            Some(RecordingWriter::create(path, seed, &level)?)
+        }
+        None => None,
+    };
+    renderer::SystemData::setup(game.world());
 
-    let textures = [
+    let mut textures = [
         tc.load_texture("assets/reaper.png")?,
         tc.load_texture("assets/bullet.png")?,
         tc.load_texture("assets/block.png")?,
     ];
-    let mut sprite_vec = Vec::<Sprite>::new();
-    sprite_vec.push(Sprite {
-        spritesheet: 0,
-        region: rect!(0, 0, 26, 36),
-        rotation: None,
-    });
-
-    world
-        .create_entity()
-        .with(KeyboardControlled)
-        .with(GravityAfflicted {
-            max_vel: 20,
-            grounded: false,
-            grounded_rect: None,
-        })
-        .with(Velocity {
-            speed: 0,
-            max_speed: 4,
-            acc: 2,
-            last_dir: None,
-        })
-        .with(Position {
-            point: Point::new(0, 0),
-        })
-        .with(SpriteVec { sprite_vec })
-        .with(Collideable {
-            col_box: rect!(10, 10, 16, 36),
-        })
-        .with(Player)
-        .with(Health {
-            hp: 100,
-            hurt_box: rect!(10, 10, 16, 36),
-        })
-        .build();
-
-    //*** GROUND COLLISION BLOCK ***/
-    world
-        .create_entity()
-        .with(Position {
-            point: Point::new(0, (WINDOW_HEIGHT as i32) - 200),
-        })
-        .with(Collideable {
-            col_box: rect!(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT),
-        })
-        .build();
-    //** **/
-    // Bound the world so that entities cant leave the system
-    world
-        .create_entity()
-        .with(Position {
-            point: Point::new(0, WINDOW_HEIGHT as i32),
-        })
-        .with(Collideable {
-            col_box: rect!(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT),
-        })
-        .build();
-
-    world
-        .create_entity()
-        .with(Position {
-            point: Point::new(0, -(WINDOW_HEIGHT as i32)),
-        })
-        .with(Collideable {
-            col_box: rect!(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT),
-        })
-        .build();
-
-    world
-        .create_entity()
-        .with(Position {
-            point: Point::new(-(WINDOW_WIDTH as i32), 0),
-        })
-        .with(Collideable {
-            col_box: rect!(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT),
-        })
-        .build();
-
-    world
-        .create_entity()
-        .with(Position {
-            point: Point::new(WINDOW_WIDTH as i32, 0),
-        })
-        .with(Collideable {
-            col_box: rect!(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT),
-        })
-        .build();
 
     let mut fps_man = FPSManager::new();
 
@@ -191,11 +162,305 @@ pub fn main() -> Result<(), String> {
 
     let mut x_ctrl: i8 = 0;
     let mut y_ctrl: i8 = 0;
+    // Presses wait here until a tick has consumed them
+    let mut actions: Vec<ActionCommand> = Vec::new();
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+    // The console can change it, so it is no longer the constant
+    let mut sim_rate = SIM_RATE;
+    let mut console = Console::new();
+    let mut inspector = Inspector::default();
+    // Kept while play testing so leaving the test returns to the same layout and history
+    let mut editor: Option<Editor> = None;
+    let mut in_editor = false;
+    let mut show_profile = false;
+    // A desync is told once, the session keeps the frame it was first seen on
+    let mut desync_shown = false;
+    if options.trace.is_some() {
+        game.world().read_resource::<Profiler>().start_trace();
+    }
+    let text_input = video_subsystem.text_input();
+    text_input.stop();
+    let mut mouse = Point::new(0, 0);
 
     'running: loop {
         let mut shoot_flag: bool = false;
+        let mut use_flag: bool = false;
+        let mut jump_flag: bool = false;
         for event in event_pump.poll_iter() {
+            if let Event::MouseMotion { x, y, .. } = event {
+                mouse = Point::new(x, y);
+            }
+            if let (true, Some(ed)) = (in_editor, &mut editor) {
+                let point = screen_to_world(&canvas, ed.camera, mouse)?;
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F3),
+                        repeat: false,
+                        ..
+                    } => in_editor = false,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        repeat: false,
+                        ..
+                    } => {
+                        let profiler = (*game.world().read_resource::<Profiler>()).clone();
+                        match Simulation::from_level(
+                            seed,
+                            deterministic,
+                            ed.level.clone(),
+                            profiler,
+                        ) {
+                            Ok(sim) => {
+                                game = Game::Local(sim);
+                                in_editor = false;
+                                inspector.select(None);
+                                actions.clear();
+                            }
+                            Err(e) => ed.message = format!("error: {}", e),
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        keymod,
+                        ..
+                    } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => match key {
+                        Keycode::Z => ed.undo(),
+                        Keycode::Y => ed.redo(),
+                        Keycode::S => ed.save(),
+                        Keycode::L => ed.load(),
+                        _ => {}
+                    },
+                    Event::KeyDown {
+                        keycode: Some(key), ..
+                    } => match key {
+                        Keycode::Escape => ed.selected = None,
+                        Keycode::Tab => ed.next_brush(),
+                        Keycode::G => ed.toggle_snap(),
+                        Keycode::T => ed.toggle_texture(),
+                        Keycode::P => ed.set_spawn(point),
+                        Keycode::Left => ed.camera = ed.camera.offset(-EDITOR_PAN, 0),
+                        Keycode::Right => ed.camera = ed.camera.offset(EDITOR_PAN, 0),
+                        Keycode::Up => ed.camera = ed.camera.offset(0, -EDITOR_PAN),
+                        Keycode::Down => ed.camera = ed.camera.offset(0, EDITOR_PAN),
+                        _ => {}
+                    },
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    } => ed.press(point),
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Right,
+                        ..
+                    } => ed.remove_at(point),
+                    Event::MouseMotion { mousestate, .. } if mousestate.left() => ed.drag_to(point),
+                    Event::MouseButtonUp {
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    } => ed.release(point),
+                    _ => {}
+                }
+                continue;
+            }
+            if console.open {
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backquote | Keycode::Escape),
+                        ..
+                    } => {
+                        console.open = false;
+                        text_input.stop();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Return),
+                        ..
+                    } => {
+                        // Console changes are not recorded and would only desync a shared game
+                        if matches!(game, Game::Local(_)) && recording.is_none() {
+                            let world = game.world();
+                            let camera = world.read_resource::<Camera>().point;
+                            let cursor = screen_to_world(&canvas, camera, mouse)?;
+                            console.submit(&mut ConsoleContext {
+                                world,
+                                cursor,
+                                sim_rate: &mut sim_rate,
+                            });
+                        } else {
+                            console.input.clear();
+                            console.print(
+                                "error: commands only work in a local game that is not recorded",
+                            );
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backspace),
+                        ..
+                    } => {
+                        console.input.pop();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Up),
+                        ..
+                    } => console.history_back(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Down),
+                        ..
+                    } => console.history_forward(),
+                    Event::TextInput { text, .. } if text != "`" => console.input.push_str(&text),
+                    _ => {}
+                }
+                continue;
+            }
+            if inspector.editing.is_some() {
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Return),
+                        ..
+                    } => {
+                        inspector.commit_edit(game.world());
+                        text_input.stop();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => {
+                        inspector.editing = None;
+                        text_input.stop();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Backspace),
+                        ..
+                    } => {
+                        if let Some(edit) = &mut inspector.editing {
+                            edit.text.pop();
+                        }
+                    }
+                    Event::TextInput { text, .. } => {
+                        if let Some(edit) = &mut inspector.editing {
+                            edit.text.push_str(&text);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
             match event {
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    repeat: false,
+                    ..
+                } => {
+                    let world = game.world();
+                    let mut debug = world.write_resource::<DebugDraw>();
+                    debug.enabled = !debug.enabled;
+                }
+                // Opens in any game to read the log, commands are refused where they can not run
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backquote),
+                    repeat: false,
+                    ..
+                } => {
+                    console.open = true;
+                    text_input.start();
+                    x_ctrl = 0;
+                    y_ctrl = 0;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => show_profile = !show_profile,
+                // Edits the level the running game was built from, F5 in the editor plays it
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => {
+                    if matches!(game, Game::Local(_)) && recording.is_none() {
+                        let world = game.world();
+                        let camera = world.read_resource::<Camera>().point;
+                        let level = (*world.read_resource::<Level>()).clone();
+                        editor
+                            .get_or_insert_with(|| {
+                                let mut editor = Editor::new(level, DEFAULT_LEVEL);
+                                editor.camera = camera;
+                                editor
+                            })
+                            .message
+                            .clear();
+                        in_editor = true;
+                        x_ctrl = 0;
+                        y_ctrl = 0;
+                    } else {
+                        console.print(
+                            "error: the editor only works in a local game that is not recorded",
+                        );
+                        console.open = true;
+                        text_input.start();
+                        x_ctrl = 0;
+                        y_ctrl = 0;
+                    }
+                }
+                // Same rule as the console, pausing and edits only make sense alone
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => {
+                    if matches!(game, Game::Local(_)) && recording.is_none() {
+                        inspector.open = !inspector.open;
+                        inspector.paused = false;
+                        inspector.steps = 0;
+                    } else {
+                        console.print(
+                            "error: the inspector only works in a local game that is not recorded",
+                        );
+                        console.open = true;
+                        text_input.start();
+                        x_ctrl = 0;
+                        y_ctrl = 0;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } if inspector.open => inspector.toggle_pause(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } if inspector.open => inspector.step(),
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::LeftBracket | Keycode::RightBracket)),
+                    ..
+                } if inspector.open => {
+                    inspector.select_next(game.world(), key == Keycode::RightBracket)
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } if inspector.open => {
+                    let (width, _) = canvas.output_size()?;
+                    let world = game.world();
+                    if x >= (width - renderer::INSPECTOR_WIDTH) as i32 {
+                        inspector.click_row(world, y as usize / renderer::LINE_HEIGHT as usize);
+                        if inspector.editing.is_some() {
+                            text_input.start();
+                            x_ctrl = 0;
+                            y_ctrl = 0;
+                        }
+                    } else {
+                        let camera = world.read_resource::<Camera>().point;
+                        let point = screen_to_world(&canvas, camera, Point::new(x, y))?;
+                        inspector.select(inspector::pick(world, point));
+                    }
+                }
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
@@ -210,6 +475,13 @@ pub fn main() -> Result<(), String> {
                 } => {
                     shoot_flag = true;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::E),
+                    repeat: false,
+                    ..
+                } => {
+                    use_flag = true;
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::Right),
                     repeat: false,
@@ -232,6 +504,7 @@ pub fn main() -> Result<(), String> {
                     ..
                 } => {
                     y_ctrl = -1;
+                    jump_flag = true;
                 }
 
                 Event::KeyDown {
@@ -276,34 +549,143 @@ pub fn main() -> Result<(), String> {
             }
         }
 
-        let mut action: Option<ActionCommand> = None;
+        if let (true, Some(ed)) = (in_editor, &editor) {
+            renderer::render_editor(&mut canvas, ed)?;
+            canvas.present();
+            // The game waits where it was, without catching up afterwards
+            lag = Duration::ZERO;
+            last_frame = Instant::now();
+            fps_man.delay();
+            continue;
+        }
+
+        if jump_flag {
+            actions.push(ActionCommand::Jump);
+        }
         if shoot_flag {
-            action = Some(ActionCommand::Shoot(Direction::MoveDelta {
+            actions.push(ActionCommand::Shoot(Direction::MoveDelta {
                 x: x_ctrl,
                 y: y_ctrl,
             }));
         }
+        if use_flag {
+            actions.push(ActionCommand::Use);
+        }
 
         let movement_command = Some(MovementCommand::Move(Direction::MoveDelta {
             x: x_ctrl,
             y: y_ctrl,
         }));
 
-        *world.write_resource() = movement_command;
-        *world.write_resource() = action;
+        // Fixed timestep, slow frames run several ticks to catch up
+        let now = Instant::now();
+        lag += now - last_frame;
+        last_frame = now;
+        // Paused, only the ticks stepped through run
+        if inspector.paused {
+            lag = sim_rate * inspector.take_steps(MAX_TICKS_PER_FRAME);
+        }
+        let mut ticks = 0;
+        while lag >= sim_rate && ticks < MAX_TICKS_PER_FRAME {
+            let input = TickInput {
+                movement: movement_command,
+                actions: actions.clone(),
+            };
+            // A stalled session keeps the presses for the next tick
+            if game.tick(&input)? {
+                actions.clear();
+            }
+            if let (Some(recording), Game::Local(sim)) = (&mut recording, &game) {
+                recording.record(&input, Some(sim.checksum()))?;
+            }
+            lag -= sim_rate;
+            ticks += 1;
+        }
+        if ticks == MAX_TICKS_PER_FRAME {
+            lag = Duration::ZERO;
+        }
 
-        dispatcher.dispatch(&mut world);
-        world.maintain();
+        if let Game::Client(client) = &mut game {
+            for event in client.take_events() {
+                console.print(&event.to_string());
+            }
+        }
+        if let Game::Netplay(_, session) = &game {
+            if let (Some(frame), false) = (session.desync, desync_shown) {
+                console.print(&format!("error: peers went out of sync at frame {}", frame));
+                desync_shown = true;
+            }
+        }
+        let world = game.world();
+        for line in world.write_resource::<ScriptLog>().take() {
+            console.print(&line);
+        }
+        audio.play_queued(
+            &mut world.write_resource::<SoundQueue>(),
+            &world.read_resource::<AudioVolume>(),
+            &world.read_resource::<Camera>(),
+        );
 
+        let render_start = Instant::now();
         renderer::render(
             &mut canvas,
             Color::RGB(255, 255, 255),
-            &textures,
+            &mut textures,
             world.system_data(),
         )?;
+        if world.read_resource::<DebugDraw>().enabled {
+            renderer::render_debug(&mut canvas, world.system_data())?;
+        }
+        if show_profile {
+            let profiler = world.read_resource::<Profiler>();
+            renderer::render_profile(&mut canvas, &profiler.ticks(), &profiler.frames(), sim_rate)?;
+        }
+        if inspector.open {
+            let selected = inspector
+                .selected
+                .and_then(|entity| inspector::bounds(world, entity));
+            renderer::render_inspector(
+                &mut canvas,
+                &inspector.rows(world),
+                selected,
+                world.read_resource::<Camera>().point,
+            )?;
+        }
+        if console.open {
+            renderer::render_console(&mut canvas, &console)?;
+        }
+        world.read_resource::<Profiler>().record_frame(render_start);
+        canvas.present();
 
         fps_man.delay();
     }
 
+    if let Game::Netplay(
+        _,
+        RollbackSession {
+            desync: Some(frame),
+            ..
+        },
+    ) = &game
+    {
+        // The window is gone, the console line went with it
+        println!("peers went out of sync at frame {}", frame);
+    }
+    // Last, a trace that can not be written should not take anything else with it
+    if let Some(trace) = &options.trace {
+        game.world()
+            .read_resource::<Profiler>()
+            .write_trace(trace)?;
+    }
+
     return Ok(());
 }
+
+// Pixels the editor view moves per arrow key press
+const EDITOR_PAN: i32 = GRID * 4;
+
+// World point shown at a pixel of the window
+fn screen_to_world(canvas: &WindowCanvas, camera: Point, screen: Point) -> Result<Point, String> {
+    let (width, height) = canvas.output_size()?;
+    Ok(screen - Point::new(width as i32 / 2, height as i32 / 2) + camera)
+}
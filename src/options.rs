@@ -0,0 +1,93 @@
+// Command line of both binaries. Each one only runs the modes it has the parts for
+pub struct Options {
+    pub seed: Option<u64>,
+    pub deterministic: bool,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    // Stop a replay after this many ticks
+    pub ticks: Option<u64>,
+    // Where to write the state dump at the end of a replay
+    pub dump: Option<String>,
+    pub diff: Option<(String, String)>,
+    // Slot of this peer in a two player rollback session
+    pub netplay: Option<usize>,
+    pub bind: Option<String>,
+    pub peer: Option<String>,
+    pub input_delay: Option<u64>,
+    // Runs two peers against each other for this many ticks without a window
+    pub rollback_test: Option<u64>,
+    // Headless authoritative server on --bind
+    pub server: bool,
+    // Address of the server to join
+    pub connect: Option<String>,
+    // Runs a server and a client over localhost for this many ticks without a window
+    pub net_test: Option<u64>,
+    // Address of a server to join with random input instead of a player
+    pub bot: Option<String>,
+    // Chrome trace of the game or replay, written when it ends
+    pub trace: Option<String>,
+}
+
+pub fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        seed: None,
+        deterministic: false,
+        record: None,
+        replay: None,
+        ticks: None,
+        dump: None,
+        diff: None,
+        netplay: None,
+        bind: None,
+        peer: None,
+        input_delay: None,
+        rollback_test: None,
+        server: false,
+        connect: None,
+        net_test: None,
+        bot: None,
+        trace: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--seed" => options.seed = Some(number(value()?, "seed")?),
+            "--deterministic" => options.deterministic = true,
+            "--record" => options.record = Some(value()?),
+            "--replay" => options.replay = Some(value()?),
+            "--ticks" => options.ticks = Some(number(value()?, "tick count")?),
+            "--dump" => options.dump = Some(value()?),
+            "--diff" => {
+                let first = value()?;
+                options.diff = Some((first, value()?));
+            }
+            "--netplay" => {
+                let slot = number(value()?, "slot")?;
+                if slot > 1 {
+                    return Err("--netplay takes slot 0 or 1".to_string());
+                }
+                options.netplay = Some(slot);
+            }
+            "--bind" => options.bind = Some(value()?),
+            "--peer" => options.peer = Some(value()?),
+            "--input-delay" => options.input_delay = Some(number(value()?, "input delay")?),
+            "--rollback-test" => options.rollback_test = Some(number(value()?, "tick count")?),
+            "--server" => options.server = true,
+            "--connect" => options.connect = Some(value()?),
+            "--net-test" => options.net_test = Some(number(value()?, "tick count")?),
+            "--bot" => options.bot = Some(value()?),
+            "--trace" => options.trace = Some(value()?),
+            other => return Err(format!("unknown argument `{}`", other)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(value: String, what: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid {}", value, what))
+}
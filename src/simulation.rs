@@ -0,0 +1,333 @@
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+use std::time::Instant;
+
+use crate::checksum::*;
+use crate::collisionsys::CollisionSys;
+use crate::commands::*;
+use crate::debugdraw::DebugDraw;
+use crate::entity_components::*;
+use crate::entity_flags::*;
+use crate::events::*;
+use crate::globalcomponents::*;
+use crate::keyboard::Keyboard;
+use crate::level::*;
+use crate::particles::ParticlePool;
+use crate::physics::Physics;
+use crate::prefabs::PrefabLibrary;
+use crate::profiler::*;
+use crate::rect;
+use crate::rng::*;
+use crate::systems::actionsys::ActionSys;
+use crate::systems::aisys::AiSys;
+use crate::systems::animator::Animator;
+use crate::systems::contactsys::ContactSys;
+use crate::systems::controllersys::ControllerSys;
+use crate::systems::damagesys::DamageSys;
+use crate::systems::deathsys::DeathSys;
+use crate::systems::decaysys::DecaySys;
+use crate::systems::gravitysys::GravitySys;
+use crate::systems::navsys::NavSys;
+use crate::systems::particlesys::ParticleSys;
+use crate::systems::pickupsys::PickupSys;
+use crate::systems::platformsys::PlatformSys;
+use crate::systems::projectilesys::ProjectileSys;
+use crate::systems::rigidbodysys::RigidBodySys;
+use crate::systems::scriptsys::ScriptSys;
+use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+
+// The game world and the systems stepping it, without anything that needs a window
+pub struct Simulation<'a, 'b> {
+    pub world: World,
+    pub dispatcher: Dispatcher<'a, 'b>,
+    // Runs the systems one after another so the outcome never depends on thread scheduling.
+    // par_join loops only write the components of the entity they are looking at, so they
+    // stay deterministic either way
+    pub deterministic: bool,
+    pub tick: u64,
+}
+
+impl<'a, 'b> Simulation<'a, 'b> {
+    pub fn new(seed: u64, deterministic: bool) -> Result<Simulation<'a, 'b>, String> {
+        Simulation::create(
+            seed,
+            deterministic,
+            None,
+            Level::load(DEFAULT_LEVEL)?,
+            Profiler::default(),
+        )
+    }
+
+    // Local game on a level that need not be saved anywhere, the editor plays tests this way.
+    // Handing over the last game's profiler keeps its graph and trace going
+    pub fn from_level(
+        seed: u64,
+        deterministic: bool,
+        level: Level,
+        profiler: Profiler,
+    ) -> Result<Simulation<'a, 'b>, String> {
+        Simulation::create(seed, deterministic, None, level, profiler)
+    }
+
+    // Always deterministic, every peer has to arrive at the same world
+    pub fn new_multiplayer(seed: u64, players: usize) -> Result<Simulation<'a, 'b>, String> {
+        Simulation::create(
+            seed,
+            true,
+            Some(players),
+            Level::load(DEFAULT_LEVEL)?,
+            Profiler::default(),
+        )
+    }
+
+    // No players at all until clients join through add_net_player
+    pub fn new_server(seed: u64) -> Result<Simulation<'a, 'b>, String> {
+        Simulation::create(
+            seed,
+            false,
+            Some(0),
+            Level::load(DEFAULT_LEVEL)?,
+            Profiler::default(),
+        )
+    }
+
+    fn create(
+        seed: u64,
+        deterministic: bool,
+        net_players: Option<usize>,
+        level: Level,
+        profiler: Profiler,
+    ) -> Result<Simulation<'a, 'b>, String> {
+        let mut world = World::new();
+        profiler.set_sequential(deterministic);
+        let mut dispatcher = build_dispatcher(&profiler);
+        dispatcher.setup(&mut world);
+        world.insert(Rng::new(seed));
+        world.write_resource::<ParticlePool>().rng = Rng::new(seed.rotate_left(32));
+        world.insert(PrefabLibrary::load()?);
+        world.insert(HotReload(!deterministic && net_players.is_none()));
+        populate(&mut world, net_players, &level)?;
+        // Kept for players joining later and for the editor to start from
+        world.insert(level);
+        world.insert(profiler);
+
+        Ok(Simulation {
+            world,
+            dispatcher,
+            deterministic,
+            tick: 0,
+        })
+    }
+
+    pub fn step(&mut self, input: &TickInput) {
+        *self.world.write_resource() = input.movement;
+        *self.world.write_resource() = input.actions.clone();
+        self.run_tick();
+    }
+
+    // One input per NetPlayer slot
+    pub fn step_players(&mut self, inputs: &[TickInput]) {
+        *self.world.write_resource::<Option<MovementCommand>>() = None;
+        self.world.write_resource::<Vec<ActionCommand>>().clear();
+        self.world.write_resource::<PlayerInputs>().inputs = inputs.to_vec();
+        self.run_tick();
+    }
+
+    fn run_tick(&mut self) {
+        let start = Instant::now();
+        self.world.write_resource::<DebugDraw>().clear();
+        if self.deterministic {
+            self.dispatcher.dispatch_seq(&self.world);
+        } else {
+            self.dispatcher.dispatch(&self.world);
+        }
+        self.world.maintain();
+        let entities = self.world.entities().join().count();
+        self.world
+            .read_resource::<Profiler>()
+            .end_tick(self.tick, start, entities);
+        self.tick += 1;
+    }
+
+    pub fn add_net_player(&mut self, slot: usize) -> Result<Entity, String> {
+        let spawn = self.world.read_resource::<Level>().spawn;
+        add_net_player(&mut self.world, spawn, slot)
+    }
+
+    pub fn checksum(&self) -> u64 {
+        world_checksum(&self.world)
+    }
+}
+
+// Every system is timed by the profiler under its dispatcher name
+pub fn build_dispatcher<'a, 'b>(profiler: &Profiler) -> Dispatcher<'a, 'b> {
+    DispatcherBuilder::new()
+        .with_timed(profiler, Keyboard, "Keyboard", &[])
+        .with_timed(profiler, NavSys, "NavSys", &[])
+        .with_timed(profiler, AiSys, "AiSys", &["NavSys"])
+        .with_timed(
+            profiler,
+            ControllerSys,
+            "ControllerSys",
+            &["Keyboard", "AiSys"],
+        )
+        .with_timed(profiler, ActionSys, "ActionSys", &["ControllerSys"])
+        .with_timed(profiler, ProjectileSys, "ProjectileSys", &[])
+        .with_timed(profiler, PlatformSys, "PlatformSys", &["ControllerSys"])
+        .with_timed(
+            profiler,
+            GravitySys,
+            "GravitySys",
+            &["ControllerSys", "ActionSys", "ProjectileSys", "PlatformSys"],
+        )
+        .with_timed(profiler, RigidBodySys, "RigidBodySys", &[])
+        .with_timed(
+            profiler,
+            CollisionSys,
+            "CollisionSys",
+            &[
+                "Keyboard",
+                "ProjectileSys",
+                "GravitySys",
+                "RigidBodySys",
+                "PlatformSys",
+            ],
+        )
+        .with_timed(profiler, ContactSys, "ContactSys", &["CollisionSys"])
+        .with_timed(
+            profiler,
+            Physics,
+            "Physics",
+            &["Keyboard", "ProjectileSys", "GravitySys", "CollisionSys"],
+        )
+        .with_timed(profiler, DecaySys, "DecaySys", &[])
+        .with_timed(profiler, Animator, "Animator", &[])
+        .with_timed(profiler, PickupSys, "PickupSys", &["Physics"])
+        .with_timed(profiler, DamageSys, "DamageSys", &["Animator"])
+        .with_timed(
+            profiler,
+            ParticleSys::default(),
+            "ParticleSys",
+            &["ActionSys", "DamageSys", "Physics"],
+        )
+        .with_timed(
+            profiler,
+            ScriptSys::default(),
+            "ScriptSys",
+            &["DamageSys", "Physics", "PickupSys"],
+        )
+        .with_timed(
+            profiler,
+            DeathSys::default(),
+            "DeathSys",
+            &["DamageSys", "ScriptSys"],
+        )
+        .build()
+}
+
+// Without net players there is a single player driven by the local keyboard
+fn populate(world: &mut World, net_players: Option<usize>, level: &Level) -> Result<(), String> {
+    let movement_command: Option<MovementCommand> = None;
+    let action_commands: Vec<ActionCommand> = Vec::new();
+
+    let screeninfo = Some(ScreenInfo {
+        screen_size: ScreenSize::Size {
+            width: WINDOW_WIDTH,
+            height: WINDOW_HEIGHT,
+        },
+    });
+
+    world.insert(movement_command);
+    world.insert(action_commands);
+    world.insert(screeninfo);
+    world.insert(Camera::default());
+    world.insert(TeamRules::default());
+    world.insert(AudioVolume::default());
+    world.insert(SoundQueue::default());
+    world.insert(PhysicsConfig::default());
+    world.insert(DebugDraw::default());
+
+    match net_players {
+        None => {
+            let player = spawn_player(world, level.spawn, 0);
+            world
+                .write_storage::<KeyboardControlled>()
+                .insert(player, KeyboardControlled)
+                .map_err(|e| e.to_string())?;
+        }
+        Some(players) => {
+            for slot in 0..players {
+                add_net_player(world, level.spawn, slot)?;
+            }
+        }
+    }
+
+    level.build(world)?;
+
+    Ok(())
+}
+
+// Networked players fight each other, slot 0 keeps the usual player team and
+// the rest skip the grunts' team 1
+fn add_net_player(world: &mut World, spawn: Point, slot: usize) -> Result<Entity, String> {
+    let team = if slot == 0 { 0 } else { slot as u32 + 1 };
+    let player = spawn_player(world, spawn.offset(slot as i32 * 60 - 30, 0), team);
+    world
+        .write_storage::<NetPlayer>()
+        .insert(player, NetPlayer { slot })
+        .map_err(|e| e.to_string())?;
+    Ok(player)
+}
+
+pub fn spawn_player(world: &mut World, point: Point, team: u32) -> Entity {
+    let sprite_vec = vec![Sprite {
+        spritesheet: 0,
+        region: rect!(0, 0, 26, 36),
+        rotation: None,
+    }];
+
+    world
+        .create_entity()
+        .with(GravityAfflicted {
+            max_vel: 20,
+            grounded: false,
+            grounded_rect: None,
+            ground_friction: 1.0,
+            jump_speed: 0,
+        })
+        .with(Velocity {
+            speed: 0,
+            max_speed: 4,
+            acc: 2,
+            last_dir: None,
+            carry: (0.0, 0.0),
+        })
+        .with(Position { point })
+        .with(SpriteVec { sprite_vec })
+        .with(Collideable {
+            col_box: rect!(10, 10, 16, 36),
+            friction: 1.0,
+        })
+        .with(Player)
+        .with(PlatformerController {
+            max_air_jumps: 1,
+            wall_jump: true,
+            ..PlatformerController::default()
+        })
+        .with(ActionQueue::default())
+        .with(Weapon::default())
+        .with(Health {
+            hp: 100,
+            max_hp: 100,
+            hurt_boxes: vec![rect!(0, 0, 16, 36)],
+            last_attacker: None,
+        })
+        .with(Invulnerability {
+            duration: 60,
+            remaining: 0,
+        })
+        .with(Team { id: team })
+        .with(Score::default())
+        .with(Inventory::new(8))
+        .build()
+}
@@ -1,77 +1,188 @@
-use crate::rect;
 use sdl2::rect::Rect;
 use specs::prelude::*;
+use specs::shrev::EventChannel;
 
 use crate::commands::*;
+use crate::events::*;
+use crate::particles::*;
 
 use crate::entity_components::*;
-use crate::entity_flags::*;
 
 pub struct ActionSys;
 
+fn spawn_projectile(
+    entity: &Entities,
+    updater: &LazyUpdate,
+    pos: &Position,
+    weapon: &Weapon,
+    props: ProjectileProperties,
+) {
+    let bullet = entity.create();
+    let b_vel: Velocity = Velocity {
+        speed: 0,
+        max_speed: weapon.speed,
+        acc: 0,
+        last_dir: None,
+        carry: (0.0, 0.0),
+    };
+
+    updater.insert(bullet, b_vel);
+    updater.insert(
+        bullet,
+        SpriteVec {
+            sprite_vec: vec![weapon.sprite.clone()],
+        },
+    );
+    updater.insert(
+        bullet,
+        DecayLife {
+            life: weapon.lifetime,
+        },
+    );
+    updater.insert(bullet, pos.clone());
+    updater.insert(
+        bullet,
+        Damage {
+            dmg: weapon.damage,
+            dmg_boxes: vec![Rect::new(
+                0,
+                0,
+                weapon.sprite.region.width(),
+                weapon.sprite.region.height(),
+            )],
+            kind: weapon.kind,
+            knockback: weapon.knockback,
+            hits: Vec::new(),
+        },
+    );
+    updater.insert(bullet, props);
+    if weapon.gravity {
+        updater.insert(
+            bullet,
+            GravityAfflicted {
+                max_vel: 20,
+                grounded: false,
+                grounded_rect: None,
+                ground_friction: 1.0,
+                jump_speed: 0,
+            },
+        );
+    }
+}
+
 impl<'a> System<'a> for ActionSys {
     type SystemData = (
-        ReadExpect<'a, Option<ActionCommand>>,
-        ReadStorage<'a, Player>,
+        WriteStorage<'a, ActionQueue>,
         ReadStorage<'a, Position>,
-        ReadStorage<'a, Velocity>,
+        WriteStorage<'a, Weapon>,
         Entities<'a>,
         Read<'a, LazyUpdate>,
-        ReadStorage<'a, ProjectileProperties>,
+        Write<'a, SoundQueue>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Team>,
+        WriteStorage<'a, Inventory>,
+        WriteStorage<'a, Health>,
+        Write<'a, EventChannel<ItemUsed>>,
+        Write<'a, ParticlePool>,
+        WriteStorage<'a, GravityAfflicted>,
     );
 
-    fn run(&mut self, data: Self::SystemData) {
-        let action = match &*data.0 {
-            Some(action) => action,
-            None => return,
-        };
-
-        let entity = &data.4;
-        let updater = &data.5;
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = &data.3;
+        let updater = &data.4;
 
-        (&data.1, &data.2, &data.3)
-            .par_join()
-            .for_each(|(_, pos, _)| match action {
-                ActionCommand::Shoot(dir) => match dir {
-                    dir => {
-                        let bullet = entity.create();
-                        let b_vel: Velocity = Velocity {
-                            speed: 0,
-                            max_speed: 100,
-                            acc: 30,
-                            last_dir: None,
+        for (queue, pos, mut weapon, mut vel, team, mut inventory, mut health, mut grav, shooter) in
+            (
+                &mut data.0,
+                &data.1,
+                (&mut data.2).maybe(),
+                (&mut data.6).maybe(),
+                (&data.7).maybe(),
+                (&mut data.8).maybe(),
+                (&mut data.9).maybe(),
+                (&mut data.12).maybe(),
+                entities,
+            )
+                .join()
+        {
+            for action in queue.actions.drain(..) {
+                match action {
+                    ActionCommand::Shoot(dir) => {
+                        // Entities without a weapon have nothing to shoot with
+                        let weapon = match &mut weapon {
+                            Some(weapon) if weapon.cooldown == 0 => weapon,
+                            _ => continue,
                         };
+                        if weapon.ammo_per_shot > 0 {
+                            let paid = inventory
+                                .as_mut()
+                                .is_some_and(|inv| inv.remove(Item::Ammo, weapon.ammo_per_shot));
+                            if !paid {
+                                continue;
+                            }
+                        }
+                        weapon.cooldown = weapon.fire_delay;
 
-                        updater.insert(bullet, b_vel);
-                        updater.insert(
-                            bullet,
-                            SpriteVec {
-                                sprite_vec: vec![Sprite {
-                                    spritesheet: 1,
-                                    region: rect!(0, 0, 5, 5),
-                                    rotation: None,
-                                }],
-                            },
-                        );
-                        updater.insert(bullet, DecayLife { life: 10 });
-                        updater.insert(bullet, pos.clone());
-                        updater.insert(
-                            bullet,
-                            Damage {
-                                dmg: 10,
-                                dmg_box: rect!(0, 0, 5, 5),
-                            },
-                        );
-                        updater.insert(
-                            bullet,
-                            ProjectileProperties {
-                                direction: dir.clone(),
-                                owner: -1,
-                            },
+                        // Shooting without a direction fires the way the shooter last faced
+                        let facing = vel
+                            .as_ref()
+                            .and_then(|v| v.last_dir)
+                            .and_then(|d| d.heading());
+                        let base = dir.heading().or(facing).unwrap_or(0.0);
+
+                        let count = weapon.projectile_count.max(1);
+                        let spread = weapon.spread.to_radians();
+                        for i in 0..count {
+                            let offset = if count == 1 {
+                                0.0
+                            } else {
+                                spread * (i as f32 / (count - 1) as f32 - 0.5)
+                            };
+                            let props =
+                                weapon.projectile(shooter, team.map(|t| t.id), base + offset);
+                            spawn_projectile(entities, updater, pos, weapon, props);
+                        }
+                        data.5.push(PlaySound::at("assets/shoot.wav", pos.point));
+                        data.11.burst(
+                            &EmitterConfig::muzzle_flash(base.to_degrees()),
+                            pos.point.x() as f32,
+                            pos.point.y() as f32,
                         );
-                        updater.insert(bullet, SingleDamage);
                     }
-                },
-            });
+                    // ControllerSys takes the jumps of entities it controls, the rest jump plainly
+                    // off the ground
+                    ActionCommand::Jump => {
+                        if let (Some(vel), Some(grav)) = (&mut vel, &mut grav) {
+                            if grav.grounded && grav.jump_speed > 0 {
+                                let (x_speed, _) = vel.unencode_speed();
+                                vel.encode_speed(x_speed, -grav.jump_speed);
+                                grav.grounded = false;
+                                grav.grounded_rect = None;
+                            }
+                        }
+                    }
+                    ActionCommand::Use => {
+                        let (inv, health) = match (&mut inventory, &mut health) {
+                            (Some(inv), Some(health)) => (inv, health),
+                            _ => continue,
+                        };
+                        // Medkits are the only usable item, and only worth using when hurt
+                        if health.hp < health.max_hp && inv.remove(Item::Medkit, 1) {
+                            health.heal(MEDKIT_HEAL);
+                            data.10.single_write(ItemUsed {
+                                entity: shooter,
+                                item: Item::Medkit,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        for weapon in (&mut data.2).join() {
+            if weapon.cooldown > 0 {
+                weapon.cooldown -= 1;
+            }
+        }
     }
 }
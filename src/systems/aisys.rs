@@ -0,0 +1,256 @@
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+
+use crate::commands::*;
+use crate::debugdraw::DebugDraw;
+use crate::entity_ai::*;
+use crate::entity_components::*;
+use crate::entity_flags::*;
+use crate::globalcomponents::*;
+use crate::navigation::*;
+
+pub struct AiSys;
+
+// Distance at which a patrol point counts as reached
+const ARRIVE_DISTANCE: i32 = 4;
+
+struct Senses {
+    target: Option<Point>,
+    distance: i32,
+    line_of_sight: bool,
+    hp: Option<u32>,
+}
+
+fn sign(v: i32) -> i8 {
+    v.signum() as i8
+}
+
+fn towards(from: Point, to: Point, vertical: bool) -> Direction {
+    let y = if vertical { sign(to.y() - from.y()) } else { 0 };
+    Direction::MoveDelta {
+        x: sign(to.x() - from.x()),
+        y,
+    }
+}
+
+// Walkers only steer sideways, their vertical speed belongs to gravity and jumps
+fn steer(vel: &mut Velocity, dir: Direction, vertical: bool) {
+    match dir {
+        Direction::MoveDelta { x, .. } if !vertical => vel.steer_x(x),
+        _ => vel.steer(dir),
+    }
+}
+
+// Heads straight for a visible target, otherwise follows the navigation grid.
+// Also says whether the next link of the path is a jump.
+fn route(
+    ai: &mut AI,
+    nav: &NavGrid,
+    from: Point,
+    to: Point,
+    walker: Option<(bool, JumpProfile)>,
+    visible: bool,
+) -> (Direction, bool) {
+    let vertical = walker.is_none();
+    if visible {
+        return (towards(from, to, vertical), false);
+    }
+
+    let goal = nav.path_cell(to, !vertical);
+    let here = nav.path_cell(from, !vertical);
+    let on_path = ai
+        .path
+        .iter()
+        .position(|(p, _)| nav.path_cell(*p, false) == here);
+    let stale = ai.path_grid != nav.version || ai.path_goal != goal;
+    // A walker back on the ground off its route was knocked off it. Flyers follow sparse jump
+    // points and are only ever between them
+    let lost = !ai.path.is_empty() && on_path.is_none() && walker.is_some_and(|(g, _)| g);
+
+    match on_path {
+        Some(i) if !stale => {
+            ai.path.drain(..i);
+        }
+        _ if stale || lost => {
+            ai.path = match walker {
+                None => nav
+                    .find_path_jps(from, to)
+                    .map(|path| path.into_iter().map(|p| (p, false)).collect()),
+                Some((_, jump)) => nav.find_platform_path(from, to, jump).map(|path| {
+                    path.into_iter()
+                        .map(|step| (step.point, step.link == NavLink::Jump))
+                        .collect()
+                }),
+            }
+            .unwrap_or_default();
+            ai.path_goal = goal;
+            ai.path_grid = nav.version;
+        }
+        // Mid jump or between jump points, keep heading for the next waypoint
+        _ => {}
+    }
+
+    let (goal, jumping) = ai.path.get(1).copied().unwrap_or((to, false));
+    (towards(from, goal, vertical), jumping)
+}
+
+fn condition_met(condition: &Condition, senses: &Senses, ticks_in_state: u32) -> bool {
+    match condition {
+        Condition::Always => true,
+        Condition::PlayerWithin(range) => senses.target.is_some() && senses.distance <= *range,
+        Condition::PlayerBeyond(range) => senses.target.is_none() || senses.distance > *range,
+        Condition::HealthBelow(hp) => senses.hp.is_some_and(|cur| cur < *hp),
+        Condition::LineOfSight => senses.line_of_sight,
+        Condition::NoLineOfSight => !senses.line_of_sight,
+        Condition::After(ticks) => ticks_in_state >= *ticks,
+    }
+}
+
+impl<'a> System<'a> for AiSys {
+    type SystemData = (
+        WriteStorage<'a, AI>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, Collideable>,
+        ReadStorage<'a, GravityAfflicted>,
+        ReadStorage<'a, Health>,
+        Read<'a, NavGrid>,
+        WriteStorage<'a, ActionQueue>,
+        ReadStorage<'a, PlatformerController>,
+        Read<'a, PhysicsConfig>,
+        Write<'a, DebugDraw>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let nav = &*data.7;
+        let config = *data.10;
+        let players: Vec<Point> = (&data.3, &data.1).join().map(|(_, p)| p.point).collect();
+
+        // Static terrain blocks line of sight
+        let terrain: Vec<Rect> = (&data.1, &data.4, !&data.2)
+            .join()
+            .map(|(pos, coll, _)| {
+                Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height())
+            })
+            .collect();
+
+        (
+            &mut data.0,
+            &data.1,
+            &mut data.2,
+            (&data.5).maybe(),
+            (&data.6).maybe(),
+            &mut data.8,
+            (&data.9).maybe(),
+        )
+            .par_join()
+            .for_each(|(ai, pos, vel, grav, health, queue, ctrl)| {
+                let target = players
+                    .iter()
+                    .min_by_key(|p| (p.x() - pos.point.x()).pow(2) + (p.y() - pos.point.y()).pow(2))
+                    .copied();
+                let distance = match target {
+                    Some(t) => (((t.x() - pos.point.x()).pow(2) + (t.y() - pos.point.y()).pow(2))
+                        as f64)
+                        .sqrt() as i32,
+                    None => i32::MAX,
+                };
+                let line_of_sight = match target {
+                    Some(t) => !terrain
+                        .iter()
+                        .any(|r| r.intersect_line(pos.point, t).is_some()),
+                    None => false,
+                };
+                let senses = Senses {
+                    target,
+                    distance,
+                    line_of_sight,
+                    hp: health.map(|h| h.hp),
+                };
+
+                ai.ticks_in_state += 1;
+                if ai.cooldown > 0 {
+                    ai.cooldown -= 1;
+                }
+
+                let next = ai.states[ai.current]
+                    .transitions
+                    .iter()
+                    .find(|t| condition_met(&t.condition, &senses, ai.ticks_in_state))
+                    .map(|t| t.target);
+                if let Some(next) = next {
+                    if next != ai.current {
+                        ai.current = next;
+                        ai.ticks_in_state = 0;
+                    }
+                }
+
+                // Gravity afflicted entities walk, everything else flies
+                let vertical = grav.is_none();
+
+                match &ai.states[ai.current].behavior {
+                    Behavior::Idle => {}
+                    Behavior::Patrol { points } => {
+                        let goal = points[ai.patrol_index % points.len()];
+                        let dx = goal.x() - pos.point.x();
+                        let dy = if vertical {
+                            goal.y() - pos.point.y()
+                        } else {
+                            0
+                        };
+                        if dx.abs() <= ARRIVE_DISTANCE && dy.abs() <= ARRIVE_DISTANCE {
+                            ai.patrol_index = (ai.patrol_index + 1) % points.len();
+                        } else {
+                            steer(vel, towards(pos.point, goal, vertical), vertical);
+                        }
+                    }
+                    Behavior::Chase { range } => {
+                        if let Some(t) = senses.target {
+                            if senses.distance <= *range {
+                                let walker = grav.map(|g| {
+                                    (g.grounded, g.jump_profile(ctrl, vel.max_speed, &config))
+                                });
+                                let (dir, jumping) =
+                                    route(ai, nav, pos.point, t, walker, senses.line_of_sight);
+                                steer(vel, dir, vertical);
+                                if jumping && grav.is_some_and(|g| g.grounded) {
+                                    queue.push(ActionCommand::Jump);
+                                }
+                            }
+                        }
+                    }
+                    Behavior::Flee { range } => {
+                        if let Some(t) = senses.target {
+                            if senses.distance <= *range {
+                                steer(vel, towards(t, pos.point, vertical), vertical);
+                            }
+                        }
+                    }
+                    Behavior::Shoot { range, cooldown } => {
+                        if let Some(t) = senses.target {
+                            if ai.cooldown == 0 && senses.line_of_sight && senses.distance <= *range
+                            {
+                                queue.push(ActionCommand::Shoot(towards(pos.point, t, true)));
+                                ai.cooldown = *cooldown;
+                            }
+                        }
+                    }
+                }
+            });
+
+        // State names for the debug overlay
+        let debug = &mut *data.11;
+        if debug.enabled {
+            for (ai, pos, coll) in (&data.0, &data.1, (&data.4).maybe()).join() {
+                let top = coll.map_or(0, |c| c.col_box.height() as i32 / 2);
+                debug.text(
+                    pos.point.offset(-12, -top - 12),
+                    &ai.states[ai.current].name,
+                    Color::RGB(255, 140, 0),
+                );
+            }
+        }
+    }
+}
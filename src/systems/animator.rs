@@ -1,20 +1,66 @@
 use specs::prelude::*;
 
+use crate::entity_animation::*;
 use crate::entity_components::*;
 
 pub struct Animator;
 
 impl<'a> System<'a> for Animator {
     type SystemData = (
-        WriteStorage<'a, MovementAnimation>,
-        WriteStorage<'a, Sprite>,
-        ReadStorage<'a, Velocity>,
+        WriteStorage<'a, Animation>,
+        WriteStorage<'a, SpriteVec>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Damage>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
-        (&mut data.0, &mut data.1, &data.2)
+        (
+            &mut data.0,
+            &mut data.1,
+            (&mut data.2).maybe(),
+            (&mut data.3).maybe(),
+        )
             .par_join()
-            .filter(|(_, _, vel)| vel.speed != 0)
-            .for_each(|(_anim, _sprite, _vel)| {});
+            .for_each(|(anim, sprites, health, damage)| {
+                let mut restarted = false;
+                if !anim.finished() {
+                    anim.elapsed += 1;
+                    if anim.elapsed >= anim.frame().duration {
+                        if anim.current + 1 < anim.frames.len() {
+                            anim.current += 1;
+                            anim.elapsed = 0;
+                        } else if anim.looping {
+                            anim.restart();
+                            restarted = true;
+                        }
+                    }
+                }
+
+                let frame = anim.frame();
+                match sprites.sprite_vec.first_mut() {
+                    Some(sprite) => {
+                        sprite.spritesheet = anim.spritesheet;
+                        sprite.region = frame.region;
+                    }
+                    None => sprites.sprite_vec.push(Sprite {
+                        spritesheet: anim.spritesheet,
+                        region: frame.region,
+                        rotation: None,
+                    }),
+                }
+
+                if let (Some(health), Some(boxes)) = (health, &frame.hurt_boxes) {
+                    health.hurt_boxes = boxes.clone();
+                }
+                if let Some(damage) = damage {
+                    if let Some(boxes) = &frame.hit_boxes {
+                        damage.dmg_boxes = boxes.clone();
+                    }
+                    // A new swing starts with the next loop or once the boxes are put away
+                    if restarted || damage.dmg_boxes.is_empty() {
+                        damage.hits.clear();
+                    }
+                }
+            });
     }
 }
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use sdl2::mixer::{self, Channel, Chunk, Music, Sdl2MixerContext, MAX_VOLUME};
+use sdl2::rect::Point;
+use sdl2::{AudioSubsystem, Sdl};
+
+use crate::events::*;
+use crate::globalcomponents::*;
+
+pub const SFX_CHANNELS: i32 = 32;
+// Distance in pixels from the camera at which positional sounds go silent
+pub const HEARING_DISTANCE: f32 = 800.0;
+
+// Owns the mixer and every loaded sound, lives on the main thread next to the textures.
+// Set SDL_AUDIODRIVER=dummy to run without an audio device, the tests do.
+pub struct AudioManager {
+    _audio: AudioSubsystem,
+    _mixer_context: Option<Sdl2MixerContext>,
+    sounds: HashMap<String, Option<Chunk>>,
+    tracks: HashMap<String, Option<Music<'static>>>,
+    current_track: Option<String>,
+    pending_track: Option<(String, i32)>,
+    half_width: f32,
+}
+
+impl AudioManager {
+    pub fn new(sdl_context: &Sdl, screen: ScreenSize) -> Result<AudioManager, String> {
+        let audio = sdl_context.audio()?;
+        mixer::open_audio(
+            mixer::DEFAULT_FREQUENCY,
+            mixer::DEFAULT_FORMAT,
+            mixer::DEFAULT_CHANNELS,
+            1024,
+        )?;
+        mixer::allocate_channels(SFX_CHANNELS);
+
+        let ScreenSize::Size { width, .. } = screen;
+
+        Ok(AudioManager {
+            _audio: audio,
+            // Only needed for compressed music, wav playback works without it
+            _mixer_context: mixer::init(mixer::InitFlag::OGG).ok(),
+            sounds: HashMap::new(),
+            tracks: HashMap::new(),
+            current_track: None,
+            pending_track: None,
+            half_width: width as f32 / 2.0,
+        })
+    }
+
+    // Loads a sound into the cache ahead of its first use
+    pub fn preload(&mut self, sound: &str) -> bool {
+        self.sound(sound).is_some()
+    }
+
+    fn sound(&mut self, path: &str) -> Option<&Chunk> {
+        self.sounds
+            .entry(path.to_string())
+            .or_insert_with(|| match Chunk::from_file(path) {
+                Ok(chunk) => Some(chunk),
+                Err(e) => {
+                    eprintln!("failed to load sound {}: {}", path, e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    fn track(&mut self, path: &str) -> Option<&Music<'static>> {
+        self.tracks
+            .entry(path.to_string())
+            .or_insert_with(|| match Music::from_file(path) {
+                Ok(music) => Some(music),
+                Err(e) => {
+                    eprintln!("failed to load music {}: {}", path, e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    // Gain and left/right panning of a sound relative to the camera
+    fn spatialize(&self, position: Option<Point>, camera: &Camera) -> (f32, u8, u8) {
+        let position = match position {
+            Some(position) => position,
+            None => return (1.0, 255, 255),
+        };
+
+        let dx = (position.x() - camera.point.x()) as f32;
+        let dy = (position.y() - camera.point.y()) as f32;
+        let distance = (dx * dx + dy * dy).sqrt();
+        let gain = (1.0 - distance / HEARING_DISTANCE).clamp(0.0, 1.0);
+
+        let pan = (dx / self.half_width).clamp(-1.0, 1.0);
+        let left = 255.0 * (1.0 - pan.max(0.0));
+        let right = 255.0 * (1.0 + pan.min(0.0));
+
+        (gain, left as u8, right as u8)
+    }
+
+    // Plays and empties everything systems pushed this tick
+    pub fn play_queued(&mut self, queue: &mut SoundQueue, volume: &AudioVolume, camera: &Camera) {
+        for sound in queue.sounds.drain(..) {
+            let (gain, left, right) = self.spatialize(sound.position, camera);
+            let gain = gain * sound.volume * volume.master * volume.sfx;
+            if gain <= 0.0 {
+                continue;
+            }
+
+            let chunk = match self.sound(&sound.sound) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+
+            // Every channel busy, drop the sound rather than cut another one off
+            if let Ok(channel) = Channel::all().play(chunk, 0) {
+                channel.set_volume((gain * MAX_VOLUME as f32) as i32);
+                let _ = channel.set_panning(left, right);
+            }
+        }
+
+        match queue.music.take() {
+            Some(MusicCommand::Play { track, fade_ms })
+                if self.current_track.as_deref() != Some(track.as_str()) =>
+            {
+                // SDL_mixer has a single music stream, so the tracks can not overlap. The old
+                // one fades out over the first half and the new one fades in over the second
+                if Music::is_playing() {
+                    let _ = Music::fade_out(fade_ms / 2);
+                    self.pending_track = Some((track, fade_ms / 2));
+                } else {
+                    self.pending_track = Some((track, fade_ms));
+                }
+            }
+            Some(MusicCommand::Stop { fade_ms }) => {
+                let _ = Music::fade_out(fade_ms);
+                self.pending_track = None;
+                self.current_track = None;
+            }
+            _ => {}
+        }
+
+        if !Music::is_playing() {
+            if let Some((track, fade_ms)) = self.pending_track.take() {
+                if let Some(music) = self.track(&track) {
+                    let _ = music.fade_in(-1, fade_ms);
+                }
+                self.current_track = Some(track);
+            }
+        }
+
+        Music::set_volume((volume.master * volume.music * MAX_VOLUME as f32) as i32);
+    }
+}
+
+impl Drop for AudioManager {
+    fn drop(&mut self) {
+        Channel::all().halt();
+        Music::halt();
+        self.sounds.clear();
+        self.tracks.clear();
+        mixer::close_audio();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single test, SDL may only be set up from one thread per process
+    #[test]
+    fn dummy_driver() {
+        std::env::set_var("SDL_AUDIODRIVER", "dummy");
+        let sdl = sdl2::init().unwrap();
+        let mut audio = AudioManager::new(
+            &sdl,
+            ScreenSize::Size {
+                width: 800,
+                height: 600,
+            },
+        )
+        .unwrap();
+
+        // Every path is loaded once, failures included
+        assert!(audio.preload("assets/hit.wav"));
+        assert!(!audio.preload("assets/missing.wav"));
+        assert!(audio.preload("assets/hit.wav"));
+        assert_eq!(audio.sounds.len(), 2);
+
+        let camera = Camera::default();
+        assert_eq!(audio.spatialize(None, &camera), (1.0, 255, 255));
+        assert_eq!(
+            audio.spatialize(Some(camera.point), &camera),
+            (1.0, 255, 255)
+        );
+        assert_eq!(
+            audio.spatialize(Some(Point::new(400, 0)), &camera),
+            (0.5, 0, 255)
+        );
+        assert_eq!(
+            audio.spatialize(Some(Point::new(-200, 0)), &camera),
+            (0.75, 255, 127)
+        );
+        assert_eq!(audio.spatialize(Some(Point::new(0, 900)), &camera).0, 0.0);
+
+        // Master and sfx multiply into the channel volume
+        let mut volume = AudioVolume {
+            master: 0.5,
+            music: 0.5,
+            sfx: 0.5,
+        };
+        let mut queue = SoundQueue::default();
+        queue.push(PlaySound::at("assets/hit.wav", camera.point));
+        audio.play_queued(&mut queue, &volume, &camera);
+        assert!(queue.sounds.is_empty());
+        assert_eq!(mixer::get_playing_channels_number(), 1);
+        assert_eq!(Channel(0).get_volume(), MAX_VOLUME / 4);
+        assert_eq!(Music::get_volume(), MAX_VOLUME / 4);
+        Channel::all().halt();
+
+        // A muted bus or a sound out of earshot never takes a channel
+        volume.sfx = 0.0;
+        queue.push(PlaySound::global("assets/hit.wav"));
+        audio.play_queued(&mut queue, &volume, &camera);
+        volume.sfx = 1.0;
+        queue.push(PlaySound::at("assets/hit.wav", Point::new(0, 900)));
+        audio.play_queued(&mut queue, &volume, &camera);
+        assert_eq!(mixer::get_playing_channels_number(), 0);
+    }
+}
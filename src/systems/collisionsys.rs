@@ -1,6 +1,7 @@
 use sdl2::rect::Rect;
 use specs::prelude::*;
 
+use crate::contacts::*;
 use crate::entity_components::*;
 
 pub struct CollisionSys;
@@ -12,6 +13,8 @@ impl<'a> System<'a> for CollisionSys {
         WriteStorage<'a, Velocity>,
         Entities<'a>,
         WriteStorage<'a, GravityAfflicted>,
+        ReadStorage<'a, RigidBody>,
+        Write<'a, Contacts>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
@@ -54,6 +57,7 @@ impl<'a> System<'a> for CollisionSys {
                                 Some(grav) => {
                                     grav.grounded = true;
                                     grav.grounded_rect = Some(trect.clone());
+                                    grav.ground_friction = tcolli.friction;
                                 }
                                 None => continue,
                             },
@@ -62,5 +66,35 @@ impl<'a> System<'a> for CollisionSys {
                     }
                 }
             });
+
+        // Rigid bodies are not stopped here, their overlaps become contacts for ContactSys
+        let mut contacts = Vec::new();
+        for (a, pos, coll, body) in (&data.3, &data.0, &data.1, &data.5).join() {
+            let center = body.center(pos);
+            for (b, tpos, tcoll) in (&data.3, &data.0, &data.1).join() {
+                // Pairs of rigid bodies are only reported once
+                if a == b || (data.5.contains(b) && b.id() < a.id()) {
+                    continue;
+                }
+                let tcenter = match data.5.get(b) {
+                    Some(tbody) => tbody.center(tpos),
+                    None => (tpos.point.x() as f32, tpos.point.y() as f32),
+                };
+                if let Some((normal, depth)) = box_overlap(
+                    center,
+                    half_extents(coll.col_box),
+                    tcenter,
+                    half_extents(tcoll.col_box),
+                ) {
+                    contacts.push(Contact {
+                        a,
+                        b,
+                        normal,
+                        depth,
+                    });
+                }
+            }
+        }
+        data.6.contacts = contacts;
     }
 }
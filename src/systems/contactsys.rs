@@ -0,0 +1,117 @@
+use specs::prelude::*;
+
+use crate::contacts::*;
+use crate::entity_components::*;
+use crate::entity_platform::*;
+
+pub struct ContactSys;
+
+// More passes let pushes travel through stacks of boxes
+const SOLVER_ITERATIONS: usize = 8;
+// Impacts slower than this do not bounce, so resting bodies settle instead of jittering
+const BOUNCE_THRESHOLD: f32 = 2.0;
+// Friction coefficient between two surfaces of friction 1.0
+const CONTACT_FRICTION: f32 = 0.3;
+
+impl<'a> System<'a> for ContactSys {
+    type SystemData = (
+        Read<'a, Contacts>,
+        WriteStorage<'a, RigidBody>,
+        WriteStorage<'a, Position>,
+        ReadStorage<'a, Collideable>,
+        ReadStorage<'a, Mass>,
+        ReadStorage<'a, PlatformPath>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for _ in 0..SOLVER_ITERATIONS {
+            for contact in data.0.contacts.iter() {
+                let (coll_a, coll_b) = match (data.3.get(contact.a), data.3.get(contact.b)) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => continue,
+                };
+                let (pos_a, pos_b) = match (data.2.get(contact.a), data.2.get(contact.b)) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => continue,
+                };
+
+                // Bodies without a RigidBody are immovable, moving platforms lend their velocity
+                let (center_a, vel_a, inv_a, bounce_a) = match data.1.get(contact.a) {
+                    Some(body) => (
+                        body.center(pos_a),
+                        body.velocity,
+                        Mass::inverse(data.4.get(contact.a)),
+                        body.restitution,
+                    ),
+                    None => continue,
+                };
+                let (center_b, vel_b, inv_b, bounce_b) = match data.1.get(contact.b) {
+                    Some(body) => (
+                        body.center(pos_b),
+                        body.velocity,
+                        Mass::inverse(data.4.get(contact.b)),
+                        body.restitution,
+                    ),
+                    None => (
+                        (pos_b.point.x() as f32, pos_b.point.y() as f32),
+                        data.5
+                            .get(contact.b)
+                            .map_or((0.0, 0.0), |p| (p.velocity.0 as f32, p.velocity.1 as f32)),
+                        0.0,
+                        1.0,
+                    ),
+                };
+                let inv_total = inv_a + inv_b;
+                if inv_total == 0.0 {
+                    continue;
+                }
+
+                // Earlier passes may already have pushed the pair apart
+                let (normal, depth) = match box_overlap(
+                    center_a,
+                    half_extents(coll_a.col_box),
+                    center_b,
+                    half_extents(coll_b.col_box),
+                ) {
+                    Some(overlap) => overlap,
+                    None => continue,
+                };
+
+                let rel = (vel_a.0 - vel_b.0, vel_a.1 - vel_b.1);
+                let approach = rel.0 * normal.0 + rel.1 * normal.1;
+                let mut impulse = (0.0, 0.0);
+                if approach > 0.0 {
+                    let restitution = if approach < BOUNCE_THRESHOLD {
+                        0.0
+                    } else {
+                        bounce_a.min(bounce_b)
+                    };
+                    let j = (1.0 + restitution) * approach / inv_total;
+
+                    let tangent = (-normal.1, normal.0);
+                    let slide = rel.0 * tangent.0 + rel.1 * tangent.1;
+                    let mu = CONTACT_FRICTION * coll_a.friction * coll_b.friction;
+                    let jt = (slide / inv_total).clamp(-mu * j, mu * j);
+
+                    impulse = (normal.0 * j + tangent.0 * jt, normal.1 * j + tangent.1 * jt);
+                }
+
+                let push = depth / inv_total;
+                if let (Some(body), Some(pos)) =
+                    (data.1.get_mut(contact.a), data.2.get_mut(contact.a))
+                {
+                    body.velocity.0 -= impulse.0 * inv_a;
+                    body.velocity.1 -= impulse.1 * inv_a;
+                    body.shift(pos, -normal.0 * push * inv_a, -normal.1 * push * inv_a);
+                }
+                if let (Some(body), Some(pos)) =
+                    (data.1.get_mut(contact.b), data.2.get_mut(contact.b))
+                {
+                    body.velocity.0 += impulse.0 * inv_b;
+                    body.velocity.1 += impulse.1 * inv_b;
+                    body.shift(pos, normal.0 * push * inv_b, normal.1 * push * inv_b);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use sdl2::rect::Rect;
+use specs::prelude::*;
+
+use crate::commands::*;
+use crate::entity_components::*;
+
+pub struct ControllerSys;
+
+impl<'a> System<'a> for ControllerSys {
+    type SystemData = (
+        WriteStorage<'a, PlatformerController>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, GravityAfflicted>,
+        WriteStorage<'a, ActionQueue>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Collideable>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        // Walls for wall jumping are the static collideables
+        let terrain: Vec<Rect> = (&data.4, &data.5, !&data.1)
+            .join()
+            .map(|(pos, coll, _)| {
+                Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height())
+            })
+            .collect();
+
+        (
+            &mut data.0,
+            &mut data.1,
+            &mut data.2,
+            (&mut data.3).maybe(),
+            &data.4,
+            (&data.5).maybe(),
+        )
+            .par_join()
+            .for_each(|(ctrl, vel, grav, queue, pos, coll)| {
+                if let Some(queue) = queue {
+                    let before = queue.actions.len();
+                    queue.actions.retain(|a| *a != ActionCommand::Jump);
+                    if queue.actions.len() != before {
+                        ctrl.buffer = ctrl.buffer_ticks.max(1);
+                        ctrl.jump_held = true;
+                    }
+                }
+
+                if grav.grounded {
+                    ctrl.coyote = ctrl.coyote_ticks.max(1);
+                    ctrl.air_jumps = 0;
+                    ctrl.jumping = false;
+                }
+
+                let wall = match coll {
+                    Some(coll) if ctrl.wall_jump && !grav.grounded => {
+                        let body = Rect::from_center(
+                            pos.point,
+                            coll.col_box.width(),
+                            coll.col_box.height(),
+                        );
+                        let touches = |dx: i32| {
+                            let probe =
+                                Rect::new(body.x() + dx, body.y(), body.width(), body.height());
+                            terrain.iter().any(|t| t.has_intersection(probe))
+                        };
+                        if touches(-1) {
+                            -1
+                        } else if touches(1) {
+                            1
+                        } else {
+                            0
+                        }
+                    }
+                    _ => 0,
+                };
+
+                let (mut x_speed, mut y_speed) = vel.unencode_speed();
+
+                if ctrl.buffer > 0 {
+                    let jumped = if ctrl.coyote > 0 {
+                        true
+                    } else if wall != 0 {
+                        x_speed = -(wall as i8) * ctrl.wall_jump_push;
+                        true
+                    } else if ctrl.air_jumps < ctrl.max_air_jumps {
+                        ctrl.air_jumps += 1;
+                        true
+                    } else {
+                        false
+                    };
+
+                    if jumped {
+                        y_speed = -ctrl.jump_speed;
+                        ctrl.buffer = 0;
+                        ctrl.coyote = 0;
+                        ctrl.jumping = true;
+                        grav.grounded = false;
+                        grav.grounded_rect = None;
+                    } else {
+                        ctrl.buffer -= 1;
+                    }
+                }
+
+                if !grav.grounded && ctrl.coyote > 0 {
+                    ctrl.coyote -= 1;
+                }
+
+                // Letting go early cuts the rise short
+                if ctrl.jumping && !ctrl.jump_held && y_speed < -ctrl.jump_cut {
+                    y_speed = -ctrl.jump_cut;
+                }
+                if y_speed >= 0 {
+                    ctrl.jumping = false;
+                }
+
+                vel.encode_speed(x_speed, y_speed);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity_flags::Player;
+    use crate::level::Level;
+    use crate::profiler::Profiler;
+    use crate::simulation::Simulation;
+
+    // Player height and vertical speed after each tick, up is held for the first `held` ticks
+    // and jump is pressed on the first. Nothing overhead, the default level has a platform
+    // drifting past its spawn
+    fn run(spawn_y: i32, held: usize, jump: bool) -> Vec<(i32, i8)> {
+        let source = format!("spawn 0,{}\nblock -400,100,800,600", spawn_y);
+        let level = Level::parse(&source).unwrap();
+        let mut sim = Simulation::from_level(7, true, level, Profiler::default()).unwrap();
+        let player = {
+            let players = sim.world.read_storage::<Player>();
+            (&sim.world.entities(), &players).join().next().unwrap().0
+        };
+
+        (0..120)
+            .map(|tick| {
+                let y = if tick < held { -1 } else { 0 };
+                sim.step(&TickInput {
+                    movement: Some(MovementCommand::Move(Direction::MoveDelta { x: 0, y })),
+                    actions: if jump && tick == 0 {
+                        vec![ActionCommand::Jump]
+                    } else {
+                        Vec::new()
+                    },
+                });
+                let y = sim
+                    .world
+                    .read_storage::<Position>()
+                    .get(player)
+                    .unwrap()
+                    .point
+                    .y();
+                let vel = sim
+                    .world
+                    .read_storage::<Velocity>()
+                    .get(player)
+                    .unwrap()
+                    .unencode_speed();
+                (y, vel.1)
+            })
+            .collect()
+    }
+
+    fn jump_height(held: usize) -> i32 {
+        // Settle on the ground first
+        let ground = run(80, 0, false).last().unwrap().0;
+        let ticks = run(ground, held, true);
+        ground - ticks.iter().map(|t| t.0).min().unwrap()
+    }
+
+    #[test]
+    fn held_jump_rises_higher_than_tapped() {
+        let held = jump_height(30);
+        let tapped = jump_height(1);
+        assert!(tapped > 0);
+        assert!(held >= tapped * 2, "held {} tapped {}", held, tapped);
+    }
+
+    #[test]
+    fn falls_faster_than_run_speed() {
+        // The player runs at 4 at most but falls at up to the gravity cap of 20
+        let fall = run(-200, 0, false).iter().map(|t| t.1).max().unwrap();
+        assert_eq!(fall, 20);
+    }
+}
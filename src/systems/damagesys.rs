@@ -1,69 +1,344 @@
 use sdl2::rect::{Point, Rect};
 use specs::prelude::*;
+use specs::shrev::EventChannel;
 
+use crate::entity_flags::GodMode;
+use crate::events::*;
+use crate::globalcomponents::*;
 use crate::{entity_components::*, SingleDamage};
 
 pub struct DamageSys;
 
+// Whether a damage box moving by (dx, dy) this tick touches the hurt box
+fn swept_hit(hurt_box: Rect, dmg_box: Rect, dx: i8, dy: i8) -> bool {
+    if hurt_box.has_intersection(dmg_box) {
+        return true;
+    }
+
+    let dmg_box_offset = Rect::from_center(
+        dmg_box.center().offset(dx as i32, dy as i32),
+        dmg_box.width(),
+        dmg_box.height(),
+    );
+    let mut line_vec: Vec<(Point, Point)> = Vec::<(Point, Point)>::new();
+    line_vec.push((dmg_box.bottom_left(), dmg_box_offset.bottom_left()));
+    line_vec.push((dmg_box.bottom_right(), dmg_box_offset.bottom_right()));
+    line_vec.push((dmg_box.top_left(), dmg_box_offset.top_left()));
+    line_vec.push((dmg_box.top_right(), dmg_box_offset.top_right()));
+
+    let mut hit = false;
+    for (p1, p2) in line_vec {
+        match hurt_box.intersect_line(p1, p2) {
+            Some(_) => {
+                hit = true;
+            }
+            None => {}
+        }
+    }
+    hit
+}
+
 impl<'a> System<'a> for DamageSys {
     type SystemData = (
         WriteStorage<'a, Health>,
-        ReadStorage<'a, Damage>,
-        ReadStorage<'a, Velocity>,
+        WriteStorage<'a, Damage>,
+        WriteStorage<'a, Velocity>,
         ReadStorage<'a, Position>,
         Entities<'a>,
         ReadStorage<'a, SingleDamage>,
+        Write<'a, SoundQueue>,
+        WriteStorage<'a, ProjectileProperties>,
+        ReadStorage<'a, Team>,
+        Read<'a, TeamRules>,
+        WriteStorage<'a, Score>,
+        ReadStorage<'a, Resistances>,
+        WriteStorage<'a, Invulnerability>,
+        Write<'a, EventChannel<DamageDealt>>,
+        Write<'a, EventChannel<Died>>,
+        ReadStorage<'a, Mass>,
+        WriteStorage<'a, RigidBody>,
+        ReadStorage<'a, GodMode>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
         let entities = &data.4;
-        (&mut data.0, &data.3, &data.4)
-            .par_join()
-            .for_each(|(health, pos, entity)| {
-                let hurt_box =
-                    Rect::from_center(pos.point, health.hurt_box.width(), health.hurt_box.height());
-
-                for (dmg, vel, pos, sdflag, tent) in
-                    (&data.1, &data.2, &data.3, (&data.5).maybe(), &data.4).join()
+        // Damage sources used up this tick stay alive until the world is maintained
+        let mut spent = BitSet::new();
+        let mut knockbacks: Vec<(Entity, i8, i8)> = Vec::new();
+        // Melee swings that landed, recorded once the targets have been gone through
+        let mut swing_hits: Vec<(Entity, Entity)> = Vec::new();
+
+        for inv in (&mut data.12).join() {
+            if inv.remaining > 0 {
+                inv.remaining -= 1;
+            }
+        }
+
+        for (health, pos, entity) in (&mut data.0, &data.3, &data.4).join() {
+            // Already dead, waiting on DeathSys
+            if health.hp == 0 {
+                continue;
+            }
+            // Toggled from the console, nothing gets through
+            if data.17.contains(entity) {
+                continue;
+            }
+            let target_pos = pos.point;
+            let mirrored = data.2.get(entity).is_some_and(|v| v.facing_left());
+            let hurt_boxes: Vec<Rect> = health
+                .hurt_boxes
+                .iter()
+                .map(|b| pos.place_box(*b, mirrored))
+                .collect();
+
+            for (dmg, vel, pos, sdflag, mut proj, tent) in (
+                &data.1,
+                (&data.2).maybe(),
+                &data.3,
+                (&data.5).maybe(),
+                (&mut data.7).maybe(),
+                &data.4,
+            )
+                .join()
+            {
+                if spent.contains(tent.id()) {
+                    continue;
+                }
+                if data.12.get(entity).is_some_and(|inv| inv.remaining > 0) {
+                    break;
+                }
+                if tent == entity {
+                    continue;
+                }
+                // Projectiles hit for their owner, anything else like a melee swing for itself
+                let attacker = match &proj {
+                    Some(proj) => proj.owner,
+                    None => Some(tent),
+                };
+                let attacker_team = match &proj {
+                    Some(proj) => proj.team,
+                    None => data.8.get(tent).map(|t| t.id),
+                };
+                if attacker == Some(entity)
+                    || !data
+                        .9
+                        .can_damage(attacker_team, data.8.get(entity).map(|t| t.id))
                 {
-                    let dmg_box =
-                        Rect::from_center(pos.point, dmg.dmg_box.width(), dmg.dmg_box.height());
-                    let (dmg_x_off, dmg_y_off) = vel.unencode_speed();
-                    let dmg_box_offset = Rect::from_center(
-                        pos.point.offset(dmg_x_off as i32, dmg_y_off as i32),
-                        dmg.dmg_box.width(),
-                        dmg.dmg_box.height(),
-                    );
-                    let mut line_vec: Vec<(Point, Point)> = Vec::<(Point, Point)>::new();
-                    line_vec.push((dmg_box.bottom_left(), dmg_box_offset.bottom_left()));
-                    line_vec.push((dmg_box.bottom_right(), dmg_box_offset.bottom_right()));
-                    line_vec.push((dmg_box.top_left(), dmg_box_offset.top_left()));
-                    line_vec.push((dmg_box.top_right(), dmg_box_offset.top_right()));
-
-                    let mut hit = false;
-                    for (p1, p2) in line_vec {
-                        match hurt_box.intersect_line(p1, p2) {
-                            Some(_) => {
-                                hit = true;
-                            }
-                            None => {}
-                        }
+                    continue;
+                }
+                if proj.as_ref().is_some_and(|p| p.hits.contains(&entity))
+                    || dmg.hits.contains(&entity)
+                {
+                    continue;
+                }
+
+                let (dmg_x_off, dmg_y_off) = vel.map_or((0, 0), |v| v.unencode_speed());
+                let mirrored = vel.is_some_and(|v| v.facing_left());
+                let hit = dmg.dmg_boxes.iter().any(|b| {
+                    let dmg_box = pos.place_box(*b, mirrored);
+                    hurt_boxes
+                        .iter()
+                        .any(|hurt_box| swept_hit(*hurt_box, dmg_box, dmg_x_off, dmg_y_off))
+                });
+
+                if hit {
+                    let amount = match data.11.get(entity) {
+                        Some(res) => res.scale(dmg.kind, dmg.dmg),
+                        None => dmg.dmg,
+                    };
+
+                    health.hp = health.hp.saturating_sub(amount);
+                    if attacker.is_some() {
+                        health.last_attacker = attacker;
+                    }
+                    if let Some(score) = attacker.and_then(|a| data.10.get_mut(a)) {
+                        score.damage_dealt += amount;
+                    }
+                    if let Some(inv) = data.12.get_mut(entity) {
+                        inv.remaining = inv.duration;
+                    }
+
+                    // Push the target away from where the hit came from, with a small hop
+                    if dmg.knockback != 0 {
+                        let away = (target_pos.x() - pos.point.x()).signum() as i8;
+                        knockbacks.push((entity, away * dmg.knockback, -dmg.knockback / 2));
                     }
 
-                    if hit {
-                        health.hp -= dmg.dmg;
-                        match sdflag {
-                            Some(_) => {
-                                let _ = entities.delete(tent);
-                            }
-                            None => {}
+                    data.13.single_write(DamageDealt {
+                        attacker,
+                        target: entity,
+                        amount,
+                        kind: dmg.kind,
+                    });
+                    data.6.push(PlaySound::at("assets/hit.wav", pos.point));
+
+                    match sdflag {
+                        Some(_) => {
+                            let _ = entities.delete(tent);
+                            spent.add(tent.id());
+                        }
+                        None => {}
+                    }
+                    if let Some(proj) = &mut proj {
+                        if proj.pierce == 0 {
+                            let _ = entities.delete(tent);
+                            spent.add(tent.id());
+                        } else {
+                            proj.pierce -= 1;
+                            proj.hits.push(entity);
                         }
+                    } else {
+                        swing_hits.push((tent, entity));
+                    }
+
+                    if health.hp == 0 {
+                        break;
                     }
                 }
+            }
 
-                if health.hp <= 0 {
-                    let _ = entities.delete(entity);
+            if health.hp == 0 {
+                // Credit the kill to whoever landed the last hit
+                if let Some(score) = health.last_attacker.and_then(|a| data.10.get_mut(a)) {
+                    score.kills += 1;
                 }
+                data.14.single_write(Died {
+                    entity,
+                    killer: health.last_attacker,
+                    position: target_pos,
+                });
+            }
+        }
+
+        for (source, target) in swing_hits {
+            if let Some(dmg) = data.1.get_mut(source) {
+                dmg.hits.push(target);
+            }
+        }
+
+        for (entity, x, y) in knockbacks {
+            // Rigid bodies take the push as an impulse and scale it by their own mass
+            if let Some(body) = data.16.get_mut(entity) {
+                body.apply_impulse(x as f32, y as f32);
+                continue;
+            }
+            // Heavier targets are pushed less
+            let mass = data.15.get(entity).map_or(1.0, |m| m.mass.max(0.01));
+            if let Some(vel) = data.2.get_mut(entity) {
+                vel.accelerate(x as f32 / mass, y as f32 / mass);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+    use crate::profiler::Profiler;
+    use crate::rect;
+    use crate::simulation::Simulation;
+
+    // Away from the player, which spawns at 0,0
+    const SPOT: (i32, i32) = (1000, 0);
+
+    fn world() -> World {
+        let level = Level::parse("spawn 0,0").unwrap();
+        Simulation::from_level(1, true, level, Profiler::default())
+            .unwrap()
+            .world
+    }
+
+    fn target(world: &mut World, team: Option<u32>, iframes: u32) -> Entity {
+        let mut builder = world
+            .create_entity()
+            .with(Position { point: SPOT.into() })
+            .with(Health {
+                hp: 100,
+                max_hp: 100,
+                hurt_boxes: vec![rect!(0, 0, 16, 16)],
+                last_attacker: None,
+            })
+            .with(Invulnerability {
+                duration: iframes,
+                remaining: 0,
+            });
+        if let Some(id) = team {
+            builder = builder.with(Team { id });
+        }
+        builder.build()
+    }
+
+    // A melee swing overlapping the target
+    fn swing(world: &mut World, team: Option<u32>) -> Entity {
+        let mut builder = world
+            .create_entity()
+            .with(Position { point: SPOT.into() })
+            .with(Damage {
+                dmg: 10,
+                dmg_boxes: vec![rect!(0, 0, 16, 16)],
+                kind: DamageKind::Physical,
+                hits: Vec::new(),
+                knockback: 0,
             });
+        if let Some(id) = team {
+            builder = builder.with(Team { id });
+        }
+        builder.build()
+    }
+
+    fn hp(world: &mut World, target: Entity) -> u32 {
+        DamageSys.run_now(world);
+        world.maintain();
+        world.read_storage::<Health>().get(target).unwrap().hp
+    }
+
+    #[test]
+    fn invulnerability_blocks_hits_until_it_wears_off() {
+        let mut world = world();
+        let target = target(&mut world, None, 3);
+        swing(&mut world, None);
+        let second = swing(&mut world, None);
+
+        // The second swing lands once the three ticks are over, the first never again
+        let hps: Vec<u32> = (0..5).map(|_| hp(&mut world, target)).collect();
+        assert_eq!(hps, [90, 90, 90, 80, 80]);
+        let health = world.read_storage::<Health>();
+        assert_eq!(health.get(target).unwrap().last_attacker, Some(second));
+    }
+
+    #[test]
+    fn god_mode_takes_no_damage() {
+        let mut world = world();
+        let target = target(&mut world, None, 0);
+        world
+            .write_storage::<GodMode>()
+            .insert(target, GodMode)
+            .unwrap();
+        swing(&mut world, None);
+        assert_eq!(hp(&mut world, target), 100);
+
+        world.write_storage::<GodMode>().remove(target);
+        assert_eq!(hp(&mut world, target), 90);
+    }
+
+    #[test]
+    fn teams_decide_who_gets_hurt() {
+        let mut world = world();
+        let target = target(&mut world, Some(1), 0);
+        swing(&mut world, Some(1));
+        assert_eq!(hp(&mut world, target), 100);
+
+        // Other teams and the teamless always hit
+        swing(&mut world, Some(2));
+        assert_eq!(hp(&mut world, target), 90);
+        swing(&mut world, None);
+        assert_eq!(hp(&mut world, target), 80);
+
+        // The first swing is still there and now lands too
+        world.insert(TeamRules {
+            friendly_fire: true,
+        });
+        assert_eq!(hp(&mut world, target), 70);
     }
 }
@@ -0,0 +1,30 @@
+use specs::prelude::*;
+use specs::shrev::{EventChannel, ReaderId};
+
+use crate::events::*;
+
+// Deletes entities once their Died event is out, runs after everything that reacts to deaths
+#[derive(Default)]
+pub struct DeathSys {
+    reader: Option<ReaderId<Died>>,
+}
+
+impl<'a> System<'a> for DeathSys {
+    type SystemData = (Entities<'a>, Read<'a, EventChannel<Died>>);
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.reader = Some(world.fetch_mut::<EventChannel<Died>>().register_reader());
+    }
+
+    fn run(&mut self, (entities, died): Self::SystemData) {
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => return,
+        };
+
+        for event in died.read(reader) {
+            let _ = entities.delete(event.entity);
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use sdl2::rect::Rect;
 use specs::prelude::*;
 
 use crate::entity_components::*;
+use crate::globalcomponents::*;
 
 pub struct GravitySys;
 
@@ -8,18 +10,38 @@ impl<'a> System<'a> for GravitySys {
     type SystemData = (
         ReadStorage<'a, GravityAfflicted>,
         WriteStorage<'a, Velocity>,
+        Read<'a, PhysicsConfig>,
+        ReadStorage<'a, GravityScale>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, GravityZone>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
-        (&data.0, &mut data.1)
+        let config = *data.2;
+        let zones: Vec<(Rect, f32)> = (&data.4, &data.5)
+            .join()
+            .map(|(pos, zone)| (pos.place_box(zone.area, false), zone.scale))
+            .collect();
+
+        (&data.0, &mut data.1, (&data.3).maybe(), (&data.4).maybe())
             .par_join()
-            .filter(|(grav, _)| grav.grounded != true)
-            .for_each(|(grav, vel)| {
-                let (x_cur, mut y_cur) = vel.unencode_speed();
-                y_cur += 1;
+            .filter(|(grav, _, _, _)| grav.grounded != true)
+            .for_each(|(grav, vel, scale, pos)| {
+                let mut scale = scale.map_or(1.0, |s| s.scale);
+                if let Some(pos) = pos {
+                    for (area, zone_scale) in zones.iter() {
+                        if area.contains_point(pos.point) {
+                            scale *= zone_scale;
+                        }
+                    }
+                }
+                vel.accelerate(config.gravity.0 * scale, config.gravity.1 * scale);
 
+                let (x_cur, mut y_cur) = vel.unencode_speed();
                 if y_cur as i32 > grav.max_vel {
                     y_cur = grav.max_vel as i8;
+                } else if (y_cur as i32) < -grav.max_vel {
+                    y_cur = -grav.max_vel as i8;
                 }
                 vel.encode_speed(x_cur, y_cur);
             });
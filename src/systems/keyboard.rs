@@ -6,48 +6,72 @@ use crate::entity_flags::*;
 
 pub struct Keyboard;
 
+fn apply_movement(
+    movement: MovementCommand,
+    vel: &mut Velocity,
+    ctrl: Option<&mut PlatformerController>,
+    grav: Option<&GravityAfflicted>,
+) {
+    match movement {
+        MovementCommand::Move(dir) => match ctrl {
+            // Platformers move sideways only, up is the jump button
+            Some(ctrl) => match dir {
+                Direction::MoveDelta { x, y } => {
+                    ctrl.jump_held = y < 0;
+                    ctrl.steer(vel, x, grav.is_some_and(|g| g.grounded));
+                }
+            },
+            None => vel.steer(dir),
+        },
+    }
+}
+
 impl<'a> System<'a> for Keyboard {
     type SystemData = (
         ReadExpect<'a, Option<MovementCommand>>,
         ReadStorage<'a, KeyboardControlled>,
         WriteStorage<'a, Velocity>,
+        ReadExpect<'a, Vec<ActionCommand>>,
+        WriteStorage<'a, ActionQueue>,
+        WriteStorage<'a, PlatformerController>,
+        ReadStorage<'a, GravityAfflicted>,
+        ReadStorage<'a, NetPlayer>,
+        Read<'a, PlayerInputs>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
-        let movement_command = match &*data.0 {
-            Some(movement_command) => movement_command,
-            None => return,
-        };
-
-        (&data.1, &mut data.2)
-            .par_join()
-            .for_each(|(_, vel)| match movement_command {
-                &MovementCommand::Move(dir) => match dir {
-                    Direction::MoveDelta { x, y } => {
-                        let (mut x_cur, mut y_cur) = vel.unencode_speed();
+        for (_, queue) in (&data.1, &mut data.4).join() {
+            queue.actions.extend(data.3.iter().copied());
+        }
+        for (player, queue) in (&data.7, &mut data.4).join() {
+            if let Some(input) = data.8.inputs.get(player.slot) {
+                queue.actions.extend(input.actions.iter().copied());
+            }
+        }
 
-                        x_cur = x_cur + (x * (vel.acc as i8));
-                        y_cur = y_cur + (y * (vel.acc as i8));
+        if let Some(movement) = *data.0 {
+            (
+                &data.1,
+                &mut data.2,
+                (&mut data.5).maybe(),
+                (&data.6).maybe(),
+            )
+                .par_join()
+                .for_each(|(_, vel, ctrl, grav)| apply_movement(movement, vel, ctrl, grav));
+        }
 
-                        if x_cur >= vel.max_speed {
-                            x_cur = vel.max_speed;
-                        } else if x_cur <= -vel.max_speed {
-                            x_cur = -vel.max_speed;
-                        }
-
-                        if y_cur >= vel.max_speed {
-                            y_cur = vel.max_speed;
-                        } else if y_cur <= -vel.max_speed {
-                            y_cur = -vel.max_speed;
-                        }
-
-                        vel.encode_speed(x_cur, y_cur);
-
-                        if x != 0 {
-                            vel.last_dir = Some(dir);
-                        }
-                    }
-                },
+        let inputs = &data.8.inputs;
+        (
+            &data.7,
+            &mut data.2,
+            (&mut data.5).maybe(),
+            (&data.6).maybe(),
+        )
+            .par_join()
+            .for_each(|(player, vel, ctrl, grav)| {
+                if let Some(movement) = inputs.get(player.slot).and_then(|i| i.movement) {
+                    apply_movement(movement, vel, ctrl, grav);
+                }
             });
     }
 }
@@ -1,10 +1,23 @@
 pub mod actionsys;
+pub mod aisys;
 pub mod animator;
+#[cfg(feature = "video")]
+pub mod audio;
 pub mod collisionsys;
+pub mod contactsys;
+pub mod controllersys;
 pub mod damagesys;
+pub mod deathsys;
 pub mod decaysys;
 pub mod gravitysys;
 pub mod keyboard;
+pub mod navsys;
+pub mod particlesys;
 pub mod physics;
+pub mod pickupsys;
+pub mod platformsys;
 pub mod projectilesys;
+#[cfg(feature = "video")]
 pub mod renderer;
+pub mod rigidbodysys;
+pub mod scriptsys;
@@ -0,0 +1,37 @@
+use sdl2::rect::Rect;
+use specs::prelude::*;
+
+use crate::entity_components::*;
+use crate::entity_platform::*;
+use crate::navigation::*;
+
+pub struct NavSys;
+
+impl<'a> System<'a> for NavSys {
+    type SystemData = (
+        Write<'a, NavGrid>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Collideable>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, RigidBody>,
+        ReadStorage<'a, PlatformPath>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        // Anything that moves is an agent, not terrain
+        let terrain: Vec<Rect> = (&data.1, &data.2, !&data.3, !&data.4, !&data.5)
+            .join()
+            .map(|(pos, coll, _, _, _)| {
+                Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height())
+            })
+            .collect();
+        if !data.0.dirty && !data.0.terrain_changed(&terrain) {
+            return;
+        }
+
+        // The grid spans the whole level, no agent can walk outside the terrain anyway
+        if let Some(bounds) = terrain.iter().copied().reduce(|a, b| a.union(b)) {
+            data.0.rebuild(bounds, &terrain);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use specs::prelude::*;
+use specs::shrev::{EventChannel, ReaderId};
+
+use crate::entity_components::*;
+use crate::events::*;
+use crate::particles::*;
+
+// Emits, moves and retires particles, and spawns the built in hit and landing effects
+#[derive(Default)]
+pub struct ParticleSys {
+    damage_reader: Option<ReaderId<DamageDealt>>,
+    grounded: BitSet,
+}
+
+impl<'a> System<'a> for ParticleSys {
+    type SystemData = (
+        Write<'a, ParticlePool>,
+        WriteStorage<'a, ParticleEmitter>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, GravityAfflicted>,
+        ReadStorage<'a, Collideable>,
+        Read<'a, EventChannel<DamageDealt>>,
+        Entities<'a>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.damage_reader = Some(
+            world
+                .fetch_mut::<EventChannel<DamageDealt>>()
+                .register_reader(),
+        );
+    }
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let pool = &mut *data.0;
+
+        for (emitter, pos) in (&mut data.1, &data.2).join() {
+            if !emitter.active {
+                continue;
+            }
+            let x = (pos.point.x() + emitter.offset.x()) as f32;
+            let y = (pos.point.y() + emitter.offset.y()) as f32;
+            emitter.accumulator += emitter.config.rate;
+            while emitter.accumulator >= 1.0 {
+                pool.emit(&emitter.config, x, y);
+                emitter.accumulator -= 1.0;
+            }
+        }
+
+        if let Some(reader) = &mut self.damage_reader {
+            let impact = EmitterConfig::impact();
+            for event in data.5.read(reader) {
+                if let Some(pos) = data.2.get(event.target) {
+                    pool.burst(&impact, pos.point.x() as f32, pos.point.y() as f32);
+                }
+            }
+        }
+
+        // Kick up dust on the tick an entity lands
+        let dust = EmitterConfig::dust();
+        let mut grounded = BitSet::new();
+        for (grav, pos, coll, entity) in (&data.3, &data.2, &data.4, &data.6).join() {
+            if !grav.grounded {
+                continue;
+            }
+            grounded.add(entity.id());
+            if !self.grounded.contains(entity.id()) {
+                let feet = pos.point.y() + coll.col_box.height() as i32 / 2;
+                pool.burst(&dust, pos.point.x() as f32, feet as f32);
+            }
+        }
+        self.grounded = grounded;
+
+        pool.update();
+    }
+}
@@ -3,6 +3,7 @@ use specs::prelude::*;
 use crate::Rect;
 
 use crate::entity_components::*;
+use crate::globalcomponents::*;
 
 pub struct Physics;
 
@@ -12,65 +13,75 @@ impl<'a> System<'a> for Physics {
         WriteStorage<'a, Velocity>,
         WriteStorage<'a, GravityAfflicted>,
         ReadStorage<'a, Collideable>,
+        Read<'a, PhysicsConfig>,
+        ReadStorage<'a, Mass>,
+        ReadStorage<'a, Friction>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
+        let config = *data.4;
+
         (
             &mut data.0,
             &mut data.1,
             (&mut data.2).maybe(),
             (&mut data.3).maybe(),
+            (&data.5).maybe(),
+            (&data.6).maybe(),
         )
             .par_join()
-            .for_each(|(pos, vel, grav, coll)| {
-                let mut x_speed: i8 = (vel.speed & 0xff) as i8;
-                let mut y_speed: i8 = ((vel.speed >> 8) & 0xff) as i8;
+            .for_each(|(pos, vel, grav, coll, mass, friction)| {
+                let (x_speed, y_speed) = vel.unencode_speed();
                 pos.point = pos.point.offset(x_speed as i32, y_speed as i32);
-                if x_speed > 0 {
-                    x_speed -= 1;
-                } else if x_speed < 0 {
-                    x_speed += 1;
-                }
 
-                if y_speed < 0 {
-                    y_speed += 1;
-                }
+                let drag = config.air_drag / mass.map_or(1.0, |m| m.mass.max(0.01));
 
                 match grav {
-                    Some(grav) => match coll {
-                        Some(coll) => {
-                            let cur_rect = Rect::from_center(
-                                pos.point,
-                                coll.col_box.width(),
-                                coll.col_box.height(),
-                            );
+                    Some(grav) => {
+                        let x_loss = if grav.grounded {
+                            config.friction
+                                * grav.ground_friction
+                                * friction.map_or(1.0, |f| f.friction)
+                        } else {
+                            drag
+                        };
+                        // Drag only works against gravity, falling speed is capped by max_vel
+                        let y_loss = if (y_speed as f32) * config.gravity.1 < 0.0 {
+                            drag
+                        } else {
+                            0.0
+                        };
+                        vel.damp(x_loss, y_loss);
 
-                            match grav.grounded_rect {
-                                Some(grect) => {
-                                    match grect.intersect_line(
-                                        cur_rect.bottom_left(),
-                                        cur_rect.bottom_right(),
-                                    ) {
-                                        Some(_) => {}
-                                        None => {
-                                            grav.grounded = false;
-                                            grav.grounded_rect = None;
+                        match coll {
+                            Some(coll) => {
+                                let cur_rect = Rect::from_center(
+                                    pos.point,
+                                    coll.col_box.width(),
+                                    coll.col_box.height(),
+                                );
+
+                                match grav.grounded_rect {
+                                    Some(grect) => {
+                                        match grect.intersect_line(
+                                            cur_rect.bottom_left(),
+                                            cur_rect.bottom_right(),
+                                        ) {
+                                            Some(_) => {}
+                                            None => {
+                                                grav.grounded = false;
+                                                grav.grounded_rect = None;
+                                            }
                                         }
                                     }
+                                    None => {}
                                 }
-                                None => {}
                             }
-                        }
-                        None => {}
-                    },
-                    None => {
-                        if y_speed > 0 {
-                            y_speed -= 1;
+                            None => {}
                         }
                     }
+                    None => vel.damp(drag, drag),
                 }
-
-                vel.encode_speed(x_speed, y_speed);
             });
     }
 }
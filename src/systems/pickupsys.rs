@@ -0,0 +1,98 @@
+use sdl2::rect::Rect;
+use specs::prelude::*;
+use specs::shrev::EventChannel;
+
+use crate::entity_components::*;
+use crate::entity_flags::Player;
+use crate::events::*;
+
+pub struct PickupSys;
+
+impl<'a> System<'a> for PickupSys {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Pickup>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Collideable>,
+        WriteStorage<'a, Inventory>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Weapon>,
+        Write<'a, EventChannel<ItemGained>>,
+        Write<'a, SoundQueue>,
+        ReadStorage<'a, Player>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = &data.0;
+
+        for (pickup, pickup_pos, pickup_entity) in (&mut data.1, &data.2, entities).join() {
+            let area = pickup_pos.place_box(pickup.area, false);
+
+            // Only players and inventory holders collect, anything else just walks over
+            for (pos, coll, collector) in (&data.2, &data.3, entities).join() {
+                if collector == pickup_entity
+                    || !(data.9.contains(collector) || data.4.contains(collector))
+                {
+                    continue;
+                }
+                let body =
+                    Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height());
+                if !body.has_intersection(area) {
+                    continue;
+                }
+
+                let taken = match &mut pickup.kind {
+                    PickupKind::Heal(amount) => match data.5.get_mut(collector) {
+                        Some(health) if health.hp < health.max_hp => {
+                            let healed = health.heal(*amount);
+                            data.7.single_write(ItemGained {
+                                entity: collector,
+                                gained: Gained::Heal(healed),
+                            });
+                            true
+                        }
+                        _ => false,
+                    },
+                    PickupKind::Item(stack) => match data.4.get_mut(collector) {
+                        Some(inv) => {
+                            let left = inv.add(stack.item, stack.count);
+                            let gained = stack.count - left;
+                            if gained > 0 {
+                                data.7.single_write(ItemGained {
+                                    entity: collector,
+                                    gained: Gained::Item {
+                                        item: stack.item,
+                                        count: gained,
+                                    },
+                                });
+                            }
+                            // A full inventory leaves the rest lying on the ground
+                            stack.count = left;
+                            left == 0
+                        }
+                        None => false,
+                    },
+                    PickupKind::Weapon(weapon) => {
+                        if data.4.contains(collector) {
+                            let _ = data.6.insert(collector, (**weapon).clone());
+                            data.7.single_write(ItemGained {
+                                entity: collector,
+                                gained: Gained::Weapon,
+                            });
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                };
+
+                if taken {
+                    data.8
+                        .push(PlaySound::at("assets/pickup.wav", pickup_pos.point));
+                    let _ = entities.delete(pickup_entity);
+                    break;
+                }
+            }
+        }
+    }
+}
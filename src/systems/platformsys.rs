@@ -0,0 +1,99 @@
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+
+use crate::entity_components::*;
+use crate::entity_platform::*;
+
+pub struct PlatformSys;
+
+struct Move {
+    platform: Entity,
+    from: Rect,
+    to: Rect,
+}
+
+impl<'a> System<'a> for PlatformSys {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, PlatformPath>,
+        WriteStorage<'a, Position>,
+        ReadStorage<'a, Collideable>,
+        WriteStorage<'a, GravityAfflicted>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, RigidBody>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = &data.0;
+
+        let mut moves = Vec::new();
+        for (platform, path, pos, coll) in (entities, &mut data.1, &mut data.2, &data.3).join() {
+            let target = match path.advance() {
+                Some(target) => target,
+                None => continue,
+            };
+            let delta = target - pos.point;
+            path.velocity = (delta.x(), delta.y());
+
+            let size = (coll.col_box.width(), coll.col_box.height());
+            moves.push(Move {
+                platform,
+                from: Rect::from_center(pos.point, size.0, size.1),
+                to: Rect::from_center(target, size.0, size.1),
+            });
+            pos.point = target;
+        }
+        if moves.is_empty() {
+            return;
+        }
+
+        // Riders are not pushed into static terrain, the platform slides out from under them
+        let terrain: Vec<Rect> = (&data.2, &data.3, !&data.5, !&data.6, !&data.1)
+            .join()
+            .map(|(pos, coll, _, _, _)| {
+                Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height())
+            })
+            .collect();
+
+        let mut riders: Vec<(Entity, Entity)> = Vec::new();
+        for (rider, pos, grav, coll) in (entities, &mut data.2, &mut data.4, &data.3).join() {
+            let carrier = match moves.iter().find(|m| grav.grounded_rect == Some(m.from)) {
+                Some(carrier) => carrier,
+                None => continue,
+            };
+            let delta = carrier.to.center() - carrier.from.center();
+            let moved = Rect::from_center(
+                pos.point + delta,
+                coll.col_box.width(),
+                coll.col_box.height(),
+            );
+            if !terrain.iter().any(|t| t.has_intersection(moved)) {
+                pos.point += delta;
+            } else {
+                pos.point += Point::new(0, delta.y());
+            }
+            grav.grounded_rect = Some(carrier.to);
+            riders.push((carrier.platform, rider));
+        }
+
+        // Whoever stepped or jumped off keeps the platform's speed
+        for m in moves.iter() {
+            let path = match data.1.get_mut(m.platform) {
+                Some(path) => path,
+                None => continue,
+            };
+            let (dx, dy) = path.velocity;
+            let current: Vec<Entity> = riders
+                .iter()
+                .filter(|(p, _)| *p == m.platform)
+                .map(|(_, r)| *r)
+                .collect();
+            for left in path.riders.iter().filter(|r| !current.contains(r)) {
+                if let Some(vel) = data.5.get_mut(*left) {
+                    vel.accelerate(dx as f32, dy as f32);
+                }
+            }
+            path.riders = current;
+        }
+    }
+}
@@ -1,9 +1,97 @@
+use std::f32::consts::PI;
+
+use sdl2::rect::Point;
 use specs::prelude::*;
 
+use crate::entity_components::*;
+use crate::globalcomponents::*;
+
 pub struct ProjectileSys;
 
+// Shortest signed angle turning a onto b
+fn angle_between(a: f32, b: f32) -> f32 {
+    let mut d = (b - a) % (2.0 * PI);
+    if d > PI {
+        d -= 2.0 * PI;
+    } else if d < -PI {
+        d += 2.0 * PI;
+    }
+    d
+}
+
 impl<'a> System<'a> for ProjectileSys {
-    type SystemData = (Entities<'a>,);
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, ProjectileProperties>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Health>,
+        WriteStorage<'a, SpriteVec>,
+        ReadStorage<'a, GravityAfflicted>,
+        ReadStorage<'a, Team>,
+        Read<'a, TeamRules>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let targets: Vec<(Entity, Option<u32>, Point)> =
+            (&data.0, &data.4, &data.3, (&data.7).maybe())
+                .join()
+                .map(|(e, _, pos, team)| (e, team.map(|t| t.id), pos.point))
+                .collect();
+        let rules = &*data.8;
+
+        (
+            &mut data.1,
+            &mut data.2,
+            &data.3,
+            (&mut data.5).maybe(),
+            (&data.6).maybe(),
+        )
+            .par_join()
+            .for_each(|(proj, vel, pos, sprites, grav)| {
+                if proj.homing > 0.0 && grav.is_none() {
+                    let target = targets
+                        .iter()
+                        .filter(|(e, team, _)| {
+                            proj.owner != Some(*e) && rules.can_damage(proj.team, *team)
+                        })
+                        .min_by_key(|(_, _, p)| {
+                            (p.x() - pos.point.x()).pow(2) + (p.y() - pos.point.y()).pow(2)
+                        });
+                    if let Some((_, _, t)) = target {
+                        let wanted =
+                            ((t.y() - pos.point.y()) as f32).atan2((t.x() - pos.point.x()) as f32);
+                        let turn = angle_between(proj.heading, wanted);
+                        proj.heading += turn.clamp(-proj.homing, proj.homing);
+                    }
+                }
+
+                let x_speed = (proj.heading.cos() * proj.speed as f32).round() as i8;
+                let y_speed = (proj.heading.sin() * proj.speed as f32).round() as i8;
+
+                if grav.is_some() {
+                    // Arcing projectiles get their launch speed once, gravity takes it from there.
+                    // The heading only turns the sprite along the arc
+                    if !proj.launched {
+                        vel.encode_speed(x_speed, y_speed);
+                    }
+                    let (x_cur, y_cur) = vel.unencode_speed();
+                    if x_cur != 0 || y_cur != 0 {
+                        proj.heading = (y_cur as f32).atan2(x_cur as f32);
+                    }
+                } else {
+                    vel.encode_speed(x_speed, y_speed);
+                }
+                proj.launched = true;
 
-    fn run(&mut self, _data: Self::SystemData) {}
+                if let Some(sprites) = sprites {
+                    for sprite in sprites.sprite_vec.iter_mut() {
+                        sprite.rotation = Some(Rotation {
+                            rotation: proj.heading.to_degrees() as f64,
+                            rot_point: None,
+                        });
+                    }
+                }
+            });
+    }
 }
@@ -1,18 +1,34 @@
 use sdl2::{
+    gfx::primitives::DrawRenderer,
     pixels::Color,
     rect::{Point, Rect},
     render::{Texture, WindowCanvas},
 };
 use specs::prelude::*;
+use std::time::Duration;
 
+use crate::console::Console;
+use crate::debugdraw::*;
+use crate::editor::*;
 use crate::entity_components::*;
+use crate::globalcomponents::*;
+use crate::inspector::InspectorRow;
+use crate::level::*;
+use crate::navigation::NavGrid;
+use crate::particles::*;
+use crate::profiler::*;
 
-pub type SystemData<'a> = (ReadStorage<'a, Position>, ReadStorage<'a, SpriteVec>);
+pub type SystemData<'a> = (
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, SpriteVec>,
+    Read<'a, Camera>,
+    Read<'a, ParticlePool>,
+);
 
 pub fn render(
     canvas: &mut WindowCanvas,
     background: Color,
-    textures: &[Texture],
+    textures: &mut [Texture],
     data: SystemData,
 ) -> Result<(), String> {
     canvas.set_draw_color(background);
@@ -24,7 +40,8 @@ pub fn render(
         for sprite in sprites.sprite_vec.iter() {
             let current_frame = sprite.region;
 
-            let screen_position = pos.point + Point::new(width as i32 / 2, height as i32 / 2);
+            let screen_position =
+                pos.point - data.2.point + Point::new(width as i32 / 2, height as i32 / 2);
             let screen_rect = Rect::from_center(
                 screen_position,
                 current_frame.width(),
@@ -58,7 +75,518 @@ pub fn render(
         }
     }
 
-    canvas.present();
+    let offset = Point::new(width as i32 / 2, height as i32 / 2) - data.2.point;
+    for particle in data.3.particles.iter() {
+        let color = particle.color();
+        let x = particle.x as i32 + offset.x();
+        let y = particle.y as i32 + offset.y();
+
+        match particle.shape {
+            ParticleShape::Circle(radius) => {
+                let radius = (radius * particle.scale()).round().max(1.0) as i16;
+                canvas.filled_circle(x as i16, y as i16, radius, color)?;
+            }
+            ParticleShape::Textured {
+                spritesheet,
+                region,
+            } => {
+                let texture = &mut textures[spritesheet];
+                texture.set_color_mod(color.r, color.g, color.b);
+                texture.set_alpha_mod(color.a);
+                let scale = particle.scale();
+                let dst = Rect::from_center(
+                    Point::new(x, y),
+                    (region.width() as f32 * scale).max(1.0) as u32,
+                    (region.height() as f32 * scale).max(1.0) as u32,
+                );
+                canvas.copy(texture, region, dst)?;
+                texture.set_color_mod(255, 255, 255);
+                texture.set_alpha_mod(255);
+            }
+        }
+    }
 
     return Ok(());
 }
+
+pub type DebugData<'a> = (
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, Collideable>,
+    ReadStorage<'a, GravityAfflicted>,
+    ReadStorage<'a, Health>,
+    ReadStorage<'a, Damage>,
+    ReadStorage<'a, Velocity>,
+    Read<'a, NavGrid>,
+    Read<'a, DebugDraw>,
+    Read<'a, Camera>,
+);
+
+// Pixels a velocity vector is drawn long per unit of speed
+const VELOCITY_SCALE: i32 = 4;
+
+// Debug overlay: the navigation grid's solid cells, collision boxes (green standing, yellow
+// in the air, blue for terrain), hurt boxes in red, damage boxes in magenta, velocities, and
+// whatever systems pushed into DebugDraw
+pub fn render_debug(canvas: &mut WindowCanvas, data: DebugData) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let offset = Point::new(width as i32 / 2, height as i32 / 2) - data.8.point;
+    let screen = Rect::new(0, 0, width, height);
+    let outline = |canvas: &mut WindowCanvas, rect: Rect, color: Color| {
+        let rect = Rect::new(
+            rect.x() + offset.x(),
+            rect.y() + offset.y(),
+            rect.width(),
+            rect.height(),
+        );
+        if !rect.has_intersection(screen) {
+            return Ok(());
+        }
+        canvas.rectangle(
+            rect.left() as i16,
+            rect.top() as i16,
+            rect.right() as i16 - 1,
+            rect.bottom() as i16 - 1,
+            color,
+        )
+    };
+
+    let nav = &*data.6;
+    for y in 0..nav.height {
+        for x in 0..nav.width {
+            if !nav.is_walkable(x, y) {
+                let cell = Rect::new(
+                    nav.origin.x() + x * nav.cell_size,
+                    nav.origin.y() + y * nav.cell_size,
+                    nav.cell_size as u32,
+                    nav.cell_size as u32,
+                );
+                outline(canvas, cell, Color::RGBA(0, 0, 0, 40))?;
+            }
+        }
+    }
+
+    for (pos, coll, grav) in (&data.0, &data.1, (&data.2).maybe()).join() {
+        let color = match grav {
+            Some(grav) if grav.grounded => Color::RGB(0, 200, 0),
+            Some(_) => Color::RGB(230, 200, 0),
+            None => Color::RGB(60, 120, 255),
+        };
+        let rect = Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height());
+        outline(canvas, rect, color)?;
+    }
+    for (pos, health, vel) in (&data.0, &data.3, (&data.5).maybe()).join() {
+        let mirrored = vel.is_some_and(|v| v.facing_left());
+        for hurt_box in health.hurt_boxes.iter() {
+            outline(
+                canvas,
+                pos.place_box(*hurt_box, mirrored),
+                Color::RGB(255, 0, 0),
+            )?;
+        }
+    }
+    for (pos, damage, vel) in (&data.0, &data.4, (&data.5).maybe()).join() {
+        let mirrored = vel.is_some_and(|v| v.facing_left());
+        for dmg_box in damage.dmg_boxes.iter() {
+            outline(
+                canvas,
+                pos.place_box(*dmg_box, mirrored),
+                Color::RGB(255, 0, 255),
+            )?;
+        }
+    }
+    for (pos, vel) in (&data.0, &data.5).join() {
+        let (x, y) = vel.unencode_speed();
+        if x == 0 && y == 0 {
+            continue;
+        }
+        let from = pos.point + offset;
+        let to = from.offset(x as i32 * VELOCITY_SCALE, y as i32 * VELOCITY_SCALE);
+        canvas.line(
+            from.x() as i16,
+            from.y() as i16,
+            to.x() as i16,
+            to.y() as i16,
+            Color::RGB(0, 160, 160),
+        )?;
+    }
+
+    for shape in data.7.shapes.iter() {
+        match shape {
+            DebugShape::Line { from, to, color } => {
+                let (from, to) = (*from + offset, *to + offset);
+                canvas.line(
+                    from.x() as i16,
+                    from.y() as i16,
+                    to.x() as i16,
+                    to.y() as i16,
+                    *color,
+                )?;
+            }
+            DebugShape::Rect { rect, color } => outline(canvas, *rect, *color)?,
+            DebugShape::Text { point, text, color } => {
+                let point = *point + offset;
+                canvas.string(point.x() as i16, point.y() as i16, text, *color)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Height of one line of the built in 8x8 font, with a little spacing
+pub const LINE_HEIGHT: u32 = 10;
+
+// Console over the top half of the screen, newest output right above the input line
+pub fn render_console(canvas: &mut WindowCanvas, console: &Console) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let rows = (height / 2 / LINE_HEIGHT) as usize;
+    canvas.box_(
+        0,
+        0,
+        width as i16 - 1,
+        (rows as u32 * LINE_HEIGHT + 4) as i16,
+        Color::RGBA(0, 0, 0, 200),
+    )?;
+
+    let start = console.log.len().saturating_sub(rows - 1);
+    for (row, line) in console.log[start..].iter().enumerate() {
+        let color = if line.starts_with("error:") {
+            Color::RGB(255, 120, 120)
+        } else {
+            Color::RGB(210, 210, 210)
+        };
+        canvas.string(4, (row as u32 * LINE_HEIGHT + 2) as i16, line, color)?;
+    }
+    canvas.string(
+        4,
+        ((rows - 1) as u32 * LINE_HEIGHT + 2) as i16,
+        &format!("> {}_", console.input),
+        Color::RGB(255, 255, 255),
+    )?;
+    Ok(())
+}
+
+pub const INSPECTOR_WIDTH: u32 = 300;
+
+// Inspector panel down the right edge, one row per LINE_HEIGHT so clicks map back to rows.
+// The selected entity is outlined in the scene
+pub fn render_inspector(
+    canvas: &mut WindowCanvas,
+    rows: &[InspectorRow],
+    selected: Option<Rect>,
+    camera: Point,
+) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    if let Some(rect) = selected {
+        let offset = Point::new(width as i32 / 2, height as i32 / 2) - camera;
+        canvas.rectangle(
+            (rect.left() + offset.x() - 2) as i16,
+            (rect.top() + offset.y() - 2) as i16,
+            (rect.right() + offset.x() + 1) as i16,
+            (rect.bottom() + offset.y() + 1) as i16,
+            Color::RGB(255, 140, 0),
+        )?;
+    }
+
+    let left = (width - INSPECTOR_WIDTH) as i16;
+    canvas.box_(
+        left,
+        0,
+        width as i16 - 1,
+        height as i16 - 1,
+        Color::RGBA(0, 0, 0, 200),
+    )?;
+    for (row, line) in rows.iter().enumerate() {
+        let y = (row as u32 * LINE_HEIGHT + 2) as i16;
+        let (text, color) = match line {
+            InspectorRow::Text(text) => (text.clone(), Color::RGB(160, 160, 160)),
+            InspectorRow::Entity {
+                entity,
+                selected,
+                summary,
+            } => (
+                format!(
+                    "{}{:>4} {}",
+                    if *selected { ">" } else { " " },
+                    entity.id(),
+                    summary
+                ),
+                if *selected {
+                    Color::RGB(255, 140, 0)
+                } else {
+                    Color::RGB(210, 210, 210)
+                },
+            ),
+            InspectorRow::Component(name) => (name.to_string(), Color::RGB(120, 200, 255)),
+            InspectorRow::Field {
+                field,
+                value,
+                editing,
+                ..
+            } => (
+                format!("  {} = {}", field, value),
+                if *editing {
+                    Color::RGB(255, 255, 255)
+                } else {
+                    Color::RGB(210, 210, 210)
+                },
+            ),
+        };
+        canvas.string(left + 4, y, &text, color)?;
+    }
+    Ok(())
+}
+
+// Level editor view of a level that is not running: the snapping grid, blocks filled in grey
+// (textured ones darker), platforms with a line to where they travel, prefab handles with their
+// names, the player spawn, and the block being dragged out
+pub fn render_editor(canvas: &mut WindowCanvas, editor: &Editor) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let offset = Point::new(width as i32 / 2, height as i32 / 2) - editor.camera;
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.clear();
+
+    if editor.snap {
+        let grid_color = Color::RGB(235, 235, 235);
+        for x in (0..width as i32).filter(|x| (x - offset.x()).rem_euclid(GRID) == 0) {
+            canvas.vline(x as i16, 0, height as i16 - 1, grid_color)?;
+        }
+        for y in (0..height as i32).filter(|y| (y - offset.y()).rem_euclid(GRID) == 0) {
+            canvas.hline(0, width as i16 - 1, y as i16, grid_color)?;
+        }
+    }
+
+    let screen = Rect::new(0, 0, width, height);
+    let draw_box = |canvas: &mut WindowCanvas, rect: Rect, fill: Option<Color>, color: Color| {
+        let rect = Rect::new(
+            rect.x() + offset.x(),
+            rect.y() + offset.y(),
+            rect.width(),
+            rect.height(),
+        );
+        // Off screen parts would overflow the i16 coordinates
+        let rect = match rect.intersection(screen) {
+            Some(rect) => rect,
+            None => return Ok(()),
+        };
+        let (left, top) = (rect.left() as i16, rect.top() as i16);
+        let (right, bottom) = (rect.right() as i16 - 1, rect.bottom() as i16 - 1);
+        if let Some(fill) = fill {
+            canvas.box_(left, top, right, bottom, fill)?;
+        }
+        canvas.rectangle(left, top, right, bottom, color)
+    };
+
+    for (i, item) in editor.level.items.iter().enumerate() {
+        let outline = if editor.selected == Some(i) {
+            Color::RGB(255, 140, 0)
+        } else {
+            Color::RGB(90, 90, 90)
+        };
+        match item {
+            LevelItem::Block { rect, textured, .. } => {
+                let fill = if *textured {
+                    Color::RGB(150, 130, 110)
+                } else {
+                    Color::RGB(200, 200, 200)
+                };
+                draw_box(canvas, *rect, Some(fill), outline)?;
+            }
+            LevelItem::Platform { rect, to, .. } => {
+                let end = Rect::from_center(*to, rect.width(), rect.height());
+                draw_box(canvas, end, None, Color::RGB(180, 180, 180))?;
+                let (from, to) = (rect.center() + offset, *to + offset);
+                canvas.line(
+                    from.x() as i16,
+                    from.y() as i16,
+                    to.x() as i16,
+                    to.y() as i16,
+                    Color::RGB(120, 120, 120),
+                )?;
+                draw_box(canvas, *rect, Some(Color::RGB(150, 130, 110)), outline)?;
+            }
+            LevelItem::Prefab { name, .. } => {
+                let bounds = item.bounds();
+                draw_box(canvas, bounds, Some(Color::RGBA(60, 120, 255, 80)), outline)?;
+                let label = bounds.bottom_left() + offset;
+                canvas.string(
+                    label.x() as i16,
+                    label.y() as i16 + 2,
+                    name,
+                    Color::RGB(0, 0, 0),
+                )?;
+            }
+        }
+    }
+
+    let spawn = Rect::from_center(editor.level.spawn, 26, 36);
+    draw_box(
+        canvas,
+        spawn,
+        Some(Color::RGBA(0, 200, 0, 80)),
+        Color::RGB(0, 150, 0),
+    )?;
+    if let Some(rect) = editor.preview() {
+        draw_box(canvas, rect, None, Color::RGB(255, 140, 0))?;
+    }
+
+    let status = [
+        format!(
+            "editor  {}  brush {} (Tab)  snap {} (G)",
+            editor.path,
+            editor.brush_name(),
+            if editor.snap { "on" } else { "off" }
+        ),
+        "left place/move, corner resize, right delete, T texture, P spawn, arrows pan".to_string(),
+        "Ctrl+Z undo  Ctrl+Y redo  Ctrl+S save  Ctrl+L load  F5 play test  F3 leave".to_string(),
+        editor.message.clone(),
+    ];
+    for (row, line) in status.iter().enumerate() {
+        let color = if line.starts_with("error:") {
+            Color::RGB(200, 0, 0)
+        } else {
+            Color::RGB(0, 0, 0)
+        };
+        canvas.string(4, (row as u32 * LINE_HEIGHT + 2) as i16, line, color)?;
+    }
+    Ok(())
+}
+
+// Systems are told apart by these in the profile graph, in dispatch order
+const PROFILE_COLORS: [Color; 8] = [
+    Color::RGB(230, 80, 80),
+    Color::RGB(80, 170, 80),
+    Color::RGB(80, 120, 230),
+    Color::RGB(230, 170, 40),
+    Color::RGB(170, 80, 200),
+    Color::RGB(40, 190, 190),
+    Color::RGB(240, 120, 200),
+    Color::RGB(150, 150, 150),
+];
+// The graph is two tick budgets high, the line through the middle is the budget
+const GRAPH_HEIGHT: u32 = 80;
+// Slowest systems listed under the graph
+const PROFILE_SYSTEMS: usize = 10;
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Timing overlay in the bottom left: a bar per tick split up by system, averages over the kept
+// history for ticks, rendering, entities and allocations, and the slowest systems
+pub fn render_profile(
+    canvas: &mut WindowCanvas,
+    ticks: &[TickStats],
+    frames: &[Duration],
+    budget: Duration,
+) -> Result<(), String> {
+    let (_, height) = canvas.output_size()?;
+    let rows = 3 + PROFILE_SYSTEMS as u32;
+    let panel_height = GRAPH_HEIGHT + 8 + rows * LINE_HEIGHT;
+    let (left, top) = (4, height as i32 - panel_height as i32 - 4);
+    canvas.box_(
+        left as i16,
+        top as i16,
+        (left + HISTORY as i32 + 8) as i16,
+        (height - 4) as i16,
+        Color::RGBA(0, 0, 0, 200),
+    )?;
+
+    let bottom = top + 4 + GRAPH_HEIGHT as i32;
+    let scale = GRAPH_HEIGHT as f64 / 2.0 / budget.as_secs_f64();
+    for (i, tick) in ticks.iter().enumerate() {
+        let x = (left + 4 + i as i32) as i16;
+        let mut y = bottom as f64;
+        for (system, (_, duration)) in tick.systems.iter().enumerate() {
+            let next = (y - duration.as_secs_f64() * scale).max((top + 4) as f64);
+            if y.round() > next.round() {
+                canvas.vline(
+                    x,
+                    next.round() as i16,
+                    y.round() as i16 - 1,
+                    PROFILE_COLORS[system % PROFILE_COLORS.len()],
+                )?;
+            }
+            y = next;
+        }
+        if tick.duration > budget * 2 {
+            canvas.pixel(x, (top + 4) as i16, Color::RGB(255, 0, 0))?;
+        }
+    }
+    let budget_y = (bottom - GRAPH_HEIGHT as i32 / 2) as i16;
+    canvas.hline(
+        (left + 4) as i16,
+        (left + 4 + HISTORY as i32) as i16,
+        budget_y,
+        Color::RGB(255, 255, 255),
+    )?;
+
+    let count = ticks.len().max(1) as u32;
+    let tick_avg = ticks.iter().map(|t| t.duration).sum::<Duration>() / count;
+    let tick_max = ticks.iter().map(|t| t.duration).max().unwrap_or_default();
+    let render_avg = frames.iter().sum::<Duration>() / frames.len().max(1) as u32;
+    let latest = ticks.last().cloned().unwrap_or_default();
+    let allocations = ticks.iter().map(|t| t.allocations).sum::<u64>() / count as u64;
+    let bytes = ticks.iter().map(|t| t.bytes).sum::<u64>() / count as u64;
+
+    let mut systems: Vec<(usize, &'static str, Duration)> = latest
+        .systems
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| {
+            let total = ticks
+                .iter()
+                .flat_map(|t| t.systems.iter())
+                .filter(|(n, _)| n == name)
+                .map(|(_, d)| *d)
+                .sum::<Duration>();
+            (i, *name, total / count)
+        })
+        .collect();
+    systems.sort_by_key(|s| std::cmp::Reverse(s.2));
+
+    let text_top = bottom + 4;
+    let white = Color::RGB(230, 230, 230);
+    let lines = [
+        format!(
+            "tick {:.2} ms avg {:.2} max  budget {:.1}",
+            millis(tick_avg),
+            millis(tick_max),
+            millis(budget)
+        ),
+        format!(
+            "render {:.2} ms  {} entities",
+            millis(render_avg),
+            latest.entities
+        ),
+        format!(
+            "{} allocations, {:.1} KB a tick",
+            allocations,
+            bytes as f64 / 1024.0
+        ),
+    ];
+    for (row, line) in lines.iter().enumerate() {
+        canvas.string(
+            (left + 4) as i16,
+            (text_top + row as i32 * LINE_HEIGHT as i32) as i16,
+            line,
+            white,
+        )?;
+    }
+    for (row, (index, name, average)) in systems.iter().take(PROFILE_SYSTEMS).enumerate() {
+        let y = text_top + (lines.len() + row) as i32 * LINE_HEIGHT as i32;
+        canvas.box_(
+            (left + 4) as i16,
+            y as i16,
+            (left + 10) as i16,
+            (y + 6) as i16,
+            PROFILE_COLORS[index % PROFILE_COLORS.len()],
+        )?;
+        canvas.string(
+            (left + 14) as i16,
+            y as i16,
+            &format!("{:<14} {:.3} ms", name, millis(*average)),
+            white,
+        )?;
+    }
+    Ok(())
+}
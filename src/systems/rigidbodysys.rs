@@ -0,0 +1,66 @@
+use sdl2::rect::Rect;
+use specs::prelude::*;
+
+use crate::entity_components::*;
+use crate::globalcomponents::*;
+
+pub struct RigidBodySys;
+
+// Keeps fast bodies from tunneling through thin terrain
+const MAX_RIGID_SPEED: f32 = 24.0;
+
+impl<'a> System<'a> for RigidBodySys {
+    type SystemData = (
+        WriteStorage<'a, RigidBody>,
+        WriteStorage<'a, Position>,
+        ReadStorage<'a, Mass>,
+        ReadStorage<'a, GravityScale>,
+        ReadStorage<'a, GravityZone>,
+        Read<'a, PhysicsConfig>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let config = *data.5;
+        let zones: Vec<(Rect, f32)> = (&data.1, &data.4)
+            .join()
+            .map(|(pos, zone)| (pos.place_box(zone.area, false), zone.scale))
+            .collect();
+
+        (
+            &mut data.0,
+            &mut data.1,
+            (&data.2).maybe(),
+            (&data.3).maybe(),
+        )
+            .par_join()
+            .for_each(|(body, pos, mass, scale)| {
+                let inv_mass = Mass::inverse(mass);
+                let force = std::mem::take(&mut body.force);
+                let impulse = std::mem::take(&mut body.impulse);
+                if inv_mass == 0.0 {
+                    body.velocity = (0.0, 0.0);
+                    return;
+                }
+
+                let mut scale = scale.map_or(1.0, |s| s.scale);
+                for (area, zone_scale) in zones.iter() {
+                    if area.contains_point(pos.point) {
+                        scale *= zone_scale;
+                    }
+                }
+
+                let (mut vx, mut vy) = body.velocity;
+                vx += (force.0 + impulse.0) * inv_mass + config.gravity.0 * scale;
+                vy += (force.1 + impulse.1) * inv_mass + config.gravity.1 * scale;
+                vx *= 1.0 - body.damping;
+                vy *= 1.0 - body.damping;
+                body.velocity = (
+                    vx.clamp(-MAX_RIGID_SPEED, MAX_RIGID_SPEED),
+                    vy.clamp(-MAX_RIGID_SPEED, MAX_RIGID_SPEED),
+                );
+
+                let (dx, dy) = body.velocity;
+                body.shift(pos, dx, dy);
+            });
+    }
+}
@@ -0,0 +1,245 @@
+use rhai::{Array, Dynamic, Map};
+use sdl2::rect::{Point, Rect};
+use specs::prelude::*;
+use specs::shrev::{EventChannel, ReaderId};
+
+use crate::commands::ActionCommand;
+use crate::entity_components::*;
+use crate::entity_script::Script;
+use crate::events::*;
+use crate::globalcomponents::HotReload;
+use crate::prefabs::PrefabLibrary;
+use crate::rng::Rng;
+use crate::scripting::ScriptHost;
+
+// Ticks between checks for edited script files
+const RELOAD_INTERVAL: u64 = 30;
+
+// Runs entity scripts after the world has moved and taken damage, before the dead are removed.
+// Each hook sees the entity as `this`, a map of its components that is written back after:
+//   id, x, y, vx, vy, max_speed, hp, max_hp, grounded (read only), state
+// Hooks: init(), update(), on_collision(other), on_damage(amount, attacker), on_death(killer)
+#[derive(Default)]
+pub struct ScriptSys {
+    host: ScriptHost,
+    damage_reader: Option<ReaderId<DamageDealt>>,
+    died_reader: Option<ReaderId<Died>>,
+    ticks: u64,
+}
+
+impl<'a> System<'a> for ScriptSys {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Script>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Health>,
+        ReadStorage<'a, GravityAfflicted>,
+        ReadStorage<'a, Collideable>,
+        WriteStorage<'a, ActionQueue>,
+        Read<'a, EventChannel<DamageDealt>>,
+        Read<'a, EventChannel<Died>>,
+        Write<'a, Rng>,
+        Read<'a, LazyUpdate>,
+        Read<'a, PrefabLibrary>,
+        Read<'a, HotReload>,
+        Write<'a, ScriptLog>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.damage_reader = Some(
+            world
+                .fetch_mut::<EventChannel<DamageDealt>>()
+                .register_reader(),
+        );
+        self.died_reader = Some(world.fetch_mut::<EventChannel<Died>>().register_reader());
+    }
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut scripts,
+            mut positions,
+            mut velocities,
+            mut healths,
+            gravity,
+            colliders,
+            mut queues,
+            damage_events,
+            died_events,
+            mut rng,
+            lazy,
+            prefabs,
+            hot_reload,
+            mut log,
+        ): Self::SystemData,
+    ) {
+        self.ticks += 1;
+        if hot_reload.0 && self.ticks.is_multiple_of(RELOAD_INTERVAL) {
+            self.host.reload_changed();
+        }
+
+        let damage: Vec<DamageDealt> = match &mut self.damage_reader {
+            Some(reader) => damage_events.read(reader).copied().collect(),
+            None => Vec::new(),
+        };
+        let died: Vec<Died> = match &mut self.died_reader {
+            Some(reader) => died_events.read(reader).copied().collect(),
+            None => Vec::new(),
+        };
+        let boxes: Vec<(Entity, Rect)> = (&entities, &positions, &colliders)
+            .join()
+            .map(|(e, pos, coll)| (e, bounds(pos, coll)))
+            .collect();
+
+        self.host.lend_rng(&rng);
+        for (entity, script) in (&entities, &mut scripts).join() {
+            self.host.load(&script.path);
+
+            // Touching counts too, CollisionSys stops things just short of overlapping
+            let touching: Vec<Entity> = match (positions.get(entity), colliders.get(entity)) {
+                (Some(pos), Some(coll)) => {
+                    let own = bounds(pos, coll);
+                    let reach =
+                        Rect::new(own.x() - 1, own.y() - 1, own.width() + 2, own.height() + 2);
+                    boxes
+                        .iter()
+                        .filter(|(other, rect)| *other != entity && reach.has_intersection(*rect))
+                        .map(|(other, _)| *other)
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+
+            let mut map = Map::new();
+            map.insert("id".into(), (entity.id() as i64).into());
+            if let Some(pos) = positions.get(entity) {
+                map.insert("x".into(), (pos.point.x() as i64).into());
+                map.insert("y".into(), (pos.point.y() as i64).into());
+            }
+            if let Some(vel) = velocities.get(entity) {
+                let (vx, vy) = vel.unencode_speed();
+                map.insert("vx".into(), (vx as i64).into());
+                map.insert("vy".into(), (vy as i64).into());
+                map.insert("max_speed".into(), (vel.max_speed as i64).into());
+            }
+            if let Some(health) = healths.get(entity) {
+                map.insert("hp".into(), (health.hp as i64).into());
+                map.insert("max_hp".into(), (health.max_hp as i64).into());
+            }
+            if let Some(grav) = gravity.get(entity) {
+                map.insert("grounded".into(), grav.grounded.into());
+            }
+            map.insert("state".into(), script.state.clone().into());
+
+            let mut this = Dynamic::from(map);
+            let path = script.path.as_str();
+            if !script.started {
+                self.host.call(path, "init", &mut this, ());
+            }
+            self.host.call(path, "update", &mut this, ());
+            for other in touching.iter().filter(|e| !script.touching.contains(e)) {
+                let mut other_map = Map::new();
+                other_map.insert("id".into(), (other.id() as i64).into());
+                if let Some(pos) = positions.get(*other) {
+                    other_map.insert("x".into(), (pos.point.x() as i64).into());
+                    other_map.insert("y".into(), (pos.point.y() as i64).into());
+                }
+                self.host
+                    .call(path, "on_collision", &mut this, (other_map,));
+            }
+            for event in damage.iter().filter(|d| d.target == entity) {
+                let args = (event.amount as i64, entity_id(event.attacker));
+                self.host.call(path, "on_damage", &mut this, args);
+            }
+            for event in died.iter().filter(|d| d.entity == entity) {
+                self.host
+                    .call(path, "on_death", &mut this, (entity_id(event.killer),));
+            }
+            script.started = true;
+            script.touching = touching;
+
+            let map = match this.try_cast::<Map>() {
+                Some(map) => map,
+                None => continue,
+            };
+            if let (Some(pos), Some(x), Some(y)) =
+                (positions.get_mut(entity), int(&map, "x"), int(&map, "y"))
+            {
+                pos.point = Point::new(x as i32, y as i32);
+            }
+            if let Some(vel) = velocities.get_mut(entity) {
+                apply_velocity(vel, &map);
+            }
+            if let Some(health) = healths.get_mut(entity) {
+                if let Some(max_hp) = int(&map, "max_hp") {
+                    health.max_hp = max_hp.max(1) as u32;
+                }
+                if let Some(hp) = int(&map, "hp") {
+                    health.hp = hp.clamp(0, health.max_hp as i64) as u32;
+                }
+            }
+            if let (Some(queue), Some(actions)) = (
+                queues.get_mut(entity),
+                map.get("actions")
+                    .and_then(|a| a.clone().try_cast::<Array>()),
+            ) {
+                for action in actions {
+                    if let Some(action) = action.try_cast::<ActionCommand>() {
+                        queue.push(action);
+                    }
+                }
+            }
+            if let Some(state) = map.get("state").and_then(|s| s.clone().try_cast::<Map>()) {
+                script.state = state;
+            }
+        }
+        self.host.return_rng(&mut rng);
+
+        for (name, point) in self.host.take_spawns() {
+            match prefabs.get(&name) {
+                Ok(prefab) => {
+                    prefab.build(lazy.create_entity(&entities), point);
+                }
+                Err(e) => log.push(format!("spawn_prefab: {}", e)),
+            }
+        }
+        for line in self.host.take_log() {
+            log.push(line);
+        }
+    }
+}
+
+fn bounds(pos: &Position, coll: &Collideable) -> Rect {
+    Rect::from_center(pos.point, coll.col_box.width(), coll.col_box.height())
+}
+
+// Scripts only see entity ids, () when there is nobody
+fn entity_id(entity: Option<Entity>) -> Dynamic {
+    entity.map_or(Dynamic::UNIT, |e| (e.id() as i64).into())
+}
+
+// Values of the wrong type are ignored
+fn int(map: &Map, key: &str) -> Option<i64> {
+    map.get(key).and_then(|v| v.as_int().ok())
+}
+
+fn apply_velocity(vel: &mut Velocity, map: &Map) {
+    let clamp = |v: i64| v.clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+    let (vx, vy) = vel.unencode_speed();
+    vel.encode_speed(
+        int(map, "vx").map_or(vx, clamp),
+        int(map, "vy").map_or(vy, clamp),
+    );
+    if let Some(max_speed) = int(map, "max_speed") {
+        vel.max_speed = clamp(max_speed);
+    }
+    if let Some(dir) = map
+        .get("steer")
+        .and_then(|d| d.clone().try_cast::<Direction>())
+    {
+        vel.steer(dir);
+    }
+}
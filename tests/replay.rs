@@ -0,0 +1,52 @@
+// Replays a hand written input script headless and checks the world against the checksums
+// it was recorded with. A change that alters the simulation on purpose has to record them
+// again, with `genjin-headless --replay <script> --record <file>`
+use genjin::library::events::SoundQueue;
+use genjin::library::replay::InputRecording;
+use genjin::simulation::Simulation;
+use specs::prelude::*;
+
+const SCRIPT: &str = "
+seed 42
+move 1,0 * 90 = 96d34cec516f633e
+move 1,0 jump = d1b3c20e66aed79b
+move 1,0 * 30 = 6d128e8745eeaab7
+shoot 1,0 = deee06d50658f0a8
+- * 20 = f845b62196ee190d
+move -1,0 shoot -1,0 * 60 = d7eb8e938a40cb3b
+jump = 329f58022202a433
+- * 60 = 4479dc70e0003b9f
+";
+
+#[test]
+fn script_replays_to_recorded_checksums() {
+    let recording = InputRecording::parse(SCRIPT).unwrap();
+    assert_eq!(recording.ticks.len(), 263);
+
+    let mut sim = Simulation::new(recording.seed, true).unwrap();
+    for recorded in recording.ticks.iter() {
+        sim.step(&recorded.input);
+        *sim.world.write_resource::<SoundQueue>() = SoundQueue::default();
+        if let Some(expected) = recorded.checksum {
+            assert_eq!(
+                sim.checksum(),
+                expected,
+                "checksum differs at tick {}",
+                sim.tick
+            );
+        }
+    }
+}
+
+#[test]
+fn replay_is_repeatable() {
+    let recording = InputRecording::parse(SCRIPT).unwrap();
+    let run = || {
+        let mut sim = Simulation::new(recording.seed, true).unwrap();
+        for recorded in recording.ticks.iter() {
+            sim.step(&recorded.input);
+        }
+        sim.checksum()
+    };
+    assert_eq!(run(), run());
+}
@@ -0,0 +1,67 @@
+// Two rollback peers over an in process connection that holds packets back, so every change
+// of input arrives late and the other side has to roll back. Both have to end up agreeing on
+// every frame they compared
+use genjin::headless::random_input;
+use genjin::library::commands::TickInput;
+use genjin::library::events::SoundQueue;
+use genjin::library::level::{Level, LevelId, DEFAULT_LEVEL};
+use genjin::library::netcode::LoopbackTransport;
+use genjin::library::rng::Rng;
+use genjin::library::rollback::{RollbackConfig, RollbackSession};
+use genjin::simulation::Simulation;
+use specs::prelude::*;
+
+const TICKS: u64 = 600;
+
+fn run(seed: u64, delay: u32) -> [RollbackSession<LoopbackTransport>; 2] {
+    let (first, second) = LoopbackTransport::pair(delay);
+    let level = LevelId::new(DEFAULT_LEVEL, &Level::load(DEFAULT_LEVEL).unwrap());
+    let config = RollbackConfig::default();
+    let mut sims = [
+        Simulation::new_multiplayer(seed, 2).unwrap(),
+        Simulation::new_multiplayer(seed, 2).unwrap(),
+    ];
+    let mut sessions = [
+        RollbackSession::new(0, config, first, level.clone()),
+        RollbackSession::new(1, config, second, level),
+    ];
+    let mut rngs = [Rng::new(seed ^ 1), Rng::new(seed ^ 2)];
+    let mut inputs = [TickInput::default(), TickInput::default()];
+
+    while sims.iter().any(|sim| sim.tick < TICKS) {
+        for slot in 0..2 {
+            if sessions[slot]
+                .advance(&mut sims[slot], &inputs[slot])
+                .unwrap()
+            {
+                inputs[slot] = random_input(&mut rngs[slot], &inputs[slot]);
+            }
+            *sims[slot].world.write_resource::<SoundQueue>() = SoundQueue::default();
+        }
+    }
+    sessions
+}
+
+#[test]
+fn peers_agree_after_rollbacks() {
+    for seed in 1..=3 {
+        for session in run(seed, 5).iter() {
+            assert!(session.rollbacks > 0, "seed {} never rolled back", seed);
+            assert!(session.checked > 0, "seed {} compared no checksums", seed);
+            assert_eq!(
+                session.desync, None,
+                "seed {} peer {}",
+                seed, session.local_slot
+            );
+            assert_eq!(session.dropped, 0);
+        }
+    }
+}
+
+#[test]
+fn peers_agree_without_latency() {
+    for session in run(4, 0).iter() {
+        assert!(session.checked > 0);
+        assert_eq!(session.desync, None);
+    }
+}